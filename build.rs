@@ -12,19 +12,77 @@ const INCLUDED_FUNCTIONS: &[&str] = &[
     "krealloc",
     "kfree",
     "mount_nodev",
-    "kill_litter_super",
+    "mount_bdev",
+    "get_tree_bdev",
+    "kill_anon_super",
+    "kill_block_super",
+    "d_genocide",
+    "sb_set_blocksize",
+    "__brelse",
+    "inc_nlink",
+    "d_make_root",
+    "d_alloc_name",
+    "dput",
+    "d_splice_alias",
+    "d_instantiate",
+    "set_nlink",
+    "clear_nlink",
+    "drop_nlink",
+    "truncate_setsize",
+    "ihold",
+    "iput",
+    "iget_locked",
+    "iget_failed",
+    "unlock_new_inode",
+    "simple_statfs",
+    "generic_delete_inode",
+    "truncate_inode_pages_final",
+    "clear_inode",
     "register_sysctl",
+    "register_sysctl_table",
+    "register_net_sysctl",
+    "register_pernet_subsys",
+    "unregister_pernet_subsys",
     "unregister_sysctl_table",
     "access_ok",
     "_copy_to_user",
     "_copy_from_user",
     "alloc_chrdev_region",
     "unregister_chrdev_region",
+    "register_chrdev_region",
+    "class_destroy",
+    "device_unregister",
+    "misc_register",
+    "misc_deregister",
+    "kobject_init",
+    "kobject_get",
+    "kobject_put",
+    "mutex_unlock",
     "wait_for_random_bytes",
     "get_random_bytes",
     "rng_is_initialized",
     "printk",
     "add_device_randomness",
+    "stream_open",
+    "nonseekable_open",
+    "fasync_helper",
+    "kill_fasync",
+    "seq_open",
+    "seq_release",
+    "seq_read",
+    "seq_lseek",
+    "seq_write",
+    "single_open",
+    "single_release",
+    "proc_create",
+    "proc_remove",
+    "debugfs_create_file",
+    "debugfs_remove",
+    "generic_file_splice_read",
+    "iter_file_splice_write",
+    "posix_lock_file",
+    "posix_test_lock",
+    "generic_setlease",
 ];
 const INCLUDED_VARS: &[&str] = &[
     "EINVAL",
@@ -32,6 +90,15 @@ const INCLUDED_VARS: &[&str] = &[
     "ESPIPE",
     "EFAULT",
     "EAGAIN",
+    "EBUSY",
+    "ENOENT",
+    "ENXIO",
+    "ENOTEMPTY",
+    "ENAMETOOLONG",
+    "EIO",
+    "EXDEV",
+    "SB_SILENT",
+    "EOPNOTSUPP",
     "__this_module",
     "FS_REQUIRES_DEV",
     "FS_BINARY_MOUNTDATA",
@@ -39,13 +106,72 @@ const INCLUDED_VARS: &[&str] = &[
     "FS_USERNS_MOUNT",
     "FS_RENAME_DOES_D_MOVE",
     "BINDINGS_GFP_KERNEL",
+    "BINDINGS_PAGE_SIZE",
+    "S_IFDIR",
+    "S_IFREG",
+    "S_IFMT",
+    "I_NEW",
+    "NAME_MAX",
+    "RENAME_NOREPLACE",
+    "ATTR_MODE",
+    "ATTR_UID",
+    "ATTR_GID",
+    "ATTR_SIZE",
+    "simple_dir_operations",
+    "simple_dir_inode_operations",
     "KERN_INFO",
     "VERIFY_WRITE",
     "LINUX_VERSION_CODE",
     "SEEK_SET",
     "SEEK_CUR",
     "SEEK_END",
+    "O_WRONLY",
+    "O_RDWR",
+    "O_CREAT",
+    "O_EXCL",
+    "O_NOCTTY",
+    "O_TRUNC",
+    "O_APPEND",
     "O_NONBLOCK",
+    "O_DSYNC",
+    "O_SYNC",
+    "O_DIRECT",
+    "O_LARGEFILE",
+    "O_DIRECTORY",
+    "O_NOFOLLOW",
+    "O_NOATIME",
+    "O_CLOEXEC",
+    "O_PATH",
+    "BINDINGS_FMODE_READ",
+    "BINDINGS_FMODE_WRITE",
+    "BINDINGS_FMODE_LSEEK",
+    "BINDINGS_FMODE_PREAD",
+    "BINDINGS_FMODE_PWRITE",
+    "BINDINGS_FMODE_EXEC",
+    "BINDINGS_FMODE_EXCL",
+    "BINDINGS_FMODE_ATOMIC_POS",
+    "BINDINGS_FMODE_UNSIGNED_OFFSET",
+    "BINDINGS_FMODE_NOWAIT",
+    "BINDINGS_FMODE_STREAM",
+    "SIGIO",
+    "POLL_IN",
+    "POLL_OUT",
+    "FALLOC_FL_KEEP_SIZE",
+    "FALLOC_FL_PUNCH_HOLE",
+    "FALLOC_FL_NO_HIDE_STALE",
+    "FALLOC_FL_COLLAPSE_RANGE",
+    "FALLOC_FL_ZERO_RANGE",
+    "FALLOC_FL_INSERT_RANGE",
+    "FALLOC_FL_UNSHARE_RANGE",
+    "F_GETLK",
+    "F_SETLK",
+    "F_SETLKW",
+    "F_RDLCK",
+    "F_WRLCK",
+    "F_UNLCK",
+    "FL_OFDLCK",
+    "MINORBITS",
+    "MISC_DYNAMIC_MINOR",
 ];
 const OPAQUE_TYPES: &[&str] = &[
     // These need to be opaque because they're both packed and aligned, which rustc
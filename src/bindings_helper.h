@@ -1,11 +1,43 @@
+#include <linux/buffer_head.h>
 #include <linux/cdev.h>
+#include <linux/cred.h>
+#include <linux/debugfs.h>
+#include <linux/device.h>
+#include <linux/falloc.h>
 #include <linux/fs.h>
+#include <linux/miscdevice.h>
 #include <linux/module.h>
+#include <linux/mutex.h>
+#include <linux/proc_fs.h>
+#include <linux/signal.h>
 #include <linux/random.h>
+#include <linux/seq_file.h>
 #include <linux/slab.h>
 #include <linux/uaccess.h>
 #include <linux/version.h>
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(5, 1, 0)
+#include <linux/fs_parser.h>
+#endif
+#include <net/net_namespace.h>
 
 // Bindgen gets confused at certain things
 //
 const gfp_t BINDINGS_GFP_KERNEL = GFP_KERNEL;
+const unsigned long BINDINGS_PAGE_SIZE = PAGE_SIZE;
+
+// fmode_t constants are __force casts, which bindgen can't evaluate.
+const fmode_t BINDINGS_FMODE_READ = FMODE_READ;
+const fmode_t BINDINGS_FMODE_WRITE = FMODE_WRITE;
+const fmode_t BINDINGS_FMODE_LSEEK = FMODE_LSEEK;
+const fmode_t BINDINGS_FMODE_PREAD = FMODE_PREAD;
+const fmode_t BINDINGS_FMODE_PWRITE = FMODE_PWRITE;
+const fmode_t BINDINGS_FMODE_EXEC = FMODE_EXEC;
+const fmode_t BINDINGS_FMODE_EXCL = FMODE_EXCL;
+const fmode_t BINDINGS_FMODE_ATOMIC_POS = FMODE_ATOMIC_POS;
+const fmode_t BINDINGS_FMODE_UNSIGNED_OFFSET = FMODE_UNSIGNED_OFFSET;
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(4, 13, 0)
+const fmode_t BINDINGS_FMODE_NOWAIT = FMODE_NOWAIT;
+#endif
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(5, 2, 0)
+const fmode_t BINDINGS_FMODE_STREAM = FMODE_STREAM;
+#endif
@@ -1,6 +1,8 @@
 use core::convert::TryInto;
+use core::marker;
 use core::mem;
 use core::ops::Range;
+use core::ptr;
 
 use alloc::boxed::Box;
 use alloc::vec;
@@ -8,91 +10,491 @@ use alloc::vec::Vec;
 
 use crate::bindings;
 use crate::c_types;
-use crate::error::{Error, KernelResult};
+use crate::error::{from_kernel_err_ptr, Error, KernelResult};
 use crate::file_operations;
-use crate::types::CStr;
+use crate::seq_file;
+use crate::types::{CStr, Kgid, Kuid, Mode};
+
+extern "C" {
+    fn class_create_helper(
+        owner: *mut bindings::module,
+        name: *const c_types::c_char,
+    ) -> *mut bindings::class;
+    fn device_create_with_type_helper(
+        class: *mut bindings::class,
+        type_: *const bindings::device_type,
+        devt: bindings::dev_t,
+        name: *const c_types::c_char,
+        index: c_types::c_uint,
+    ) -> *mut bindings::device;
+}
 
 pub fn builder(name: CStr<'static>, minors: Range<u16>) -> KernelResult<Builder> {
     Ok(Builder {
         name,
+        major: None,
         minors,
         file_ops: vec![],
+        nodes: None,
     })
 }
 
+/// Registers a character device with a single minor number and a dynamically
+/// allocated major number.
+pub fn register_single<T: file_operations::FileOperations + file_operations::FileOpen>(
+    name: CStr<'static>,
+) -> KernelResult<Registration> {
+    builder(name, 0..1)?.register_device::<T>().build()
+}
+
 pub struct Builder {
     name: CStr<'static>,
+    major: Option<u32>,
     minors: Range<u16>,
-    file_ops: Vec<&'static bindings::file_operations>,
+    file_ops: Vec<(&'static bindings::file_operations, Option<DeviceData>)>,
+    nodes: Option<NodeDefaults>,
+}
+
+#[derive(Clone, Copy)]
+struct NodeDefaults {
+    mode: bindings::umode_t,
+    uid: Kuid,
+    gid: Kgid,
 }
 
 impl Builder {
-    pub fn register_device<T: file_operations::FileOperations>(mut self) -> Builder {
-        if self.file_ops.len() >= self.minors.len() {
-            panic!("More devices registered than minor numbers allocated.")
-        }
+    /// Requests a specific major number with `register_chrdev_region`, for
+    /// compatibility with existing device nodes, rather than having one
+    /// allocated dynamically. `build()` fails with `EBUSY` if the range is
+    /// already taken, and with `EINVAL` if `major` is 0 or out of range.
+    pub fn major(mut self, major: u32) -> Builder {
+        self.major = Some(major);
+        self
+    }
+
+    /// Creates a device class with the same name as the device, and a
+    /// `struct device` for each registered device, so that devtmpfs and udev
+    /// create `/dev/<name><minor>` automatically. The nodes are removed when
+    /// the `Registration` is dropped.
+    pub fn device_class(mut self) -> Builder {
+        self.nodes_mut();
+        self
+    }
+
+    /// Sets the permissions of the device nodes, which otherwise default to
+    /// 0600. Implies `device_class`.
+    pub fn node_mode(mut self, mode: Mode) -> Builder {
+        self.nodes_mut().mode = mode.as_int();
+        self
+    }
+
+    /// Sets the owner of the device nodes, which otherwise default to root.
+    /// Implies `device_class`.
+    pub fn node_owner(mut self, uid: Kuid, gid: Kgid) -> Builder {
+        let nodes = self.nodes_mut();
+        nodes.uid = uid;
+        nodes.gid = gid;
+        self
+    }
+
+    fn nodes_mut(&mut self) -> &mut NodeDefaults {
+        self.nodes.get_or_insert(NodeDefaults {
+            mode: 0o600,
+            uid: Kuid::GLOBAL_ROOT,
+            gid: Kgid::GLOBAL_ROOT,
+        })
+    }
+
+    pub fn register_device<T: file_operations::FileOperations + file_operations::FileOpen>(
+        self,
+    ) -> Builder {
+        self.register_vtable(&file_operations::FileOperationsVtable::<T>::VTABLE)
+    }
+
+    /// Registers a read-only device whose contents are produced by a
+    /// `SeqFile`.
+    pub fn register_seq_device<T: seq_file::SeqFile + file_operations::FileOpen>(self) -> Builder {
+        self.register_vtable(&seq_file::SeqFileVtable::<T>::VTABLE)
+    }
+
+    /// Registers a read-only device whose contents are produced by a
+    /// `SingleSeqFile`.
+    pub fn register_single_seq_device<T: seq_file::SingleSeqFile + file_operations::FileOpen>(
+        self,
+    ) -> Builder {
+        self.register_vtable(&seq_file::SingleSeqFileVtable::<T>::VTABLE)
+    }
+
+    /// Registers a device whose files are created by `T::open_with` from
+    /// `data`, so `T` doesn't need to implement `FileOpen`. Each device can
+    /// have its own value.
+    pub fn register_device_with<T, D>(mut self, data: D) -> Builder
+    where
+        T: file_operations::FileOpener<D>,
+        D: Send + Sync + 'static,
+    {
+        let data: DeviceData = Box::new(data);
         self.file_ops
-            .push(&file_operations::FileOperationsVtable::<T>::VTABLE);
+            .push((&DataVtable::<T, D>::VTABLE, Some(data)));
+        self
+    }
+
+    fn register_vtable(mut self, file_ops: &'static bindings::file_operations) -> Builder {
+        self.file_ops.push((file_ops, None));
         self
     }
 
+    /// Allocates the device numbers and adds the registered devices. Fails
+    /// with `EINVAL` if more devices were registered than there are minor
+    /// numbers.
     pub fn build(self) -> KernelResult<Registration> {
-        let mut dev: bindings::dev_t = 0;
-        let res = unsafe {
-            bindings::alloc_chrdev_region(
-                &mut dev,
-                self.minors.start.into(),
-                self.minors.len().try_into()?,
-                self.name.as_ptr() as *const c_types::c_char,
-            )
-        };
-        if res != 0 {
-            return Err(Error::from_kernel_errno(res));
+        if self.file_ops.len() > self.minors.len() {
+            return Err(Error::EINVAL);
         }
 
-        // Turn this into a boxed slice immediately because the kernel stores pointers into it, and
-        // so that data should never be moved.
-        let mut cdevs = vec![unsafe { mem::zeroed() }; self.file_ops.len()].into_boxed_slice();
-        for (i, file_op) in self.file_ops.iter().enumerate() {
-            unsafe {
-                bindings::cdev_init(&mut cdevs[i], *file_op);
-                cdevs[i].owner = &mut bindings::__this_module;
-                let rc = bindings::cdev_add(&mut cdevs[i], dev + i as bindings::dev_t, 1);
-                if rc != 0 {
-                    // Clean up the ones that were allocated.
-                    for j in 0..=i {
-                        bindings::cdev_del(&mut cdevs[j]);
-                    }
-                    bindings::unregister_chrdev_region(dev, self.minors.len() as _);
-                    return Err(Error::from_kernel_errno(rc));
+        let mut dev: bindings::dev_t = 0;
+        let res = match self.major {
+            Some(major) => {
+                if major == 0 || major >= 1 << (32 - bindings::MINORBITS) {
+                    return Err(Error::EINVAL);
+                }
+                dev = (major << bindings::MINORBITS) | bindings::dev_t::from(self.minors.start);
+                unsafe {
+                    bindings::register_chrdev_region(
+                        dev,
+                        self.minors.len().try_into()?,
+                        self.name.as_ptr() as *const c_types::c_char,
+                    )
                 }
             }
+            None => unsafe {
+                bindings::alloc_chrdev_region(
+                    &mut dev,
+                    self.minors.start.into(),
+                    self.minors.len().try_into()?,
+                    self.name.as_ptr() as *const c_types::c_char,
+                )
+            },
+        };
+        if res != 0 {
+            return Err(Error::from_kernel_errno(res));
         }
 
-        Ok(Registration {
+        let mut registration = Registration {
+            name: self.name,
             dev,
             count: self.minors.len(),
-            cdevs,
+            first_minor: self.minors.start,
+            minors: (0..self.minors.len()).map(|_| None).collect(),
+            nodes: None,
+        };
+        // On failure, dropping `registration` cleans up everything created
+        // so far.
+        if let Some(defaults) = self.nodes {
+            registration.nodes = Some(DeviceNodes::new(&registration.name, defaults)?);
+        }
+        for (i, (file_ops, data)) in self.file_ops.into_iter().enumerate() {
+            registration.add_vtable(i, file_ops, data)?;
+        }
+        Ok(registration)
+    }
+}
+
+/// The device class backing the `/dev` nodes of a `Registration`.
+#[repr(C)]
+struct DeviceNodes {
+    // Must be the first field: `devnode_callback` finds the rest of the
+    // struct from the device's type pointer.
+    device_type: bindings::device_type,
+    defaults: NodeDefaults,
+    class: *mut bindings::class,
+}
+
+impl DeviceNodes {
+    fn new(name: &CStr<'static>, defaults: NodeDefaults) -> KernelResult<Box<DeviceNodes>> {
+        let class = from_kernel_err_ptr(unsafe {
+            class_create_helper(
+                &mut bindings::__this_module,
+                name.as_ptr() as *const c_types::c_char,
+            )
+        })?;
+        // The kernel stores a pointer to `device_type`, so it's boxed before
+        // any devices are created.
+        let mut nodes = Box::new(DeviceNodes {
+            device_type: unsafe { mem::zeroed() },
+            defaults,
+            class,
+        });
+        nodes.device_type.devnode = Some(devnode_callback);
+        Ok(nodes)
+    }
+
+    fn create_device(
+        &self,
+        name: &CStr<'static>,
+        devt: bindings::dev_t,
+        index: u32,
+    ) -> KernelResult<*mut bindings::device> {
+        from_kernel_err_ptr(unsafe {
+            device_create_with_type_helper(
+                self.class,
+                &self.device_type,
+                devt,
+                name.as_ptr() as *const c_types::c_char,
+                index,
+            )
         })
     }
 }
 
+impl Drop for DeviceNodes {
+    fn drop(&mut self) {
+        unsafe { bindings::class_destroy(self.class) };
+    }
+}
+
+unsafe extern "C" fn devnode_callback(
+    dev: *mut bindings::device,
+    mode: *mut bindings::umode_t,
+    uid: *mut bindings::kuid_t,
+    gid: *mut bindings::kgid_t,
+) -> *mut c_types::c_char {
+    let nodes = &*((*dev).type_ as *const DeviceNodes);
+    // The kernel passes NULL when it only wants the name, e.g. when removing
+    // the node.
+    if !mode.is_null() {
+        *mode = nodes.defaults.mode;
+    }
+    if !uid.is_null() {
+        *uid = nodes.defaults.uid.to_kernel();
+    }
+    if !gid.is_null() {
+        *gid = nodes.defaults.gid.to_kernel();
+    }
+    // Use the device name as the node name.
+    ptr::null_mut()
+}
+
+/// The kernel objects for one minor number. Open files can keep this alive
+/// after the minor is removed from its `Registration`, so it's reference
+/// counted by `anchor` and freed by `minor_release`.
+#[repr(C)]
+struct MinorCdev {
+    // Must be the first field: `minor_release` finds the rest of the struct
+    // from it.
+    anchor: bindings::kobject,
+    // Read by the kernel before it calls `minor_release`, and never
+    // afterwards, so this can be freed along with the rest.
+    ktype: bindings::kobj_type,
+    cdev: bindings::cdev,
+    data: Option<DeviceData>,
+}
+
+/// A value attached to a device with `register_device_with`. The concrete
+/// type is only known to that device's `DataVtable`.
+type DeviceData = Box<dyn Send + Sync>;
+
+struct DataVtable<T, D>(marker::PhantomData<(T, D)>);
+
+impl<T: file_operations::FileOpener<D>, D: 'static> DataVtable<T, D> {
+    const VTABLE: bindings::file_operations = bindings::file_operations {
+        open: Some(open_with_data_callback::<T, D>),
+        ..file_operations::FileOperationsVtable::<T>::OPERATIONS
+    };
+}
+
+unsafe extern "C" fn open_with_data_callback<T: file_operations::FileOpener<D>, D: 'static>(
+    inode: *mut bindings::inode,
+    file: *mut bindings::file,
+) -> c_types::c_int {
+    // The parent of every cdev we add is the anchor of its MinorCdev, which
+    // the open file keeps alive along with the cdev.
+    let minor = (*(*inode).i_cdev).kobj.parent as *const MinorCdev;
+    let data = match &(*minor).data {
+        Some(data) => &*(&**data as *const (dyn Send + Sync) as *const D),
+        None => return Error::ENXIO.to_kernel_errno(),
+    };
+    file_operations::open_file(inode, file, || T::open_with(data))
+}
+
+unsafe extern "C" fn minor_release(kobj: *mut bindings::kobject) {
+    drop(Box::from_raw(kobj as *mut MinorCdev));
+}
+
+impl MinorCdev {
+    /// Adds a cdev for `devt` and returns the new allocation, which holds
+    /// one reference to `anchor` on behalf of the caller.
+    unsafe fn add(
+        file_ops: &'static bindings::file_operations,
+        data: Option<DeviceData>,
+        devt: bindings::dev_t,
+    ) -> KernelResult<*mut MinorCdev> {
+        let minor = Box::into_raw(Box::new(MinorCdev {
+            anchor: mem::zeroed(),
+            ktype: Default::default(),
+            cdev: mem::zeroed(),
+            data,
+        }));
+        (*minor).ktype.release = Some(minor_release);
+        bindings::kobject_init(&mut (*minor).anchor, &mut (*minor).ktype);
+
+        bindings::cdev_init(&mut (*minor).cdev, file_ops);
+        (*minor).cdev.owner = &mut bindings::__this_module;
+        // The cdev's release drops a reference to its parent, which happens
+        // once every file opened through it is closed.
+        (*minor).cdev.kobj.parent = bindings::kobject_get(&mut (*minor).anchor);
+        let rc = bindings::cdev_add(&mut (*minor).cdev, devt, 1);
+        if rc != 0 {
+            bindings::kobject_put(&mut (*minor).cdev.kobj);
+            bindings::kobject_put(&mut (*minor).anchor);
+            return Err(Error::from_kernel_errno(rc));
+        }
+        // Since 4.11 cdev_add takes its own reference to the parent.
+        #[cfg(kernel_4_11_0_or_greater)]
+        bindings::kobject_put(&mut (*minor).anchor);
+        Ok(minor)
+    }
+
+    /// Removes the cdev and drops the caller's reference. The allocation is
+    /// freed once the last open file is closed.
+    unsafe fn remove(minor: *mut MinorCdev) {
+        bindings::cdev_del(&mut (*minor).cdev);
+        bindings::kobject_put(&mut (*minor).anchor);
+    }
+}
+
+struct Minor {
+    cdev: *mut MinorCdev,
+    device: Option<*mut bindings::device>,
+}
+
 pub struct Registration {
+    name: CStr<'static>,
     dev: bindings::dev_t,
     count: usize,
-    cdevs: Box<[bindings::cdev]>,
+    first_minor: u16,
+    minors: Vec<Option<Minor>>,
+    nodes: Option<Box<DeviceNodes>>,
+}
+
+impl Registration {
+    /// Adds a device at `minor`, which must be in the range passed to
+    /// `builder` and not currently in use. Fails with `EINVAL` if it is out
+    /// of range and `EBUSY` if it is taken.
+    pub fn add_device<T: file_operations::FileOperations + file_operations::FileOpen>(
+        &mut self,
+        minor: u16,
+    ) -> KernelResult<()> {
+        let index = self.index(minor)?;
+        self.add_vtable(
+            index,
+            &file_operations::FileOperationsVtable::<T>::VTABLE,
+            None,
+        )
+    }
+
+    /// Adds a device whose files are created by `T::open_with` from `data`,
+    /// like `add_device`.
+    pub fn add_device_with<T, D>(&mut self, minor: u16, data: D) -> KernelResult<()>
+    where
+        T: file_operations::FileOpener<D>,
+        D: Send + Sync + 'static,
+    {
+        let index = self.index(minor)?;
+        let data: DeviceData = Box::new(data);
+        self.add_vtable(index, &DataVtable::<T, D>::VTABLE, Some(data))
+    }
+
+    /// Adds a read-only device whose contents are produced by a `SeqFile`,
+    /// like `add_device`.
+    pub fn add_seq_device<T: seq_file::SeqFile + file_operations::FileOpen>(
+        &mut self,
+        minor: u16,
+    ) -> KernelResult<()> {
+        let index = self.index(minor)?;
+        self.add_vtable(index, &seq_file::SeqFileVtable::<T>::VTABLE, None)
+    }
+
+    /// Adds a read-only device whose contents are produced by a
+    /// `SingleSeqFile`, like `add_device`.
+    pub fn add_single_seq_device<T: seq_file::SingleSeqFile + file_operations::FileOpen>(
+        &mut self,
+        minor: u16,
+    ) -> KernelResult<()> {
+        let index = self.index(minor)?;
+        self.add_vtable(index, &seq_file::SingleSeqFileVtable::<T>::VTABLE, None)
+    }
+
+    /// Removes the device at `minor` and its `/dev` node, if any. Files that
+    /// are already open keep working until they are closed; new opens fail
+    /// with `ENXIO`. Fails with `EINVAL` if `minor` is out of range and
+    /// `ENOENT` if there is no device there.
+    pub fn remove_device(&mut self, minor: u16) -> KernelResult<()> {
+        let index = self.index(minor)?;
+        match self.minors[index].take() {
+            Some(m) => {
+                Registration::remove_minor(m);
+                Ok(())
+            }
+            None => Err(Error::ENOENT),
+        }
+    }
+
+    fn index(&self, minor: u16) -> KernelResult<usize> {
+        let index = usize::from(minor.checked_sub(self.first_minor).ok_or(Error::EINVAL)?);
+        if index >= self.minors.len() {
+            return Err(Error::EINVAL);
+        }
+        Ok(index)
+    }
+
+    fn add_vtable(
+        &mut self,
+        index: usize,
+        file_ops: &'static bindings::file_operations,
+        data: Option<DeviceData>,
+    ) -> KernelResult<()> {
+        if self.minors[index].is_some() {
+            return Err(Error::EBUSY);
+        }
+        let devt = self.dev + index as bindings::dev_t;
+        let cdev = unsafe { MinorCdev::add(file_ops, data, devt)? };
+        let mut minor = Minor { cdev, device: None };
+        if let Some(nodes) = &self.nodes {
+            let device =
+                nodes.create_device(&self.name, devt, u32::from(self.first_minor) + index as u32);
+            match device {
+                Ok(device) => minor.device = Some(device),
+                Err(e) => {
+                    Registration::remove_minor(minor);
+                    return Err(e);
+                }
+            }
+        }
+        self.minors[index] = Some(minor);
+        Ok(())
+    }
+
+    fn remove_minor(minor: Minor) {
+        unsafe {
+            // Remove the device node before the device it refers to.
+            if let Some(device) = minor.device {
+                bindings::device_unregister(device);
+            }
+            MinorCdev::remove(minor.cdev);
+        }
+    }
 }
 
-// This is safe because Registration doesn't actually expose any methods.
+// This is safe because Registration is only modified through `&mut self`.
 unsafe impl Sync for Registration {}
 
 impl Drop for Registration {
     fn drop(&mut self) {
-        unsafe {
-            for dev in self.cdevs.iter_mut() {
-                bindings::cdev_del(dev);
-            }
-            bindings::unregister_chrdev_region(self.dev, self.count as _);
+        for minor in self.minors.drain(..).flatten() {
+            Registration::remove_minor(minor);
         }
+        self.nodes.take();
+        unsafe { bindings::unregister_chrdev_region(self.dev, self.count as _) };
     }
 }
@@ -1,3 +1,4 @@
+use core::fmt;
 use core::num::TryFromIntError;
 
 use crate::bindings;
@@ -11,6 +12,14 @@ impl Error {
     pub const EFAULT: Self = Error(-(bindings::EFAULT as i32));
     pub const ESPIPE: Self = Error(-(bindings::ESPIPE as i32));
     pub const EAGAIN: Self = Error(-(bindings::EAGAIN as i32));
+    pub const EOPNOTSUPP: Self = Error(-(bindings::EOPNOTSUPP as i32));
+    pub const EBUSY: Self = Error(-(bindings::EBUSY as i32));
+    pub const ENOENT: Self = Error(-(bindings::ENOENT as i32));
+    pub const ENXIO: Self = Error(-(bindings::ENXIO as i32));
+    pub const ENOTEMPTY: Self = Error(-(bindings::ENOTEMPTY as i32));
+    pub const ENAMETOOLONG: Self = Error(-(bindings::ENAMETOOLONG as i32));
+    pub const EIO: Self = Error(-(bindings::EIO as i32));
+    pub const EXDEV: Self = Error(-(bindings::EXDEV as i32));
 
     pub fn from_kernel_errno(errno: c_types::c_int) -> Error {
         Error(errno)
@@ -27,4 +36,62 @@ impl From<TryFromIntError> for Error {
     }
 }
 
+impl From<fmt::Error> for Error {
+    fn from(_: fmt::Error) -> Error {
+        Error::EINVAL
+    }
+}
+
 pub type KernelResult<T> = Result<T, Error>;
+
+/// The integer types returned from `extern "C"` callbacks, which all signal
+/// failure with a negative errno.
+pub(crate) trait ErrnoReturn {
+    fn from_errno(errno: c_types::c_int) -> Self;
+}
+
+impl ErrnoReturn for c_types::c_int {
+    fn from_errno(errno: c_types::c_int) -> Self {
+        errno
+    }
+}
+
+// Also covers loff_t, which is the same type.
+impl ErrnoReturn for c_types::c_long {
+    fn from_errno(errno: c_types::c_int) -> Self {
+        errno.into()
+    }
+}
+
+impl ErrnoReturn for c_types::c_ssize_t {
+    fn from_errno(errno: c_types::c_int) -> Self {
+        errno as c_types::c_ssize_t
+    }
+}
+
+/// Runs the body of an `extern "C"` callback, turning an `Err` into the
+/// negative errno the kernel expects. Callback bodies should propagate every
+/// failure, including integer conversions, with `?` rather than panicking.
+pub(crate) fn from_kernel_result<T: ErrnoReturn>(f: impl FnOnce() -> KernelResult<T>) -> T {
+    match f() {
+        Ok(v) => v,
+        Err(e) => T::from_errno(e.to_kernel_errno()),
+    }
+}
+
+/// Decodes a pointer returned by a kernel function that uses `ERR_PTR` to
+/// signal failure, like `IS_ERR` and `PTR_ERR`.
+pub(crate) fn from_kernel_err_ptr<T>(ptr: *mut T) -> KernelResult<*mut T> {
+    // The kernel reserves the top MAX_ERRNO (4095) addresses for errors.
+    let value = ptr as isize;
+    if (-4095..0).contains(&value) {
+        return Err(Error::from_kernel_errno(value as c_types::c_int));
+    }
+    Ok(ptr)
+}
+
+/// Encodes an error as a pointer, like `ERR_PTR`, for callbacks that return
+/// pointers.
+pub(crate) fn to_kernel_err_ptr<T>(err: Error) -> *mut T {
+    err.to_kernel_errno() as isize as *mut T
+}
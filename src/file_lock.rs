@@ -0,0 +1,95 @@
+use core::marker;
+
+use crate::bindings;
+use crate::c_types;
+
+/// The kind of lock being requested, from `fl_type` or a lease argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockType {
+    /// A shared lock (`F_RDLCK`, or `LOCK_SH` for `flock`).
+    Read,
+    /// An exclusive lock (`F_WRLCK`, or `LOCK_EX` for `flock`).
+    Write,
+    /// Releasing a lock (`F_UNLCK`, or `LOCK_UN` for `flock`).
+    Unlock,
+}
+
+impl LockType {
+    pub(crate) fn from_kernel(t: c_types::c_long) -> Option<LockType> {
+        match t as u32 {
+            bindings::F_RDLCK => Some(LockType::Read),
+            bindings::F_WRLCK => Some(LockType::Write),
+            bindings::F_UNLCK => Some(LockType::Unlock),
+            _ => None,
+        }
+    }
+}
+
+/// How a lock request should be handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockCommand {
+    /// Test whether the lock could be placed (`F_GETLK`).
+    Get,
+    /// Place the lock, failing if there is a conflict (`F_SETLK`).
+    Set,
+    /// Place the lock, waiting for any conflict to go away (`F_SETLKW`).
+    SetWait,
+}
+
+impl LockCommand {
+    pub(crate) fn from_kernel(cmd: c_types::c_int) -> Option<LockCommand> {
+        match cmd as u32 {
+            bindings::F_GETLK => Some(LockCommand::Get),
+            bindings::F_SETLK => Some(LockCommand::Set),
+            bindings::F_SETLKW => Some(LockCommand::SetWait),
+            _ => None,
+        }
+    }
+}
+
+/// A borrowed reference to a kernel `struct file_lock` describing a requested
+/// POSIX, open file description or `flock` lock.
+pub struct FileLock<'a> {
+    ptr: *const bindings::file_lock,
+    lock_type: LockType,
+    _phantom: marker::PhantomData<&'a bindings::file_lock>,
+}
+
+impl FileLock<'_> {
+    // Returns None for locks of any other type, such as the LOCK_MAND
+    // flock locks that userspace could request before 5.17.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *const bindings::file_lock) -> Option<FileLock<'a>> {
+        let lock_type = LockType::from_kernel((*ptr).fl_type as c_types::c_long)?;
+        Some(FileLock {
+            ptr,
+            lock_type,
+            _phantom: marker::PhantomData,
+        })
+    }
+
+    pub fn lock_type(&self) -> LockType {
+        self.lock_type
+    }
+
+    /// The first byte covered by the lock.
+    pub fn start(&self) -> u64 {
+        unsafe { (*self.ptr).fl_start as u64 }
+    }
+
+    /// The last byte covered by the lock, inclusive. Locks that extend to the
+    /// end of the file have an end of `i64::MAX`.
+    pub fn end(&self) -> u64 {
+        unsafe { (*self.ptr).fl_end as u64 }
+    }
+
+    /// The process that requested the lock.
+    pub fn pid(&self) -> u32 {
+        unsafe { (*self.ptr).fl_pid as u32 }
+    }
+
+    /// Whether this is an open file description lock (`F_OFD_SETLK` and
+    /// friends) rather than a classic per-process POSIX lock.
+    pub fn is_ofd(&self) -> bool {
+        unsafe { (*self.ptr).fl_flags & bindings::FL_OFDLCK != 0 }
+    }
+}
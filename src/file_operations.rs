@@ -1,16 +1,60 @@
-use core::convert::{TryFrom, TryInto};
+use core::any::TypeId;
+use core::cell::UnsafeCell;
+use core::convert::TryInto;
 use core::{marker, mem, ptr};
 
 use alloc::boxed::Box;
 
 use crate::bindings;
 use crate::c_types;
-use crate::error::{Error, KernelResult};
-use crate::user_ptr::{UserSlicePtr, UserSlicePtrReader, UserSlicePtrWriter};
+use crate::error::{from_kernel_result, Error, KernelResult};
+use crate::file_lock::{FileLock, LockCommand, LockType};
+use crate::filesystem::Inode;
+use crate::io_buffer::{IoBufferReader, IoBufferWriter};
+use crate::iov_iter::IovIter;
+use crate::seq_file::SeqWriter;
+use crate::types::{Kgid, Kuid, Mode};
+use crate::user_ptr::UserSlicePtr;
 
 bitflags::bitflags! {
+    /// The `O_*` flags the file was opened with, as found in `f_flags`.
     pub struct FileFlags: c_types::c_uint {
+        const WRONLY = bindings::O_WRONLY;
+        const RDWR = bindings::O_RDWR;
+        const CREAT = bindings::O_CREAT;
+        const EXCL = bindings::O_EXCL;
+        const NOCTTY = bindings::O_NOCTTY;
+        const TRUNC = bindings::O_TRUNC;
+        const APPEND = bindings::O_APPEND;
         const NONBLOCK = bindings::O_NONBLOCK;
+        const DSYNC = bindings::O_DSYNC;
+        const SYNC = bindings::O_SYNC;
+        const DIRECT = bindings::O_DIRECT;
+        const LARGEFILE = bindings::O_LARGEFILE;
+        const DIRECTORY = bindings::O_DIRECTORY;
+        const NOFOLLOW = bindings::O_NOFOLLOW;
+        const NOATIME = bindings::O_NOATIME;
+        const CLOEXEC = bindings::O_CLOEXEC;
+        const PATH = bindings::O_PATH;
+    }
+}
+
+bitflags::bitflags! {
+    /// The `FMODE_*` bits of the open file, as found in `f_mode`.
+    pub struct FileMode: bindings::fmode_t {
+        const READ = bindings::BINDINGS_FMODE_READ;
+        const WRITE = bindings::BINDINGS_FMODE_WRITE;
+        const LSEEK = bindings::BINDINGS_FMODE_LSEEK;
+        const PREAD = bindings::BINDINGS_FMODE_PREAD;
+        const PWRITE = bindings::BINDINGS_FMODE_PWRITE;
+        const EXEC = bindings::BINDINGS_FMODE_EXEC;
+        const EXCL = bindings::BINDINGS_FMODE_EXCL;
+        const ATOMIC_POS = bindings::BINDINGS_FMODE_ATOMIC_POS;
+        const UNSIGNED_OFFSET = bindings::BINDINGS_FMODE_UNSIGNED_OFFSET;
+        #[cfg(kernel_4_13_0_or_greater)]
+        const NOWAIT = bindings::BINDINGS_FMODE_NOWAIT;
+        #[cfg(kernel_5_2_0_or_greater)]
+        const STREAM = bindings::BINDINGS_FMODE_STREAM;
     }
 }
 
@@ -30,6 +74,168 @@ impl File {
     pub fn flags(&self) -> FileFlags {
         FileFlags::from_bits_truncate(unsafe { (*self.ptr).f_flags })
     }
+
+    pub fn mode(&self) -> FileMode {
+        FileMode::from_bits_truncate(unsafe { (*self.ptr).f_mode })
+    }
+
+    /// The credentials of the task that opened this file. These don't change
+    /// for the lifetime of the file, even if the file is passed to another
+    /// process.
+    pub fn credentials(&self) -> Credentials {
+        unsafe { Credentials::from_ptr((*self.ptr).f_cred) }
+    }
+
+    pub fn inode(&self) -> Inode {
+        unsafe { Inode::from_ptr((*self.ptr).f_inode) }
+    }
+}
+
+/// A borrowed reference to a kernel `struct cred`.
+pub struct Credentials<'a> {
+    ptr: *const bindings::cred,
+    _phantom: marker::PhantomData<&'a bindings::cred>,
+}
+
+impl Credentials<'_> {
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *const bindings::cred) -> Credentials<'a> {
+        Credentials {
+            ptr,
+            _phantom: marker::PhantomData,
+        }
+    }
+
+    pub fn uid(&self) -> Kuid {
+        Kuid::from_kernel(unsafe { (*self.ptr).uid })
+    }
+
+    pub fn gid(&self) -> Kgid {
+        Kgid::from_kernel(unsafe { (*self.ptr).gid })
+    }
+
+    pub fn euid(&self) -> Kuid {
+        Kuid::from_kernel(unsafe { (*self.ptr).euid })
+    }
+
+    pub fn egid(&self) -> Kgid {
+        Kgid::from_kernel(unsafe { (*self.ptr).egid })
+    }
+
+    pub fn fsuid(&self) -> Kuid {
+        Kuid::from_kernel(unsafe { (*self.ptr).fsuid })
+    }
+
+    pub fn fsgid(&self) -> Kgid {
+        Kgid::from_kernel(unsafe { (*self.ptr).fsgid })
+    }
+}
+
+extern "C" {
+    fn dir_emit_helper(
+        ctx: *mut bindings::dir_context,
+        name: *const c_types::c_char,
+        namelen: c_types::c_int,
+        ino: u64,
+        type_: c_types::c_uint,
+    ) -> bool;
+    fn dir_emit_dots_helper(file: *mut bindings::file, ctx: *mut bindings::dir_context) -> bool;
+}
+
+/// The state of a directory listing, wrapping a `struct dir_context`. The
+/// position is chosen by the directory's implementation, and is where the
+/// next `getdents(2)` call resumes.
+pub struct DirContext {
+    ptr: *mut bindings::dir_context,
+}
+
+impl DirContext {
+    pub fn pos(&self) -> u64 {
+        unsafe { (*self.ptr).pos as u64 }
+    }
+
+    pub fn set_pos(&mut self, pos: u64) {
+        unsafe { (*self.ptr).pos = pos as bindings::loff_t };
+    }
+
+    /// Adds an entry to the listing at the current position. The file type
+    /// is taken from `mode`. Returns `false` if userspace's buffer is full, in
+    /// which case the listing should stop without changing the position.
+    pub fn emit(&mut self, name: &[u8], ino: u64, mode: &Mode) -> bool {
+        let file_type = (mode.as_int() >> 12) & 0o17;
+        unsafe {
+            dir_emit_helper(
+                self.ptr,
+                name.as_ptr() as *const c_types::c_char,
+                name.len() as c_types::c_int,
+                ino,
+                file_type.into(),
+            )
+        }
+    }
+
+    /// Adds the "." and ".." entries, which use positions 0 and 1, if the
+    /// listing hasn't got past them. Returns `false` if userspace's buffer is
+    /// full.
+    pub fn emit_dots(&mut self, file: &File) -> bool {
+        unsafe { dir_emit_dots_helper(file.ptr as *mut bindings::file, self.ptr) }
+    }
+}
+
+/// A list of files to notify with `SIGIO`, wrapping a `struct fasync_struct`
+/// list head. Return one from `FileOperations::FASYNC` to let files join it.
+pub struct FasyncQueue(UnsafeCell<*mut bindings::fasync_struct>);
+
+// This is safe because fasync_helper() and kill_fasync() do their own locking.
+unsafe impl Sync for FasyncQueue {}
+
+impl FasyncQueue {
+    pub const fn new() -> FasyncQueue {
+        FasyncQueue(UnsafeCell::new(ptr::null_mut()))
+    }
+
+    /// Sends `SIGIO` to every file in the queue, signalling that data is
+    /// available to read.
+    pub fn notify_readable(&self) {
+        unsafe {
+            bindings::kill_fasync(
+                self.0.get(),
+                bindings::SIGIO as c_types::c_int,
+                bindings::POLL_IN as c_types::c_int,
+            )
+        };
+    }
+
+    /// Sends `SIGIO` to every file in the queue, signalling that there is
+    /// room to write.
+    pub fn notify_writable(&self) {
+        unsafe {
+            bindings::kill_fasync(
+                self.0.get(),
+                bindings::SIGIO as c_types::c_int,
+                bindings::POLL_OUT as c_types::c_int,
+            )
+        };
+    }
+}
+
+impl Default for FasyncQueue {
+    fn default() -> FasyncQueue {
+        FasyncQueue::new()
+    }
+}
+
+bitflags::bitflags! {
+    /// The `FALLOC_FL_*` mode flags passed to `fallocate`.
+    pub struct FallocateMode: c_types::c_int {
+        const KEEP_SIZE = bindings::FALLOC_FL_KEEP_SIZE as c_types::c_int;
+        const PUNCH_HOLE = bindings::FALLOC_FL_PUNCH_HOLE as c_types::c_int;
+        const NO_HIDE_STALE = bindings::FALLOC_FL_NO_HIDE_STALE as c_types::c_int;
+        const COLLAPSE_RANGE = bindings::FALLOC_FL_COLLAPSE_RANGE as c_types::c_int;
+        const ZERO_RANGE = bindings::FALLOC_FL_ZERO_RANGE as c_types::c_int;
+        const INSERT_RANGE = bindings::FALLOC_FL_INSERT_RANGE as c_types::c_int;
+        #[cfg(kernel_4_9_0_or_greater)]
+        const UNSHARE_RANGE = bindings::FALLOC_FL_UNSHARE_RANGE as c_types::c_int;
+    }
 }
 
 // Matches std::io::SeekFrom in the Rust stdlib
@@ -39,16 +245,104 @@ pub enum SeekFrom {
     Current(i64),
 }
 
-unsafe extern "C" fn open_callback<T: FileOperations>(
-    _inode: *mut bindings::inode,
+unsafe extern "C" fn open_callback<T: FileOperations + FileOpen>(
+    inode: *mut bindings::inode,
+    file: *mut bindings::file,
+) -> c_types::c_int {
+    open_file(inode, file, T::open)
+}
+
+/// Sets up `file` as an instance of `T` created by `open`, for `open`
+/// callbacks that get their instance from somewhere other than
+/// `FileOpen::open`.
+pub(crate) unsafe fn open_file<T: FileOperations>(
+    inode: *mut bindings::inode,
     file: *mut bindings::file,
+    open: impl FnOnce() -> KernelResult<T>,
 ) -> c_types::c_int {
-    let f = match T::open() {
-        Ok(f) => Box::new(f),
-        Err(e) => return e.to_kernel_errno(),
+    from_kernel_result(|| {
+        if T::STREAM {
+            let rc = open_stream(inode, file);
+            if rc != 0 {
+                return Err(Error::from_kernel_errno(rc));
+            }
+        }
+        if T::UNSIGNED_OFFSET {
+            (*file).f_mode |= FileMode::UNSIGNED_OFFSET.bits();
+        }
+        let f = Box::new(FileData {
+            type_id: TypeId::of::<T>(),
+            value: open()?,
+        });
+        (*file).private_data = Box::into_raw(f) as *mut c_types::c_void;
+        Ok(0)
+    })
+}
+
+// What `open_file` stores in `private_data`. The type is recorded so that
+// `copy_file_range_callback` can tell whether another file holds a `T` too.
+#[repr(C)]
+struct FileData<T> {
+    type_id: TypeId,
+    value: T,
+}
+
+unsafe fn file_data<'a, T>(file: *mut bindings::file) -> &'a T {
+    &(*((*file).private_data as *const FileData<T>)).value
+}
+
+#[cfg(kernel_5_2_0_or_greater)]
+unsafe fn open_stream(inode: *mut bindings::inode, file: *mut bindings::file) -> c_types::c_int {
+    bindings::stream_open(inode, file)
+}
+
+// Before FMODE_STREAM existed, the closest equivalent was a nonseekable file,
+// which still gets a (meaningless) position passed to read and write.
+#[cfg(not(kernel_5_2_0_or_greater))]
+unsafe fn open_stream(inode: *mut bindings::inode, file: *mut bindings::file) -> c_types::c_int {
+    bindings::nonseekable_open(inode, file)
+}
+
+// Offsets must be in [0, 2^63) unless the file has FMODE_UNSIGNED_OFFSET, in
+// which case the whole u64 range is valid. See discussion in #113.
+fn position_from_loff(file: &File, offset: bindings::loff_t) -> KernelResult<u64> {
+    if file.mode().contains(FileMode::UNSIGNED_OFFSET) {
+        Ok(offset as u64)
+    } else {
+        Ok(offset.try_into()?)
+    }
+}
+
+fn position_to_loff(file: &File, pos: u64) -> KernelResult<bindings::loff_t> {
+    if file.mode().contains(FileMode::UNSIGNED_OFFSET) {
+        Ok(pos as bindings::loff_t)
+    } else {
+        Ok(pos.try_into()?)
+    }
+}
+
+// Shared by the read and write callbacks: runs `f` with the position stored
+// in `offset`, commits the position it chose, and checks the byte count it
+// returned. `offset` is NULL for stream files, which have no position.
+unsafe fn transfer(
+    file: &File,
+    len: usize,
+    offset: *mut bindings::loff_t,
+    f: impl FnOnce(&mut u64) -> KernelResult<usize>,
+) -> KernelResult<c_types::c_ssize_t> {
+    let mut pos = if offset.is_null() {
+        0
+    } else {
+        position_from_loff(file, *offset)?
     };
-    (*file).private_data = Box::into_raw(f) as *mut c_types::c_void;
-    0
+    let n = f(&mut pos)?;
+    if n > len {
+        return Err(Error::EINVAL);
+    }
+    if !offset.is_null() {
+        *offset = position_to_loff(file, pos)?;
+    }
+    Ok(n.try_into()?)
 }
 
 unsafe extern "C" fn read_callback<T: FileOperations>(
@@ -57,26 +351,13 @@ unsafe extern "C" fn read_callback<T: FileOperations>(
     len: c_types::c_size_t,
     offset: *mut bindings::loff_t,
 ) -> c_types::c_ssize_t {
-    let mut data = match UserSlicePtr::new(buf as *mut c_types::c_void, len) {
-        Ok(ptr) => ptr.writer(),
-        Err(e) => return e.to_kernel_errno().try_into().unwrap(),
-    };
-    let f = &*((*file).private_data as *const T);
-    // No FMODE_UNSIGNED_OFFSET support, so offset must be in [0, 2^63).
-    // See discussion in #113
-    let positive_offset = match (*offset).try_into() {
-        Ok(v) => v,
-        Err(_) => return Error::EINVAL.to_kernel_errno().try_into().unwrap(),
-    };
-    let read = T::READ.unwrap();
-    match read(f, &File::from_ptr(file), &mut data, positive_offset) {
-        Ok(()) => {
-            let written = len - data.len();
-            (*offset) += bindings::loff_t::try_from(written).unwrap();
-            written.try_into().unwrap()
-        }
-        Err(e) => e.to_kernel_errno().try_into().unwrap(),
-    }
+    from_kernel_result(|| {
+        let mut data = UserSlicePtr::new(buf as *mut c_types::c_void, len)?.writer();
+        let f = file_data::<T>(file);
+        let read = T::READ.ok_or(Error::EINVAL)?;
+        let file = File::from_ptr(file);
+        transfer(&file, len, offset, |pos| read(f, &file, &mut data, pos))
+    })
 }
 
 unsafe extern "C" fn write_callback<T: FileOperations>(
@@ -85,26 +366,81 @@ unsafe extern "C" fn write_callback<T: FileOperations>(
     len: c_types::c_size_t,
     offset: *mut bindings::loff_t,
 ) -> c_types::c_ssize_t {
-    let mut data = match UserSlicePtr::new(buf as *mut c_types::c_void, len) {
-        Ok(ptr) => ptr.reader(),
-        Err(e) => return e.to_kernel_errno().try_into().unwrap(),
-    };
-    let f = &*((*file).private_data as *const T);
-    // No FMODE_UNSIGNED_OFFSET support, so offset must be in [0, 2^63).
-    // See discussion in #113
-    let positive_offset = match (*offset).try_into() {
-        Ok(v) => v,
-        Err(_) => return Error::EINVAL.to_kernel_errno().try_into().unwrap(),
-    };
-    let write = T::WRITE.unwrap();
-    match write(f, &mut data, positive_offset) {
-        Ok(()) => {
-            let read = len - data.len();
-            (*offset) += bindings::loff_t::try_from(read).unwrap();
-            read.try_into().unwrap()
+    from_kernel_result(|| {
+        let mut data = UserSlicePtr::new(buf as *mut c_types::c_void, len)?.reader();
+        let f = file_data::<T>(file);
+        let write = T::WRITE.ok_or(Error::EINVAL)?;
+        let file = File::from_ptr(file);
+        transfer(&file, len, offset, |pos| write(f, &file, &mut data, pos))
+    })
+}
+
+unsafe extern "C" fn read_iter_callback<T: FileOperations>(
+    iocb: *mut bindings::kiocb,
+    iter: *mut bindings::iov_iter,
+) -> c_types::c_ssize_t {
+    from_kernel_result(|| {
+        let mut data = IovIter::from_ptr(iter);
+        let len = IoBufferWriter::len(&data);
+        let file = (*iocb).ki_filp;
+        let f = file_data::<T>(file);
+        let read = T::READ.ok_or(Error::EINVAL)?;
+        let file = File::from_ptr(file);
+        transfer(&file, len, &mut (*iocb).ki_pos, |pos| {
+            read(f, &file, &mut data, pos)
+        })
+    })
+}
+
+unsafe extern "C" fn write_iter_callback<T: FileOperations>(
+    iocb: *mut bindings::kiocb,
+    iter: *mut bindings::iov_iter,
+) -> c_types::c_ssize_t {
+    from_kernel_result(|| {
+        let mut data = IovIter::from_ptr(iter);
+        let len = IoBufferReader::len(&data);
+        let file = (*iocb).ki_filp;
+        let f = file_data::<T>(file);
+        let write = T::WRITE.ok_or(Error::EINVAL)?;
+        let file = File::from_ptr(file);
+        transfer(&file, len, &mut (*iocb).ki_pos, |pos| {
+            write(f, &file, &mut data, pos)
+        })
+    })
+}
+
+#[cfg(kernel_4_5_0_or_greater)]
+unsafe extern "C" fn copy_file_range_callback<T: FileOperations>(
+    file_in: *mut bindings::file,
+    pos_in: bindings::loff_t,
+    file_out: *mut bindings::file,
+    pos_out: bindings::loff_t,
+    len: c_types::c_size_t,
+    _flags: c_types::c_uint,
+) -> c_types::c_ssize_t {
+    from_kernel_result(|| {
+        let f = file_data::<T>(file_out);
+        // Only files opened by open_file can share this copy_file_range
+        // operation, but identical functions for different types may have
+        // been merged into one, so check the type as well.
+        let same_type = (*(*file_in).f_op).copy_file_range == (*(*file_out).f_op).copy_file_range
+            && (*((*file_in).private_data as *const FileData<()>)).type_id == TypeId::of::<T>();
+        let f_in = if same_type {
+            Some(file_data::<T>(file_in))
+        } else {
+            None
+        };
+        let file_in = File::from_ptr(file_in);
+        let file_out = File::from_ptr(file_out);
+        let pos_in = position_from_loff(&file_in, pos_in)?;
+        let pos_out = position_from_loff(&file_out, pos_out)?;
+        let copy_file_range = T::COPY_FILE_RANGE.ok_or(Error::EINVAL)?;
+        let n = copy_file_range(f, &file_out, pos_out, f_in, &file_in, pos_in, len)?;
+        if n > len {
+            return Err(Error::EINVAL);
         }
-        Err(e) => e.to_kernel_errno().try_into().unwrap(),
-    }
+        Ok(n.try_into()?)
+    })
 }
 
 unsafe extern "C" fn release_callback<T: FileOperations>(
@@ -112,7 +448,7 @@ unsafe extern "C" fn release_callback<T: FileOperations>(
     file: *mut bindings::file,
 ) -> c_types::c_int {
     let ptr = mem::replace(&mut (*file).private_data, ptr::null_mut());
-    drop(Box::from_raw(ptr as *mut T));
+    drop(Box::from_raw(ptr as *mut FileData<T>));
     0
 }
 
@@ -121,28 +457,252 @@ unsafe extern "C" fn llseek_callback<T: FileOperations>(
     offset: bindings::loff_t,
     whence: c_types::c_int,
 ) -> bindings::loff_t {
-    let off = match whence as u32 {
-        bindings::SEEK_SET => match offset.try_into() {
-            Ok(v) => SeekFrom::Start(v),
-            Err(_) => return Error::EINVAL.to_kernel_errno().into(),
-        },
-        bindings::SEEK_CUR => SeekFrom::Current(offset),
-        bindings::SEEK_END => SeekFrom::End(offset),
-        _ => return Error::EINVAL.to_kernel_errno().into(),
-    };
-    let f = &*((*file).private_data as *const T);
-    let seek = T::SEEK.unwrap();
-    match seek(f, &File::from_ptr(file), off) {
-        Ok(off) => off as bindings::loff_t,
-        Err(e) => e.to_kernel_errno().into(),
+    from_kernel_result(|| {
+        let off = match whence as u32 {
+            bindings::SEEK_SET => {
+                SeekFrom::Start(position_from_loff(&File::from_ptr(file), offset)?)
+            }
+            bindings::SEEK_CUR => SeekFrom::Current(offset),
+            bindings::SEEK_END => SeekFrom::End(offset),
+            _ => return Err(Error::EINVAL),
+        };
+        let f = file_data::<T>(file);
+        let seek = T::SEEK.ok_or(Error::EINVAL)?;
+        let off = seek(f, &File::from_ptr(file), off)?;
+        // Like vfs_setpos(), commit the new position to the file.
+        let off = position_to_loff(&File::from_ptr(file), off)?;
+        if off != (*file).f_pos {
+            (*file).f_pos = off;
+            (*file).f_version = 0;
+        }
+        Ok(off)
+    })
+}
+
+unsafe extern "C" fn fsync_callback<T: FileOperations>(
+    file: *mut bindings::file,
+    start: bindings::loff_t,
+    end: bindings::loff_t,
+    datasync: c_types::c_int,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let f = file_data::<T>(file);
+        let fsync = T::FSYNC.ok_or(Error::EINVAL)?;
+        fsync(
+            f,
+            &File::from_ptr(file),
+            start.try_into()?,
+            end.try_into()?,
+            datasync != 0,
+        )?;
+        Ok(0)
+    })
+}
+
+unsafe extern "C" fn flush_callback<T: FileOperations>(
+    file: *mut bindings::file,
+    _id: bindings::fl_owner_t,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let f = file_data::<T>(file);
+        let flush = T::FLUSH.ok_or(Error::EINVAL)?;
+        flush(f, &File::from_ptr(file))?;
+        Ok(0)
+    })
+}
+
+unsafe extern "C" fn fasync_callback<T: FileOperations>(
+    fd: c_types::c_int,
+    file: *mut bindings::file,
+    on: c_types::c_int,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let f = file_data::<T>(file);
+        let fasync = T::FASYNC.ok_or(Error::EINVAL)?;
+        let queue = fasync(f);
+        Ok(bindings::fasync_helper(fd, file, on, queue.0.get()))
+    })
+}
+
+unsafe extern "C" fn fallocate_callback<T: FileOperations>(
+    file: *mut bindings::file,
+    mode: c_types::c_int,
+    offset: bindings::loff_t,
+    len: bindings::loff_t,
+) -> c_types::c_long {
+    from_kernel_result(|| {
+        let mode = FallocateMode::from_bits(mode).ok_or(Error::EOPNOTSUPP)?;
+        let f = file_data::<T>(file);
+        let fallocate = T::FALLOCATE.ok_or(Error::EINVAL)?;
+        fallocate(
+            f,
+            &File::from_ptr(file),
+            mode,
+            offset.try_into()?,
+            len.try_into()?,
+        )?;
+        Ok(0)
+    })
+}
+
+extern "C" {
+    fn locks_lock_file_wait_helper(
+        file: *mut bindings::file,
+        fl: *mut bindings::file_lock,
+    ) -> c_types::c_int;
+}
+
+unsafe extern "C" fn lock_callback<T: FileOperations>(
+    file: *mut bindings::file,
+    cmd: c_types::c_int,
+    fl: *mut bindings::file_lock,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let cmd = LockCommand::from_kernel(cmd).ok_or(Error::EINVAL)?;
+        let lock = FileLock::from_ptr(fl).ok_or(Error::EINVAL)?;
+        // Unlocking must always succeed, since the kernel relies on it to
+        // clean up when a file is closed.
+        if lock.lock_type() != LockType::Unlock {
+            let f = file_data::<T>(file);
+            let check = T::LOCK.ok_or(Error::EINVAL)?;
+            check(f, &File::from_ptr(file), cmd, &lock)?;
+        }
+        match cmd {
+            LockCommand::Get => {
+                bindings::posix_test_lock(file, fl);
+                Ok(0)
+            }
+            LockCommand::Set | LockCommand::SetWait => {
+                Ok(bindings::posix_lock_file(file, fl, ptr::null_mut()))
+            }
+        }
+    })
+}
+
+unsafe extern "C" fn flock_callback<T: FileOperations>(
+    file: *mut bindings::file,
+    cmd: c_types::c_int,
+    fl: *mut bindings::file_lock,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let cmd = LockCommand::from_kernel(cmd).ok_or(Error::EINVAL)?;
+        let lock = FileLock::from_ptr(fl).ok_or(Error::EINVAL)?;
+        // As with lock_callback, unlocking must always succeed.
+        if lock.lock_type() != LockType::Unlock {
+            let f = file_data::<T>(file);
+            let check = T::FLOCK.ok_or(Error::EINVAL)?;
+            check(f, &File::from_ptr(file), cmd, &lock)?;
+        }
+        Ok(locks_lock_file_wait_helper(file, fl))
+    })
+}
+
+unsafe extern "C" fn setlease_callback<T: FileOperations>(
+    file: *mut bindings::file,
+    arg: c_types::c_long,
+    lease: *mut *mut bindings::file_lock,
+    private: *mut *mut c_types::c_void,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let lease_type = LockType::from_kernel(arg).ok_or(Error::EINVAL)?;
+        if lease_type != LockType::Unlock {
+            let f = file_data::<T>(file);
+            let check = T::SETLEASE.ok_or(Error::EINVAL)?;
+            check(f, &File::from_ptr(file), lease_type)?;
+        }
+        Ok(bindings::generic_setlease(file, arg, lease, private))
+    })
+}
+
+unsafe extern "C" fn show_fdinfo_callback<T: FileOperations>(
+    m: *mut bindings::seq_file,
+    file: *mut bindings::file,
+) {
+    let f = file_data::<T>(file);
+    if let Some(show_fdinfo) = T::SHOW_FDINFO {
+        // There's no way to report an error, and a full buffer is retried by
+        // the kernel, so the result is deliberately ignored.
+        let _ = show_fdinfo(f, &File::from_ptr(file), &mut SeqWriter::from_ptr(m));
     }
 }
 
+unsafe extern "C" fn iterate_callback<T: FileOperations>(
+    file: *mut bindings::file,
+    ctx: *mut bindings::dir_context,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let f = file_data::<T>(file);
+        let iterate = T::ITERATE.ok_or(Error::EINVAL)?;
+        iterate(f, &File::from_ptr(file), &mut DirContext { ptr: ctx })?;
+        Ok(0)
+    })
+}
+
+// A `struct file_operations` with every operation unset, to build vtables
+// from with struct update syntax.
+pub(crate) const EMPTY_VTABLE: bindings::file_operations = bindings::file_operations {
+    open: None,
+    release: None,
+    read: None,
+    write: None,
+    llseek: None,
+    fsync: None,
+    flush: None,
+    fasync: None,
+    fallocate: None,
+    lock: None,
+    flock: None,
+    setlease: None,
+    show_fdinfo: None,
+    read_iter: None,
+    write_iter: None,
+    splice_read: None,
+    splice_write: None,
+    #[cfg(not(kernel_4_9_0_or_greater))]
+    aio_fsync: None,
+    check_flags: None,
+    #[cfg(all(kernel_4_5_0_or_greater, not(kernel_4_20_0_or_greater)))]
+    clone_file_range: None,
+    compat_ioctl: None,
+    #[cfg(kernel_4_5_0_or_greater)]
+    copy_file_range: None,
+    #[cfg(all(kernel_4_5_0_or_greater, not(kernel_4_20_0_or_greater)))]
+    dedupe_file_range: None,
+    #[cfg(kernel_4_19_0_or_greater)]
+    fadvise: None,
+    get_unmapped_area: None,
+    iterate: None,
+    #[cfg(kernel_4_7_0_or_greater)]
+    iterate_shared: None,
+    #[cfg(kernel_5_1_0_or_greater)]
+    iopoll: None,
+    mmap: None,
+    #[cfg(kernel_4_15_0_or_greater)]
+    mmap_supported_flags: 0,
+    owner: ptr::null_mut(),
+    poll: None,
+    #[cfg(kernel_4_20_0_or_greater)]
+    remap_file_range: None,
+    sendpage: None,
+    #[cfg(kernel_aufs_setfl)]
+    setfl: None,
+    unlocked_ioctl: None,
+};
+
 pub(crate) struct FileOperationsVtable<T>(marker::PhantomData<T>);
 
-impl<T: FileOperations> FileOperationsVtable<T> {
+impl<T: FileOperations + FileOpen> FileOperationsVtable<T> {
     pub(crate) const VTABLE: bindings::file_operations = bindings::file_operations {
         open: Some(open_callback::<T>),
+        ..Self::OPERATIONS
+    };
+}
+
+impl<T: FileOperations> FileOperationsVtable<T> {
+    // Every operation other than open, which depends on how instances of T
+    // are created.
+    pub(crate) const OPERATIONS: bindings::file_operations = bindings::file_operations {
+        open: None,
         release: Some(release_callback::<T>),
         read: if let Some(_) = T::READ {
             Some(read_callback::<T>)
@@ -154,78 +714,231 @@ impl<T: FileOperations> FileOperationsVtable<T> {
         } else {
             None
         },
+        read_iter: if let Some(_) = T::READ {
+            Some(read_iter_callback::<T>)
+        } else {
+            None
+        },
+        write_iter: if let Some(_) = T::WRITE {
+            Some(write_iter_callback::<T>)
+        } else {
+            None
+        },
+        splice_read: if let Some(_) = T::READ {
+            Some(bindings::generic_file_splice_read)
+        } else {
+            None
+        },
+        splice_write: if let Some(_) = T::WRITE {
+            Some(bindings::iter_file_splice_write)
+        } else {
+            None
+        },
+        #[cfg(kernel_4_5_0_or_greater)]
+        copy_file_range: if let Some(_) = T::COPY_FILE_RANGE {
+            Some(copy_file_range_callback::<T>)
+        } else {
+            None
+        },
         llseek: if let Some(_) = T::SEEK {
             Some(llseek_callback::<T>)
         } else {
             None
         },
-
-        #[cfg(not(kernel_4_9_0_or_greater))]
-        aio_fsync: None,
-        check_flags: None,
-        #[cfg(all(kernel_4_5_0_or_greater, not(kernel_4_20_0_or_greater)))]
-        clone_file_range: None,
-        compat_ioctl: None,
-        #[cfg(kernel_4_5_0_or_greater)]
-        copy_file_range: None,
-        #[cfg(all(kernel_4_5_0_or_greater, not(kernel_4_20_0_or_greater)))]
-        dedupe_file_range: None,
-        fallocate: None,
-        #[cfg(kernel_4_19_0_or_greater)]
-        fadvise: None,
-        fasync: None,
-        flock: None,
-        flush: None,
-        fsync: None,
-        get_unmapped_area: None,
-        iterate: None,
+        fsync: if let Some(_) = T::FSYNC {
+            Some(fsync_callback::<T>)
+        } else {
+            None
+        },
+        flush: if let Some(_) = T::FLUSH {
+            Some(flush_callback::<T>)
+        } else {
+            None
+        },
+        fasync: if let Some(_) = T::FASYNC {
+            Some(fasync_callback::<T>)
+        } else {
+            None
+        },
+        fallocate: if let Some(_) = T::FALLOCATE {
+            Some(fallocate_callback::<T>)
+        } else {
+            None
+        },
+        lock: if let Some(_) = T::LOCK {
+            Some(lock_callback::<T>)
+        } else {
+            None
+        },
+        flock: if let Some(_) = T::FLOCK {
+            Some(flock_callback::<T>)
+        } else {
+            None
+        },
+        setlease: if let Some(_) = T::SETLEASE {
+            Some(setlease_callback::<T>)
+        } else {
+            None
+        },
+        show_fdinfo: if let Some(_) = T::SHOW_FDINFO {
+            Some(show_fdinfo_callback::<T>)
+        } else {
+            None
+        },
         #[cfg(kernel_4_7_0_or_greater)]
-        iterate_shared: None,
-        #[cfg(kernel_5_1_0_or_greater)]
-        iopoll: None,
-        lock: None,
-        mmap: None,
-        #[cfg(kernel_4_15_0_or_greater)]
-        mmap_supported_flags: 0,
-        owner: ptr::null_mut(),
-        poll: None,
-        read_iter: None,
-        #[cfg(kernel_4_20_0_or_greater)]
-        remap_file_range: None,
-        sendpage: None,
-        #[cfg(kernel_aufs_setfl)]
-        setfl: None,
-        setlease: None,
-        show_fdinfo: None,
-        splice_read: None,
-        splice_write: None,
-        unlocked_ioctl: None,
-        write_iter: None,
+        iterate_shared: if let Some(_) = T::ITERATE {
+            Some(iterate_callback::<T>)
+        } else {
+            None
+        },
+        #[cfg(not(kernel_4_7_0_or_greater))]
+        iterate: if let Some(_) = T::ITERATE {
+            Some(iterate_callback::<T>)
+        } else {
+            None
+        },
+
+        ..EMPTY_VTABLE
     };
 }
 
-pub type ReadFn<T> = Option<fn(&T, &File, &mut UserSlicePtrWriter, u64) -> KernelResult<()>>;
-pub type WriteFn<T> = Option<fn(&T, &mut UserSlicePtrReader, u64) -> KernelResult<()>>;
+pub type ReadFn<T> =
+    Option<fn(&T, &File, &mut dyn IoBufferWriter, &mut u64) -> KernelResult<usize>>;
+pub type WriteFn<T> =
+    Option<fn(&T, &File, &mut dyn IoBufferReader, &mut u64) -> KernelResult<usize>>;
 pub type SeekFn<T> = Option<fn(&T, &File, SeekFrom) -> KernelResult<u64>>;
+pub type FsyncFn<T> = Option<fn(&T, &File, u64, u64, bool) -> KernelResult<()>>;
+pub type FlushFn<T> = Option<fn(&T, &File) -> KernelResult<()>>;
+pub type FasyncFn<T> = Option<fn(&T) -> &FasyncQueue>;
+pub type FallocateFn<T> = Option<fn(&T, &File, FallocateMode, u64, u64) -> KernelResult<()>>;
+pub type CopyFileRangeFn<T> =
+    Option<fn(&T, &File, u64, Option<&T>, &File, u64, usize) -> KernelResult<usize>>;
+pub type LockFn<T> = Option<fn(&T, &File, LockCommand, &FileLock) -> KernelResult<()>>;
+pub type SetleaseFn<T> = Option<fn(&T, &File, LockType) -> KernelResult<()>>;
+pub type ShowFdinfoFn<T> = Option<fn(&T, &File, &mut SeqWriter) -> KernelResult<()>>;
+pub type IterateFn<T> = Option<fn(&T, &File, &mut DirContext) -> KernelResult<()>>;
 
 /// `FileOperations` corresponds to the kernel's `struct file_operations`. You
 /// implement this trait whenever you'd create a `struct file_operations`.
 /// File descriptors may be used from multiple threads (or processes)
-/// concurrently, so your type must be `Sync`.
-pub trait FileOperations: Sync + Sized {
-    /// Creates a new instance of this file. Corresponds to the `open` function
-    /// pointer in `struct file_operations`.
-    fn open() -> KernelResult<Self>;
-
+/// concurrently, so your type must be `Sync`. How instances are created is
+/// up to `FileOpen` or `FileOpener`.
+pub trait FileOperations: Sync + Sized + 'static {
     /// Reads data from this file to userspace. Corresponds to the `read`
     /// function pointer in `struct file_operations`.
+    ///
+    /// This is also used to implement `read_iter`, so the data may be going
+    /// to kernel memory or to a pipe for `splice(2)` and `sendfile(2)` rather
+    /// than directly to userspace.
+    ///
+    /// Returns the number of bytes to report to userspace, which may not
+    /// exceed the size of the user buffer. The position is passed by
+    /// reference and is only changed if the callback changes it, so most
+    /// implementations will want to advance it by the number of bytes read.
     const READ: ReadFn<Self> = None;
 
-    /// Writes data from userspace o this file. Corresponds to the `write`
+    /// Writes data from userspace to this file. Corresponds to the `write`
     /// function pointer in `struct file_operations`.
+    ///
+    /// The return value and position are handled as for `READ`, and, as with
+    /// `READ`, this also implements `write_iter` and splicing from a pipe.
     const WRITE: WriteFn<Self> = None;
 
     /// Changes the position of the file. Corresponds to the `llseek` function
     /// pointer in `struct file_operations`.
     const SEEK: SeekFn<Self> = None;
+
+    /// Writes any buffered data for the byte range `start..=end` to its
+    /// backing store. The final argument is `true` for `fdatasync`, which
+    /// need not flush metadata. Corresponds to the `fsync` function pointer
+    /// in `struct file_operations`.
+    const FSYNC: FsyncFn<Self> = None;
+
+    /// Called whenever a file descriptor referring to this file is closed,
+    /// including duplicates, unlike `Drop` which only runs on the final
+    /// close. Corresponds to the `flush` function pointer in
+    /// `struct file_operations`.
+    const FLUSH: FlushFn<Self> = None;
+
+    /// Returns the queue this file should join when userspace asks for
+    /// `SIGIO` notifications with `O_ASYNC`. Removal from the queue is handled
+    /// automatically when the flag is cleared or the file is closed.
+    /// Corresponds to the `fasync` function pointer in
+    /// `struct file_operations`.
+    const FASYNC: FasyncFn<Self> = None;
+
+    /// Allocates or deallocates space for the given offset and length.
+    /// Corresponds to the `fallocate` function pointer in
+    /// `struct file_operations`.
+    const FALLOCATE: FallocateFn<Self> = None;
+
+    /// Copies `len` bytes from another file at the given position into this
+    /// file at the given position, returning the number of bytes copied.
+    /// If the source file is of the same type, its state is passed as well.
+    /// Returning `EOPNOTSUPP` or `EXDEV` lets the kernel fall back to a
+    /// generic copy on kernels that support one. Corresponds to the
+    /// `copy_file_range` function pointer in `struct file_operations`, and is
+    /// only used on 4.5 and later kernels.
+    const COPY_FILE_RANGE: CopyFileRangeFn<Self> = None;
+
+    /// Decides whether a POSIX or open file description byte-range lock
+    /// (`fcntl(F_SETLK)` and friends) may be taken. If it returns `Ok`, the
+    /// lock is handled exactly as the kernel would for a file with no `lock`
+    /// operation; otherwise the error is returned to userspace. Unlock
+    /// requests are always allowed without calling this. Corresponds to the
+    /// `lock` function pointer in `struct file_operations`.
+    const LOCK: LockFn<Self> = None;
+
+    /// Decides whether a `flock(2)` lock may be taken, in the same way as
+    /// `LOCK`. Corresponds to the `flock` function pointer in
+    /// `struct file_operations`.
+    const FLOCK: LockFn<Self> = None;
+
+    /// Decides whether a lease (`fcntl(F_SETLEASE)`) may be taken, in the
+    /// same way as `LOCK`. The kernel only allows leases on regular files
+    /// owned by the caller, and checks that before calling this. Corresponds
+    /// to the `setlease` function pointer in `struct file_operations`.
+    const SETLEASE: SetleaseFn<Self> = None;
+
+    /// Writes extra per-descriptor information, which is shown after the
+    /// standard fields in `/proc/<pid>/fdinfo/<fd>`. Corresponds to the
+    /// `show_fdinfo` function pointer in `struct file_operations`.
+    const SHOW_FDINFO: ShowFdinfoFn<Self> = None;
+
+    /// Lists the entries of a directory, starting from the context's
+    /// position. Reaching the end of the directory or a full buffer should
+    /// both return `Ok`. This is called with the directory's lock held for
+    /// reading, so it may run concurrently for different files. Corresponds
+    /// to the `iterate_shared` function pointer in `struct file_operations`,
+    /// or to `iterate` on kernels before 4.7.
+    const ITERATE: IterateFn<Self> = None;
+
+    /// Opens the file as a stream with no position, like `stream_open`. Reads
+    /// and writes always see a position of 0, and `pread`/`pwrite` and
+    /// seeking fail with `ESPIPE`. On kernels before 5.2 this falls back to
+    /// `nonseekable_open`.
+    const STREAM: bool = false;
+
+    /// Sets `FMODE_UNSIGNED_OFFSET` on open, allowing positions in the full
+    /// `u64` range rather than only those that fit in a signed `loff_t`.
+    const UNSIGNED_OFFSET: bool = false;
+}
+
+/// `FileOpen` creates instances of a file type, such as a `FileOperations`,
+/// `SeqFile` or `SingleSeqFile`, for files that aren't given any value to
+/// open them with, which is how most files are registered.
+pub trait FileOpen: Sized {
+    /// Creates a new instance of this file. Corresponds to the `open` function
+    /// pointer in `struct file_operations`.
+    fn open() -> KernelResult<Self>;
+}
+
+/// `FileOpener` creates instances of a `FileOperations` type from a value
+/// attached to the device, so that several devices can share one
+/// implementation with different configuration. Types that are only ever
+/// registered with a value don't need to implement `FileOpen`.
+pub trait FileOpener<D>: FileOperations {
+    /// Creates a new instance of this file for the device holding `data`.
+    /// The value outlives every file opened from the device.
+    fn open_with(data: &D) -> KernelResult<Self>;
 }
@@ -1,15 +1,295 @@
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::TypeId;
 use core::default::Default;
 use core::marker;
+use core::ops::Deref;
+use core::{ptr, slice, str};
 
 use crate::bindings;
 use crate::c_types;
-use crate::error;
-use crate::types::CStr;
+use crate::error::{self, Error, KernelResult};
+use crate::file_operations::{FileOpen, FileOperations, FileOperationsVtable};
+use crate::inode_operations::{InodeOperations, InodeOperationsVtable};
+use crate::seq_file::{SeqFile, SeqFileVtable, SingleSeqFile, SingleSeqFileVtable};
+use crate::super_operations::{SuperOperations, SuperOperationsVtable};
+use crate::sysctl::parse_integer;
+use crate::types::{CStr, Kgid, Kuid, Mode};
+
+extern "C" {
+    fn i_size_read_helper(inode: *const bindings::inode) -> bindings::loff_t;
+    fn i_size_write_helper(inode: *mut bindings::inode, size: bindings::loff_t);
+    fn inode_lock_helper(inode: *mut bindings::inode);
+    fn inode_unlock_helper(inode: *mut bindings::inode);
+}
+
+/// A borrowed reference to a kernel `struct inode`.
+pub struct Inode<'a> {
+    ptr: *const bindings::inode,
+    _phantom: marker::PhantomData<&'a bindings::inode>,
+}
+
+impl Inode<'_> {
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *const bindings::inode) -> Inode<'a> {
+        Inode {
+            ptr,
+            _phantom: marker::PhantomData,
+        }
+    }
+
+    pub fn ino(&self) -> u64 {
+        unsafe { (*self.ptr).i_ino as u64 }
+    }
+
+    pub fn mode(&self) -> Mode {
+        Mode::from_int(unsafe { (*self.ptr).i_mode })
+    }
+
+    pub fn uid(&self) -> Kuid {
+        Kuid::from_kernel(unsafe { (*self.ptr).i_uid })
+    }
+
+    pub fn gid(&self) -> Kgid {
+        Kgid::from_kernel(unsafe { (*self.ptr).i_gid })
+    }
+
+    pub fn size(&self) -> i64 {
+        unsafe { i_size_read_helper(self.ptr) }
+    }
+
+    /// Takes the inode's lock (`i_rwsem`), which the kernel holds whenever it
+    /// changes the inode's size or attributes. The kernel already holds it
+    /// when calling some operations, such as `SETATTR`, which must not take
+    /// it again.
+    pub fn lock(&self) -> LockedInode {
+        unsafe { inode_lock_helper(self.ptr as *mut bindings::inode) };
+        LockedInode {
+            ptr: self.ptr as *mut bindings::inode,
+            _phantom: marker::PhantomData,
+        }
+    }
+
+    /// Returns the value the inode was created with by `get_inode`, or
+    /// `None` if it was created with a different type or by other means.
+    pub fn data<I: InodeOperations>(&self) -> Option<&I> {
+        unsafe {
+            let data = (*self.ptr).i_private as *const InodeData<I>;
+            if data.is_null() || (*data).type_id != TypeId::of::<I>() {
+                return None;
+            }
+            Some(&(*data).data)
+        }
+    }
+
+    /// Returns the inode numbered `ino` on the same filesystem as this one.
+    /// See `SuperBlock::get_inode`.
+    pub fn get_inode<I: InodeOperations, F: FileOperations + FileOpen>(
+        &self,
+        ino: u64,
+        init: impl FnOnce() -> KernelResult<NewInode<I>>,
+    ) -> KernelResult<InodeRef> {
+        unsafe { get_inode::<I, F>((*self.ptr).i_sb, ino, init) }
+    }
+
+    /// Returns the device the inode's filesystem is mounted from, or `None`
+    /// if it doesn't have one. See `SuperBlock::block_device`.
+    pub fn block_device(&self) -> Option<BlockDevice> {
+        unsafe { BlockDevice::from_super_block((*self.ptr).i_sb) }
+    }
+}
+
+/// An inode whose lock is held, which is released when this is dropped. See
+/// `Inode::lock`.
+pub struct LockedInode<'a> {
+    ptr: *mut bindings::inode,
+    _phantom: marker::PhantomData<&'a bindings::inode>,
+}
+
+impl LockedInode<'_> {
+    /// Sets the size of the file, for example after a write past its end.
+    pub fn set_size(&self, size: i64) {
+        unsafe { i_size_write_helper(self.ptr, size) };
+    }
+}
+
+impl Drop for LockedInode<'_> {
+    fn drop(&mut self) {
+        unsafe { inode_unlock_helper(self.ptr) };
+    }
+}
+
+/// An owned reference to a kernel `struct inode`, which is released with
+/// `iput` when dropped.
+pub struct InodeRef(Inode<'static>);
+
+impl InodeRef {
+    unsafe fn from_raw(ptr: *mut bindings::inode) -> InodeRef {
+        InodeRef(Inode::from_ptr(ptr))
+    }
+
+    /// Gives up ownership of the reference, for passing to kernel functions
+    /// that consume it.
+    pub(crate) fn into_raw(self) -> *mut bindings::inode {
+        let ptr = self.0.ptr as *mut bindings::inode;
+        core::mem::forget(self);
+        ptr
+    }
+}
+
+impl Deref for InodeRef {
+    type Target = Inode<'static>;
+
+    fn deref(&self) -> &Inode<'static> {
+        &self.0
+    }
+}
+
+impl Clone for InodeRef {
+    fn clone(&self) -> InodeRef {
+        let ptr = self.0.ptr as *mut bindings::inode;
+        unsafe {
+            bindings::ihold(ptr);
+            InodeRef::from_raw(ptr)
+        }
+    }
+}
+
+impl Drop for InodeRef {
+    fn drop(&mut self) {
+        unsafe { bindings::iput(self.0.ptr as *mut bindings::inode) };
+    }
+}
+
+/// A borrowed reference to a kernel `struct dentry`, a name in a directory.
+pub struct Dentry<'a> {
+    ptr: *const bindings::dentry,
+    _phantom: marker::PhantomData<&'a bindings::dentry>,
+}
+
+impl Dentry<'_> {
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *const bindings::dentry) -> Dentry<'a> {
+        Dentry {
+            ptr,
+            _phantom: marker::PhantomData,
+        }
+    }
+
+    /// The name of this entry, without a NUL terminator.
+    pub fn name(&self) -> &[u8] {
+        unsafe {
+            // Dentry names are always NUL-terminated.
+            let name = (*self.ptr).d_name.name;
+            let mut len = 0;
+            while *name.add(len) != 0 {
+                len += 1;
+            }
+            slice::from_raw_parts(name, len)
+        }
+    }
+
+    /// The inode this entry refers to, or `None` for a negative entry, which
+    /// records that the name doesn't exist.
+    pub fn inode(&self) -> Option<Inode> {
+        unsafe {
+            let inode = (*self.ptr).d_inode;
+            if inode.is_null() {
+                None
+            } else {
+                Some(Inode::from_ptr(inode))
+            }
+        }
+    }
+}
+
+/// The initial state of an inode created by `get_inode`.
+pub struct NewInode<I> {
+    /// The file type and permission bits.
+    pub mode: Mode,
+    pub uid: Kuid,
+    pub gid: Kgid,
+    pub nlink: u32,
+    pub size: i64,
+    /// The value that implements the inode's operations, which can later be
+    /// found with `Inode::data`. It's dropped when the inode is evicted from
+    /// the inode cache.
+    pub data: I,
+}
+
+// Stored in i_private, and freed by free_inode_data when the inode is
+// evicted.
+#[repr(C)]
+struct InodeData<I> {
+    type_id: TypeId,
+    drop: unsafe fn(*mut c_types::c_void),
+    data: I,
+}
+
+unsafe fn drop_inode_data<I>(data: *mut c_types::c_void) {
+    drop(Box::from_raw(data as *mut InodeData<I>));
+}
+
+// Frees the value stored in i_private by get_inode, if there is one. The
+// fields before `data` are at the same offsets whatever `I` is, so it can be
+// freed without knowing `I`.
+pub(crate) unsafe fn free_inode_data(inode: *mut bindings::inode) {
+    let data = (*inode).i_private as *mut InodeData<()>;
+    if !data.is_null() {
+        (*inode).i_private = ptr::null_mut();
+        ((*data).drop)(data as *mut c_types::c_void);
+    }
+}
+
+unsafe fn get_inode<I: InodeOperations, F: FileOperations + FileOpen>(
+    sb: *mut bindings::super_block,
+    ino: u64,
+    init: impl FnOnce() -> KernelResult<NewInode<I>>,
+) -> KernelResult<InodeRef> {
+    let inode = bindings::iget_locked(sb, ino as c_types::c_ulong);
+    if inode.is_null() {
+        return Err(Error::ENOMEM);
+    }
+    if (*inode).i_state & bindings::I_NEW as c_types::c_ulong == 0 {
+        return Ok(InodeRef::from_raw(inode));
+    }
+    let new = match init() {
+        Ok(new) => new,
+        Err(e) => {
+            bindings::iget_failed(inode);
+            return Err(e);
+        }
+    };
+    init_inode_helper(
+        inode,
+        new.mode.as_int(),
+        &InodeOperationsVtable::<I>::VTABLE,
+        &FileOperationsVtable::<F>::VTABLE,
+    );
+    (*inode).i_uid = new.uid.to_kernel();
+    (*inode).i_gid = new.gid.to_kernel();
+    bindings::set_nlink(inode, new.nlink);
+    (*inode).i_size = new.size;
+    let data = Box::new(InodeData {
+        type_id: TypeId::of::<I>(),
+        drop: drop_inode_data::<I>,
+        data: new.data,
+    });
+    (*inode).i_private = Box::into_raw(data) as *mut c_types::c_void;
+    bindings::unlock_new_inode(inode);
+    Ok(InodeRef::from_raw(inode))
+}
 
 pub struct Registration<T: FileSystem> {
     _phantom: marker::PhantomData<T>,
     ptr: Box<bindings::file_system_type>,
+    // The fs_parameter_spec array, ending with an empty entry, that
+    // `ptr.parameters` points to. Before 5.6 it's reached through an
+    // fs_parameter_description.
+    #[cfg(kernel_5_1_0_or_greater)]
+    _parameters: Vec<bindings::fs_parameter_spec>,
+    #[cfg(all(kernel_5_1_0_or_greater, not(kernel_5_6_0_or_greater)))]
+    _description: Box<bindings::fs_parameter_description>,
 }
 
 // This is safe because Registration doesn't actually expose any methods.
@@ -21,9 +301,535 @@ impl<T: FileSystem> Drop for Registration<T> {
     }
 }
 
-pub trait FileSystem: Sync {
+/// `FileSystem` corresponds to the kernel's `struct file_system_type`. Each
+/// mounted instance of the filesystem owns a value of the implementing type,
+/// which is created by `fill_super` and dropped when the superblock is
+/// destroyed. The same type provides the superblock's operations by
+/// implementing `SuperOperations`, which is required even when none of them
+/// are needed; an empty `impl SuperOperations for MyFS {}` keeps the
+/// defaults.
+pub trait FileSystem: SuperOperations + Send + Sync + Sized {
     const NAME: CStr<'static>;
     const FLAGS: FileSystemFlags;
+
+    /// The options accepted with `mount -o`. Use `()` for a filesystem that
+    /// doesn't take any.
+    type Options: MountOptions;
+
+    /// Sets up a newly allocated superblock, which must include creating its
+    /// root directory, and returns the state for this mount. `silent` is set
+    /// when errors shouldn't be logged, as when the kernel is probing for the
+    /// filesystem type.
+    fn fill_super(
+        sb: &mut SuperBlock<Self>,
+        options: &Self::Options,
+        silent: bool,
+    ) -> KernelResult<Self>;
+
+    /// Returns the options this mount is using. Remounting starts from these
+    /// and applies the options it was given on top, so options that aren't
+    /// given keep their values. By default, remounting starts from the
+    /// default options instead.
+    fn current_options(&self) -> Self::Options {
+        Self::Options::default()
+    }
+
+    /// Applies the options given when remounting with `mount -o remount`,
+    /// merged into the ones from `current_options`. Options are ignored by
+    /// default, which is what the kernel does for filesystems that don't
+    /// support remounting.
+    fn reconfigure(&self, _sb: &mut SuperBlock<Self>, _options: Self::Options) -> KernelResult<()> {
+        Ok(())
+    }
+}
+
+/// A type holding a filesystem's mount options, which are set one at a time
+/// from the comma-separated list given to `mount -o`. Mounting fails if any
+/// option is rejected.
+pub trait MountOptions: Default + Send + Sync + Sized {
+    /// The options this type accepts. On kernels with the `fs_context` mount
+    /// API (5.1 and later), these are registered as the filesystem's
+    /// `fs_parameter_spec`, so the kernel rejects and logs unknown options,
+    /// and options given with or without a value when they shouldn't be,
+    /// before `parse_option` sees them.
+    const PARAMETERS: &'static [MountParameter];
+
+    /// Applies one option, given as `name` or `name=value`. Unrecognized
+    /// options should be rejected with `EINVAL`. Values can be parsed with
+    /// `OptionValue`.
+    fn parse_option(&mut self, name: &str, value: Option<&str>) -> KernelResult<()>;
+}
+
+/// One of the options in `MountOptions::PARAMETERS`.
+// Only the fs_context mount API reads these.
+#[cfg_attr(not(kernel_5_1_0_or_greater), allow(dead_code))]
+pub struct MountParameter {
+    name: CStr<'static>,
+    flag: bool,
+}
+
+impl MountParameter {
+    /// An option given without a value, like `verbose`.
+    pub const fn flag(name: CStr<'static>) -> MountParameter {
+        MountParameter { name, flag: true }
+    }
+
+    /// An option given with a value, like `mode=755`. The value is passed to
+    /// `parse_option` as a string.
+    pub const fn value(name: CStr<'static>) -> MountParameter {
+        MountParameter { name, flag: false }
+    }
+}
+
+impl MountOptions for () {
+    const PARAMETERS: &'static [MountParameter] = &[];
+
+    fn parse_option(&mut self, _name: &str, _value: Option<&str>) -> KernelResult<()> {
+        Err(Error::EINVAL)
+    }
+}
+
+/// A type that can be the value of a mount option.
+pub trait OptionValue: Sized {
+    /// Parses the value of an option, which is `None` if the option was
+    /// given without `=`.
+    fn parse_value(value: Option<&str>) -> KernelResult<Self>;
+}
+
+/// A flag, which is set by giving the option without a value.
+impl OptionValue for bool {
+    fn parse_value(value: Option<&str>) -> KernelResult<Self> {
+        match value {
+            None => Ok(true),
+            Some(_) => Err(Error::EINVAL),
+        }
+    }
+}
+
+macro_rules! impl_integer_option_value {
+    ($($t:ty),*) => {
+        $(
+            /// An integer in decimal, or in hex with a `0x` prefix.
+            impl OptionValue for $t {
+                fn parse_value(value: Option<&str>) -> KernelResult<Self> {
+                    parse_integer(value.ok_or(Error::EINVAL)?.as_bytes())
+                }
+            }
+        )*
+    };
+}
+
+impl_integer_option_value!(i32, u32, i64, u64, usize);
+
+/// Permission bits in octal, like the `mode=` option of `tmpfs`.
+impl OptionValue for Mode {
+    fn parse_value(value: Option<&str>) -> KernelResult<Self> {
+        let mode =
+            u16::from_str_radix(value.ok_or(Error::EINVAL)?, 8).map_err(|_| Error::EINVAL)?;
+        if mode & !0o7777 != 0 {
+            return Err(Error::EINVAL);
+        }
+        Ok(Mode::from_int(mode))
+    }
+}
+
+impl OptionValue for String {
+    fn parse_value(value: Option<&str>) -> KernelResult<Self> {
+        Ok(value.ok_or(Error::EINVAL)?.into())
+    }
+}
+
+// Splits an option string from the legacy mount API, which is passed to the
+// filesystem as a single string.
+#[cfg(not(kernel_5_4_0_or_greater))]
+unsafe fn parse_options<O: MountOptions>(
+    options: &mut O,
+    data: *const c_types::c_char,
+) -> KernelResult<()> {
+    if data.is_null() {
+        return Ok(());
+    }
+    let data = c_str_to_str(data)?;
+    for option in data.split(',') {
+        if option.is_empty() {
+            continue;
+        }
+        let mut parts = option.splitn(2, '=');
+        let name = parts.next().unwrap_or_default();
+        options.parse_option(name, parts.next())?;
+    }
+    Ok(())
+}
+
+unsafe fn c_str_to_str<'a>(s: *const c_types::c_char) -> KernelResult<&'a str> {
+    let mut len = 0;
+    while *s.add(len) != 0 {
+        len += 1;
+    }
+    str::from_utf8(slice::from_raw_parts(s as *const u8, len)).map_err(|_| Error::EINVAL)
+}
+
+/// A kernel `struct super_block` belonging to a filesystem of type `T`.
+pub struct SuperBlock<T: FileSystem> {
+    ptr: *mut bindings::super_block,
+    _phantom: marker::PhantomData<T>,
+}
+
+extern "C" {
+    fn new_inode_helper(
+        sb: *mut bindings::super_block,
+        mode: bindings::umode_t,
+        iops: *const bindings::inode_operations,
+        fops: *const bindings::file_operations,
+    ) -> *mut bindings::inode;
+    fn init_inode_helper(
+        inode: *mut bindings::inode,
+        mode: bindings::umode_t,
+        iops: *const bindings::inode_operations,
+        fops: *const bindings::file_operations,
+    );
+    fn d_add_helper(dentry: *mut bindings::dentry, inode: *mut bindings::inode);
+}
+
+impl<T: FileSystem> SuperBlock<T> {
+    pub(crate) unsafe fn from_ptr(ptr: *mut bindings::super_block) -> SuperBlock<T> {
+        SuperBlock {
+            ptr,
+            _phantom: marker::PhantomData,
+        }
+    }
+
+    /// Returns the state created by `FileSystem::fill_super`, or `None`
+    /// while the superblock is still being filled in.
+    pub fn fs_info(&self) -> Option<&T> {
+        unsafe { fs_info(self.ptr) }
+    }
+
+    /// Sets the block size, which must be a power of two between 512 bytes
+    /// and the page size. For a filesystem on a block device, this is also
+    /// the unit the device is read in, and it can't be smaller than the
+    /// device's own block size.
+    pub fn set_block_size(&mut self, size: u32) -> KernelResult<()> {
+        let max = bindings::BINDINGS_PAGE_SIZE as u32;
+        if !size.is_power_of_two() || !(512..=max).contains(&size) {
+            return Err(Error::EINVAL);
+        }
+        unsafe {
+            if !(*self.ptr).s_bdev.is_null() {
+                if bindings::sb_set_blocksize(self.ptr, size as c_types::c_int) == 0 {
+                    return Err(Error::EINVAL);
+                }
+                return Ok(());
+            }
+            (*self.ptr).s_blocksize = size.into();
+            (*self.ptr).s_blocksize_bits = size.trailing_zeros() as u8;
+        }
+        Ok(())
+    }
+
+    pub fn block_size(&self) -> u32 {
+        unsafe { (*self.ptr).s_blocksize as u32 }
+    }
+
+    /// Returns the device the filesystem is mounted from, which is only set
+    /// for filesystems with `FileSystemFlags::REQUIRES_DEV`.
+    pub fn block_device(&self) -> Option<BlockDevice> {
+        unsafe { BlockDevice::from_super_block(self.ptr) }
+    }
+
+    /// Sets the magic number reported to userspace in `statfs(2)`'s
+    /// `f_type`.
+    pub fn set_magic(&mut self, magic: u64) {
+        unsafe { (*self.ptr).s_magic = magic as c_types::c_ulong };
+    }
+
+    pub fn magic(&self) -> u64 {
+        unsafe { (*self.ptr).s_magic as u64 }
+    }
+
+    /// Creates the root directory of the filesystem with the given
+    /// permission bits. The directory is empty and uses the kernel's simple
+    /// directory operations. This may only be done once.
+    pub fn create_root_dir(&mut self, mode: Mode) -> KernelResult<()> {
+        unsafe {
+            if !(*self.ptr).s_root.is_null() {
+                return Err(Error::EBUSY);
+            }
+            let inode = new_inode_helper(
+                self.ptr,
+                bindings::S_IFDIR as bindings::umode_t | (mode.as_int() & 0o7777),
+                &bindings::simple_dir_inode_operations,
+                &bindings::simple_dir_operations,
+            );
+            if inode.is_null() {
+                return Err(Error::ENOMEM);
+            }
+            // Directories start with a link from their parent and from ".".
+            bindings::inc_nlink(inode);
+            // d_make_root releases the inode if it fails.
+            let root = bindings::d_make_root(inode);
+            if root.is_null() {
+                return Err(Error::ENOMEM);
+            }
+            (*self.ptr).s_root = root;
+            (*super_info::<T>(self.ptr)).pinned_root = true;
+        }
+        Ok(())
+    }
+
+    /// Creates the root directory of the filesystem with the given
+    /// permission bits, along with all the files and directories in `tree`.
+    /// Nothing needs to be done to remove them again, as they are all freed
+    /// when the filesystem is unmounted.
+    pub fn create_root_tree(&mut self, mode: Mode, tree: &TreeDir) -> KernelResult<()> {
+        self.create_root_dir(mode)?;
+        // If this fails, whatever was created is cleaned up by kill_sb along
+        // with the root.
+        unsafe { populate((*self.ptr).s_root, tree) }
+    }
+
+    /// Returns the inode numbered `ino`. If it isn't already in the inode
+    /// cache, `init` is called to describe it, and `F` provides the
+    /// operations on files opened from it. Filesystems that keep their own
+    /// tree should create all of their inodes this way, and should always
+    /// use the same types for the same inode number.
+    pub fn get_inode<I: InodeOperations, F: FileOperations + FileOpen>(
+        &mut self,
+        ino: u64,
+        init: impl FnOnce() -> KernelResult<NewInode<I>>,
+    ) -> KernelResult<InodeRef> {
+        unsafe { get_inode::<I, F>(self.ptr, ino, init) }
+    }
+
+    /// Makes `root`, which must be a directory, the root of the filesystem.
+    /// This is the alternative to `create_root_dir` for filesystems that
+    /// implement their own directories.
+    pub fn set_root(&mut self, root: InodeRef) -> KernelResult<()> {
+        unsafe {
+            if !(*self.ptr).s_root.is_null() {
+                return Err(Error::EBUSY);
+            }
+            if u32::from(root.mode().as_int()) & bindings::S_IFMT != bindings::S_IFDIR {
+                return Err(Error::EINVAL);
+            }
+            // d_make_root releases the inode if it fails.
+            let root = bindings::d_make_root(root.into_raw());
+            if root.is_null() {
+                return Err(Error::ENOMEM);
+            }
+            (*self.ptr).s_root = root;
+        }
+        Ok(())
+    }
+}
+
+/// The block device a filesystem is mounted from, which is read in units of
+/// the filesystem's block size.
+pub struct BlockDevice<'a> {
+    sb: *mut bindings::super_block,
+    _phantom: marker::PhantomData<&'a bindings::super_block>,
+}
+
+extern "C" {
+    fn sb_bread_helper(
+        sb: *mut bindings::super_block,
+        block: bindings::sector_t,
+    ) -> *mut bindings::buffer_head;
+    fn bdev_nr_blocks_helper(sb: *mut bindings::super_block) -> bindings::sector_t;
+}
+
+impl BlockDevice<'_> {
+    unsafe fn from_super_block<'a>(sb: *mut bindings::super_block) -> Option<BlockDevice<'a>> {
+        if (*sb).s_bdev.is_null() {
+            return None;
+        }
+        Some(BlockDevice {
+            sb,
+            _phantom: marker::PhantomData,
+        })
+    }
+
+    pub fn block_size(&self) -> u32 {
+        unsafe { (*self.sb).s_blocksize as u32 }
+    }
+
+    /// The size of the device in blocks.
+    pub fn num_blocks(&self) -> u64 {
+        unsafe { bdev_nr_blocks_helper(self.sb) as u64 }
+    }
+
+    /// Reads block number `block`, or returns the copy already in the
+    /// buffer cache. Fails with `EIO` if the block is past the end of the
+    /// device or can't be read.
+    pub fn read(&self, block: u64) -> KernelResult<Buffer> {
+        if block >= self.num_blocks() {
+            return Err(Error::EIO);
+        }
+        let bh = unsafe { sb_bread_helper(self.sb, block as bindings::sector_t) };
+        if bh.is_null() {
+            return Err(Error::EIO);
+        }
+        Ok(Buffer { bh })
+    }
+}
+
+/// A block read with `BlockDevice::read`, corresponding to a kernel
+/// `struct buffer_head`. The block stays in the buffer cache at least until
+/// this is dropped.
+pub struct Buffer {
+    bh: *mut bindings::buffer_head,
+}
+
+impl Buffer {
+    /// The contents of the block.
+    pub fn data(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts((*self.bh).b_data as *const u8, (*self.bh).b_size) }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe { bindings::__brelse(self.bh) };
+    }
+}
+
+enum TreeEntry {
+    File {
+        name: CStr<'static>,
+        mode: bindings::umode_t,
+        fops: &'static bindings::file_operations,
+    },
+    Dir {
+        name: CStr<'static>,
+        mode: bindings::umode_t,
+        dir: TreeDir,
+    },
+}
+
+impl TreeEntry {
+    fn name(&self) -> &str {
+        let name = match self {
+            TreeEntry::File { name, .. } | TreeEntry::Dir { name, .. } => name,
+        };
+        // Leave out the NUL terminator.
+        &name[..name.len() - 1]
+    }
+}
+
+/// The contents of a directory in a filesystem whose files are all created
+/// when it's mounted, for use with `SuperBlock::create_root_tree`. This is
+/// the equivalent of the table passed to the kernel's `simple_fill_super`.
+pub struct TreeDir {
+    entries: Vec<TreeEntry>,
+}
+
+impl TreeDir {
+    pub fn new() -> TreeDir {
+        TreeDir { entries: vec![] }
+    }
+
+    /// Adds a regular file called `name` whose operations are provided by
+    /// `T`.
+    pub fn file<T: FileOperations + FileOpen>(self, name: CStr<'static>, mode: Mode) -> TreeDir {
+        self.add_file(name, mode, &FileOperationsVtable::<T>::VTABLE)
+    }
+
+    /// Adds a read-only file called `name` whose contents are produced by a
+    /// `SeqFile`.
+    pub fn seq_file<T: SeqFile + FileOpen>(self, name: CStr<'static>, mode: Mode) -> TreeDir {
+        self.add_file(name, mode, &SeqFileVtable::<T>::VTABLE)
+    }
+
+    /// Adds a read-only file called `name` whose contents are produced by a
+    /// `SingleSeqFile`.
+    pub fn single_seq_file<T: SingleSeqFile + FileOpen>(
+        self,
+        name: CStr<'static>,
+        mode: Mode,
+    ) -> TreeDir {
+        self.add_file(name, mode, &SingleSeqFileVtable::<T>::VTABLE)
+    }
+
+    /// Adds a subdirectory called `name`.
+    pub fn subdir(mut self, name: CStr<'static>, mode: Mode, dir: TreeDir) -> TreeDir {
+        self.entries.push(TreeEntry::Dir {
+            name,
+            mode: bindings::S_IFDIR as bindings::umode_t | (mode.as_int() & 0o7777),
+            dir,
+        });
+        self
+    }
+
+    fn add_file(
+        mut self,
+        name: CStr<'static>,
+        mode: Mode,
+        fops: &'static bindings::file_operations,
+    ) -> TreeDir {
+        self.entries.push(TreeEntry::File {
+            name,
+            mode: bindings::S_IFREG as bindings::umode_t | (mode.as_int() & 0o7777),
+            fops,
+        });
+        self
+    }
+
+    fn validate(&self) -> KernelResult<()> {
+        for (i, entry) in self.entries.iter().enumerate() {
+            let name = entry.name();
+            if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+                return Err(Error::EINVAL);
+            }
+            if self.entries[..i].iter().any(|e| e.name() == name) {
+                return Err(Error::EINVAL);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for TreeDir {
+    fn default() -> TreeDir {
+        TreeDir::new()
+    }
+}
+
+/// Adds the entries of `dir` to the directory `parent`. Each new dentry keeps
+/// the reference from `d_alloc_name`, which pins it in the dcache until
+/// `kill_sb_callback` drops it.
+unsafe fn populate(parent: *mut bindings::dentry, dir: &TreeDir) -> KernelResult<()> {
+    dir.validate()?;
+    for entry in dir.entries.iter() {
+        let (name, mode, iops, fops) = match entry {
+            TreeEntry::File { name, mode, fops } => (name, *mode, ptr::null(), *fops),
+            TreeEntry::Dir { name, mode, .. } => (
+                name,
+                *mode,
+                &bindings::simple_dir_inode_operations as *const _,
+                &bindings::simple_dir_operations as *const _,
+            ),
+        };
+        let dentry = bindings::d_alloc_name(parent, name.as_ptr() as *const c_types::c_char);
+        if dentry.is_null() {
+            return Err(Error::ENOMEM);
+        }
+        let inode = new_inode_helper((*parent).d_sb, mode, iops, fops);
+        if inode.is_null() {
+            bindings::dput(dentry);
+            return Err(Error::ENOMEM);
+        }
+        if let TreeEntry::Dir { .. } = entry {
+            // One link from the new directory's "." and one from its entry in
+            // the parent, which also gains a link from "..".
+            bindings::inc_nlink(inode);
+            bindings::inc_nlink((*parent).d_inode);
+        }
+        d_add_helper(dentry, inode);
+        if let TreeEntry::Dir { dir, .. } = entry {
+            populate(dentry, dir)?;
+        }
+    }
+    Ok(())
 }
 
 bitflags::bitflags! {
@@ -36,38 +842,342 @@ bitflags::bitflags! {
     }
 }
 
+// What s_fs_info points to. It's allocated before FileSystem::fill_super is
+// called, so that kill_sb_callback can clean up after create_root_dir even
+// if mounting fails.
+struct SuperInfo<T> {
+    // Set by create_root_dir, whose dentries are pinned until they're
+    // dropped with d_genocide.
+    pinned_root: bool,
+    fs: Option<T>,
+}
+
+unsafe fn super_info<T>(sb: *mut bindings::super_block) -> *mut SuperInfo<T> {
+    (*sb).s_fs_info as *mut SuperInfo<T>
+}
+
+// Returns the state of the mount, which is missing while the superblock is
+// being set up and if that failed.
+pub(crate) unsafe fn fs_info<'a, T>(sb: *mut bindings::super_block) -> Option<&'a T> {
+    super_info::<T>(sb).as_ref()?.fs.as_ref()
+}
+
+unsafe fn fill_super<T: FileSystem>(
+    sb: *mut bindings::super_block,
+    options: &T::Options,
+    silent: bool,
+) -> KernelResult<()> {
+    (*sb).s_time_gran = 1;
+    (*sb).s_op = &SuperOperationsVtable::<T>::VTABLE;
+    // kill_sb_callback frees this, including when mounting fails.
+    let info = Box::new(SuperInfo::<T> {
+        pinned_root: false,
+        fs: None,
+    });
+    (*sb).s_fs_info = Box::into_raw(info) as *mut c_types::c_void;
+    let mut super_block = SuperBlock::<T>::from_ptr(sb);
+    let fs = T::fill_super(&mut super_block, options, silent)?;
+    if (*sb).s_root.is_null() {
+        return Err(Error::EINVAL);
+    }
+    (*super_info::<T>(sb)).fs = Some(fs);
+    Ok(())
+}
+
+// Returns the options a remount starts from.
+unsafe fn current_options<T: FileSystem>(sb: *mut bindings::super_block) -> T::Options {
+    match fs_info::<T>(sb) {
+        Some(fs) => fs.current_options(),
+        None => T::Options::default(),
+    }
+}
+
+unsafe fn reconfigure<T: FileSystem>(
+    sb: *mut bindings::super_block,
+    options: T::Options,
+) -> KernelResult<()> {
+    let fs = fs_info::<T>(sb).ok_or(Error::EINVAL)?;
+    fs.reconfigure(&mut SuperBlock::from_ptr(sb), options)
+}
+
+extern "C" fn kill_sb_callback<T: FileSystem>(sb: *mut bindings::super_block) {
+    unsafe {
+        let info = super_info::<T>(sb);
+        // Drop the references pinning the dentries made by create_root_tree,
+        // as kill_litter_super does. This mustn't be done for filesystems
+        // that set their own root, whose dentries aren't pinned.
+        let root = (*sb).s_root;
+        if !info.is_null() && (*info).pinned_root && !root.is_null() {
+            bindings::d_genocide(root);
+        }
+        if T::FLAGS.contains(FileSystemFlags::REQUIRES_DEV) {
+            bindings::kill_block_super(sb);
+        } else {
+            bindings::kill_anon_super(sb);
+        }
+        if !info.is_null() {
+            drop(Box::from_raw(info));
+        }
+    }
+}
+
+// The legacy mount API, which passes the options as a single string. It's
+// used before 5.1, and before 5.4 for filesystems that need a block device,
+// since get_tree_bdev doesn't exist yet.
+
+#[cfg(not(kernel_5_4_0_or_greater))]
 extern "C" fn fill_super_callback<T: FileSystem>(
-    _sb: *mut bindings::super_block,
-    _data: *mut c_types::c_void,
-    _silent: c_types::c_int,
+    sb: *mut bindings::super_block,
+    data: *mut c_types::c_void,
+    silent: c_types::c_int,
 ) -> c_types::c_int {
-    // T::fill_super(...)
-    // This should actually create an object that gets dropped by
-    // file_system_registration::kill_sb. You can point to it with
-    // sb->s_fs_info.
-    unimplemented!();
+    error::from_kernel_result(|| unsafe {
+        let mut options = T::Options::default();
+        parse_options(&mut options, data as *const c_types::c_char)?;
+        fill_super::<T>(sb, &options, silent != 0)?;
+        Ok(0)
+    })
 }
 
+#[cfg(not(kernel_5_4_0_or_greater))]
 extern "C" fn mount_callback<T: FileSystem>(
     fs_type: *mut bindings::file_system_type,
     flags: c_types::c_int,
-    _dev_name: *const c_types::c_char,
+    dev_name: *const c_types::c_char,
     data: *mut c_types::c_void,
 ) -> *mut bindings::dentry {
-    unsafe { bindings::mount_nodev(fs_type, flags, data, Some(fill_super_callback::<T>)) }
+    unsafe {
+        if T::FLAGS.contains(FileSystemFlags::REQUIRES_DEV) {
+            bindings::mount_bdev(
+                fs_type,
+                flags,
+                dev_name,
+                data,
+                Some(fill_super_callback::<T>),
+            )
+        } else {
+            bindings::mount_nodev(fs_type, flags, data, Some(fill_super_callback::<T>))
+        }
+    }
+}
+
+#[cfg(not(kernel_5_4_0_or_greater))]
+pub(crate) unsafe extern "C" fn remount_callback<T: FileSystem>(
+    sb: *mut bindings::super_block,
+    _flags: *mut c_types::c_int,
+    data: *mut c_types::c_char,
+) -> c_types::c_int {
+    error::from_kernel_result(|| {
+        let mut options = current_options::<T>(sb);
+        parse_options(&mut options, data)?;
+        reconfigure::<T>(sb, options)?;
+        Ok(0)
+    })
+}
+
+// The fs_context mount API, which passes the options one at a time. The
+// options being built up are kept in fs_private.
+
+#[cfg(kernel_5_1_0_or_greater)]
+extern "C" {
+    fn fs_parameter_spec_init_helper(
+        spec: *mut bindings::fs_parameter_spec,
+        name: *const c_types::c_char,
+        flag: bool,
+    );
+    #[cfg(not(kernel_5_6_0_or_greater))]
+    fn fs_parameter_description_init_helper(
+        desc: *mut bindings::fs_parameter_description,
+        name: *const c_types::c_char,
+        specs: *const bindings::fs_parameter_spec,
+    );
+    fn fs_parse_helper(
+        fc: *mut bindings::fs_context,
+        param: *mut bindings::fs_parameter,
+    ) -> c_types::c_int;
+    fn get_tree_nodev_helper(
+        fc: *mut bindings::fs_context,
+        fill_super: Option<
+            unsafe extern "C" fn(
+                sb: *mut bindings::super_block,
+                fc: *mut bindings::fs_context,
+            ) -> c_types::c_int,
+        >,
+    ) -> c_types::c_int;
+}
+
+// Builds the fs_parameter_spec array for O, which ends with an empty entry.
+#[cfg(kernel_5_1_0_or_greater)]
+fn parameter_specs<O: MountOptions>() -> Vec<bindings::fs_parameter_spec> {
+    let mut specs = Vec::with_capacity(O::PARAMETERS.len() + 1);
+    for parameter in O::PARAMETERS {
+        let mut spec = bindings::fs_parameter_spec::default();
+        unsafe {
+            fs_parameter_spec_init_helper(
+                &mut spec,
+                parameter.name.as_ptr() as *const c_types::c_char,
+                parameter.flag,
+            )
+        };
+        specs.push(spec);
+    }
+    specs.push(bindings::fs_parameter_spec::default());
+    specs
+}
+
+// get_tree_bdev only exists from 5.4, so before that filesystems that need a
+// block device are mounted with the legacy API.
+#[cfg(kernel_5_1_0_or_greater)]
+fn uses_fs_context<T: FileSystem>() -> bool {
+    cfg!(kernel_5_4_0_or_greater) || !T::FLAGS.contains(FileSystemFlags::REQUIRES_DEV)
+}
+
+#[cfg(kernel_5_1_0_or_greater)]
+unsafe fn context_options<'a, T: FileSystem>(fc: *mut bindings::fs_context) -> &'a mut T::Options {
+    &mut *((*fc).fs_private as *mut T::Options)
+}
+
+#[cfg(kernel_5_1_0_or_greater)]
+unsafe extern "C" fn init_fs_context_callback<T: FileSystem>(
+    fc: *mut bindings::fs_context,
+) -> c_types::c_int {
+    let options = if (*fc).purpose() == bindings::fs_context_purpose_FS_CONTEXT_FOR_RECONFIGURE {
+        Box::new(current_options::<T>((*(*fc).root).d_sb))
+    } else {
+        Box::new(T::Options::default())
+    };
+    (*fc).fs_private = Box::into_raw(options) as *mut c_types::c_void;
+    (*fc).ops = &FsContextOperationsVtable::<T>::VTABLE;
+    0
+}
+
+#[cfg(kernel_5_1_0_or_greater)]
+unsafe extern "C" fn free_callback<T: FileSystem>(fc: *mut bindings::fs_context) {
+    let options = (*fc).fs_private as *mut T::Options;
+    if !options.is_null() {
+        drop(Box::from_raw(options));
+    }
+}
+
+// Each option is first checked against the fs_parameter_spec built from
+// MountOptions::PARAMETERS, which gives ENOPARAM for options that aren't in
+// it, leaving the device name in "source" and unknown options to the VFS.
+// The value is then handed to MountOptions::parse_option as a string, just
+// like an option split out of the legacy API's string, so that OptionValue
+// does the parsing with either API.
+#[cfg(kernel_5_1_0_or_greater)]
+unsafe extern "C" fn parse_param_callback<T: FileSystem>(
+    fc: *mut bindings::fs_context,
+    param: *mut bindings::fs_parameter,
+) -> c_types::c_int {
+    error::from_kernel_result(|| {
+        let rc = fs_parse_helper(fc, param);
+        if rc < 0 {
+            return Err(Error::from_kernel_errno(rc));
+        }
+        let name = c_str_to_str((*param).key)?;
+        let value = match (*param).type_ {
+            bindings::fs_value_type_fs_value_is_flag => None,
+            bindings::fs_value_type_fs_value_is_string => {
+                Some(c_str_to_str((*param).__bindgen_anon_1.string)?)
+            }
+            _ => return Err(Error::EINVAL),
+        };
+        context_options::<T>(fc).parse_option(name, value)?;
+        Ok(0)
+    })
+}
+
+#[cfg(kernel_5_1_0_or_greater)]
+unsafe extern "C" fn fill_super_fc_callback<T: FileSystem>(
+    sb: *mut bindings::super_block,
+    fc: *mut bindings::fs_context,
+) -> c_types::c_int {
+    error::from_kernel_result(|| {
+        let silent = (*fc).sb_flags & bindings::SB_SILENT != 0;
+        fill_super::<T>(sb, context_options::<T>(fc), silent)?;
+        Ok(0)
+    })
+}
+
+#[cfg(kernel_5_1_0_or_greater)]
+unsafe extern "C" fn get_tree_callback<T: FileSystem>(
+    fc: *mut bindings::fs_context,
+) -> c_types::c_int {
+    #[cfg(kernel_5_4_0_or_greater)]
+    {
+        if T::FLAGS.contains(FileSystemFlags::REQUIRES_DEV) {
+            return bindings::get_tree_bdev(fc, Some(fill_super_fc_callback::<T>));
+        }
+    }
+    get_tree_nodev_helper(fc, Some(fill_super_fc_callback::<T>))
+}
+
+#[cfg(kernel_5_1_0_or_greater)]
+unsafe extern "C" fn reconfigure_callback<T: FileSystem>(
+    fc: *mut bindings::fs_context,
+) -> c_types::c_int {
+    error::from_kernel_result(|| {
+        let options = core::mem::take(context_options::<T>(fc));
+        reconfigure::<T>((*(*fc).root).d_sb, options)?;
+        Ok(0)
+    })
+}
+
+#[cfg(kernel_5_1_0_or_greater)]
+struct FsContextOperationsVtable<T>(marker::PhantomData<T>);
+
+#[cfg(kernel_5_1_0_or_greater)]
+impl<T: FileSystem> FsContextOperationsVtable<T> {
+    const VTABLE: bindings::fs_context_operations = bindings::fs_context_operations {
+        free: Some(free_callback::<T>),
+        parse_param: Some(parse_param_callback::<T>),
+        get_tree: Some(get_tree_callback::<T>),
+        reconfigure: Some(reconfigure_callback::<T>),
+        ..empty_vtable!(bindings::fs_context_operations)
+    };
 }
 
 pub fn register<T: FileSystem>() -> error::KernelResult<Registration<T>> {
+    #[cfg(kernel_5_1_0_or_greater)]
+    let parameters = parameter_specs::<T::Options>();
+    #[cfg(all(kernel_5_1_0_or_greater, not(kernel_5_6_0_or_greater)))]
+    let description = {
+        let mut description = Box::new(bindings::fs_parameter_description::default());
+        unsafe {
+            fs_parameter_description_init_helper(
+                &mut *description,
+                T::NAME.as_ptr() as *const c_types::c_char,
+                parameters.as_ptr(),
+            )
+        };
+        description
+    };
     let mut fs_registration = Registration {
         ptr: Box::new(bindings::file_system_type {
             name: T::NAME.as_ptr() as *const i8,
             owner: unsafe { &mut bindings::__this_module },
             fs_flags: T::FLAGS.bits(),
+            #[cfg(kernel_5_6_0_or_greater)]
+            parameters: parameters.as_ptr(),
+            #[cfg(all(kernel_5_1_0_or_greater, not(kernel_5_6_0_or_greater)))]
+            parameters: &*description,
+            #[cfg(kernel_5_1_0_or_greater)]
+            init_fs_context: if uses_fs_context::<T>() {
+                Some(init_fs_context_callback::<T>)
+            } else {
+                None
+            },
+            #[cfg(not(kernel_5_4_0_or_greater))]
             mount: Some(mount_callback::<T>),
-            kill_sb: Some(bindings::kill_litter_super),
+            kill_sb: Some(kill_sb_callback::<T>),
 
             ..Default::default()
         }),
+        #[cfg(kernel_5_1_0_or_greater)]
+        _parameters: parameters,
+        #[cfg(all(kernel_5_1_0_or_greater, not(kernel_5_6_0_or_greater)))]
+        _description: description,
         _phantom: marker::PhantomData,
     };
     let result = unsafe { bindings::register_filesystem(&mut *fs_registration.ptr) };
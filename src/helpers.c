@@ -1,7 +1,20 @@
 #include <linux/bug.h>
+#include <linux/buffer_head.h>
+#include <linux/cred.h>
+#include <linux/device.h>
+#include <linux/fs.h>
+#include <linux/mutex.h>
+#include <linux/nsproxy.h>
 #include <linux/printk.h>
+#include <linux/sched.h>
+#include <linux/slab.h>
 #include <linux/uaccess.h>
+#include <linux/uio.h>
 #include <linux/version.h>
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(5, 1, 0)
+#include <linux/fs_parser.h>
+#endif
+#include <net/netns/generic.h>
 
 
 void bug_helper(void)
@@ -18,6 +31,268 @@ int access_ok_helper(const void __user *addr, unsigned long n)
 #endif
 }
 
+size_t copy_to_iter_helper(const void *addr, size_t bytes, struct iov_iter *i)
+{
+    return copy_to_iter(addr, bytes, i);
+}
+
+size_t copy_from_iter_helper(void *addr, size_t bytes, struct iov_iter *i)
+{
+    return copy_from_iter(addr, bytes, i);
+}
+
+int locks_lock_file_wait_helper(struct file *filp, struct file_lock *fl)
+{
+    return locks_lock_file_wait(filp, fl);
+}
+
+struct class *class_create_helper(struct module *owner, const char *name)
+{
+    return class_create(owner, name);
+}
+
+static void device_release_helper(struct device *dev)
+{
+    kfree(dev);
+}
+
+/*
+ * Like device_create(), but also sets the device type so that its devnode
+ * callback can pick the owner of the node, which the class devnode can't.
+ */
+struct device *device_create_with_type_helper(struct class *class,
+                                              const struct device_type *type,
+                                              dev_t devt, const char *name,
+                                              unsigned int index)
+{
+    struct device *dev;
+    int rc;
+
+    dev = kzalloc(sizeof(*dev), GFP_KERNEL);
+    if (!dev)
+        return ERR_PTR(-ENOMEM);
+
+    device_initialize(dev);
+    dev->devt = devt;
+    dev->class = class;
+    dev->type = type;
+    dev->release = device_release_helper;
+
+    rc = dev_set_name(dev, "%s%u", name, index);
+    if (!rc)
+        rc = device_add(dev);
+    if (rc) {
+        put_device(dev);
+        return ERR_PTR(rc);
+    }
+    return dev;
+}
+
+void mutex_init_helper(struct mutex *lock, const char *name,
+                       struct lock_class_key *key)
+{
+    __mutex_init(lock, name, key);
+}
+
+void mutex_lock_helper(struct mutex *lock)
+{
+    mutex_lock(lock);
+}
+
+void mutex_lock_nested_helper(struct mutex *lock, unsigned int subclass)
+{
+    mutex_lock_nested(lock, subclass);
+}
+
+void *net_generic_helper(const struct net *net, unsigned int id)
+{
+    return net_generic(net, id);
+}
+
+/* Returns NULL once the task has dropped its namespaces while exiting. */
+struct net *current_net_helper(void)
+{
+    struct nsproxy *nsproxy = current->nsproxy;
+
+    return nsproxy ? nsproxy->net_ns : NULL;
+}
+
+static void set_current_times(struct inode *inode, bool atime)
+{
+    /* This is a struct timespec before 4.18 and a timespec64 since. */
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(4, 9, 0) /* v4.9-rc1~11 */
+    typeof(inode->i_mtime) now = current_time(inode);
+#else
+    typeof(inode->i_mtime) now = CURRENT_TIME;
+#endif
+
+    if (atime)
+        inode->i_atime = now;
+    inode->i_mtime = inode->i_ctime = now;
+}
+
+void init_inode_helper(struct inode *inode, umode_t mode,
+                       const struct inode_operations *iops,
+                       const struct file_operations *fops)
+{
+    inode->i_mode = mode;
+    set_current_times(inode, true);
+    if (iops)
+        inode->i_op = iops;
+    if (fops)
+        inode->i_fop = fops;
+}
+
+struct inode *new_inode_helper(struct super_block *sb, umode_t mode,
+                               const struct inode_operations *iops,
+                               const struct file_operations *fops)
+{
+    struct inode *inode = new_inode(sb);
+
+    if (!inode)
+        return NULL;
+    inode->i_ino = get_next_ino();
+    init_inode_helper(inode, mode, iops, fops);
+    return inode;
+}
+
+void touch_inode_helper(struct inode *inode)
+{
+    set_current_times(inode, false);
+}
+
+loff_t i_size_read_helper(const struct inode *inode)
+{
+    return i_size_read(inode);
+}
+
+void i_size_write_helper(struct inode *inode, loff_t size)
+{
+    i_size_write(inode, size);
+}
+
+void inode_lock_helper(struct inode *inode)
+{
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(4, 5, 0)
+    inode_lock(inode);
+#else
+    mutex_lock(&inode->i_mutex);
+#endif
+}
+
+void inode_unlock_helper(struct inode *inode)
+{
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(4, 5, 0)
+    inode_unlock(inode);
+#else
+    mutex_unlock(&inode->i_mutex);
+#endif
+}
+
+void d_add_helper(struct dentry *dentry, struct inode *inode)
+{
+    d_add(dentry, inode);
+}
+
+const struct cred *current_cred_helper(void)
+{
+    return current_cred();
+}
+
+bool dir_emit_helper(struct dir_context *ctx, const char *name, int namelen,
+                     u64 ino, unsigned type)
+{
+    return dir_emit(ctx, name, namelen, ino, type);
+}
+
+bool dir_emit_dots_helper(struct file *file, struct dir_context *ctx)
+{
+    return dir_emit_dots(file, ctx);
+}
+
+/*
+ * None of our filesystems set FS_ALLOW_IDMAP, so they are only ever mounted
+ * with the initial user namespace as the mount's namespace.
+ */
+int setattr_prepare_helper(struct dentry *dentry, struct iattr *attr)
+{
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(5, 12, 0)
+    return setattr_prepare(&init_user_ns, dentry, attr);
+#elif LINUX_VERSION_CODE >= KERNEL_VERSION(4, 9, 0)
+    return setattr_prepare(dentry, attr);
+#else
+    return inode_change_ok(d_inode(dentry), attr);
+#endif
+}
+
+void setattr_copy_helper(struct inode *inode, const struct iattr *attr)
+{
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(5, 12, 0)
+    setattr_copy(&init_user_ns, inode, attr);
+#else
+    setattr_copy(inode, attr);
+#endif
+}
+
+void generic_fillattr_helper(struct inode *inode, struct kstat *stat)
+{
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(5, 12, 0)
+    generic_fillattr(&init_user_ns, inode, stat);
+#else
+    generic_fillattr(inode, stat);
+#endif
+}
+
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(5, 1, 0)
+void fs_parameter_spec_init_helper(struct fs_parameter_spec *spec,
+                                   const char *name, bool flag)
+{
+    /* Options are looked up by name again in parse_param, so opt is unused. */
+    if (flag)
+        *spec = (struct fs_parameter_spec)fsparam_flag(name, 0);
+    else
+        *spec = (struct fs_parameter_spec)fsparam_string(name, 0);
+}
+
+#if LINUX_VERSION_CODE < KERNEL_VERSION(5, 6, 0)
+void fs_parameter_description_init_helper(
+    struct fs_parameter_description *desc, const char *name,
+    const struct fs_parameter_spec *specs)
+{
+    strscpy((char *)desc->name, name, sizeof(desc->name));
+    desc->specs = specs;
+}
+#endif
+
+int fs_parse_helper(struct fs_context *fc, struct fs_parameter *param)
+{
+    struct fs_parse_result result;
+
+    return fs_parse(fc, fc->fs_type->parameters, param, &result);
+}
+
+int get_tree_nodev_helper(struct fs_context *fc,
+                          int (*fill_super)(struct super_block *sb,
+                                            struct fs_context *fc))
+{
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(5, 3, 0)
+    return get_tree_nodev(fc, fill_super);
+#else
+    return vfs_get_super(fc, vfs_get_independent_super, fill_super);
+#endif
+}
+#endif
+
+struct buffer_head *sb_bread_helper(struct super_block *sb, sector_t block)
+{
+    return sb_bread(sb, block);
+}
+
+sector_t bdev_nr_blocks_helper(struct super_block *sb)
+{
+    return i_size_read(sb->s_bdev->bd_inode) >> sb->s_blocksize_bits;
+}
+
 /* see https://github.com/rust-lang/rust-bindgen/issues/1671 */
 _Static_assert(__builtin_types_compatible_p(size_t, uintptr_t),
                "size_t must match uintptr_t, what architecture is this??");
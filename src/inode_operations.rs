@@ -0,0 +1,490 @@
+use core::marker;
+
+use crate::bindings;
+use crate::c_types;
+use crate::error::{from_kernel_result, to_kernel_err_ptr, Error, KernelResult};
+use crate::file_operations::Credentials;
+use crate::filesystem::{Dentry, Inode, InodeRef};
+use crate::types::{Kgid, Kuid, Mode};
+
+extern "C" {
+    fn current_cred_helper() -> *const bindings::cred;
+    fn touch_inode_helper(inode: *mut bindings::inode);
+    fn setattr_prepare_helper(
+        dentry: *mut bindings::dentry,
+        attr: *mut bindings::iattr,
+    ) -> c_types::c_int;
+    fn setattr_copy_helper(inode: *mut bindings::inode, attr: *const bindings::iattr);
+    fn generic_fillattr_helper(inode: *mut bindings::inode, stat: *mut bindings::kstat);
+}
+
+/// A borrowed reference to a kernel `struct iattr`, describing the changes
+/// requested by `chmod(2)`, `chown(2)`, `truncate(2)` and friends. Each
+/// accessor returns `None` if that attribute isn't being changed.
+pub struct Attr<'a> {
+    ptr: *const bindings::iattr,
+    _phantom: marker::PhantomData<&'a bindings::iattr>,
+}
+
+impl Attr<'_> {
+    unsafe fn from_ptr<'a>(ptr: *const bindings::iattr) -> Attr<'a> {
+        Attr {
+            ptr,
+            _phantom: marker::PhantomData,
+        }
+    }
+
+    fn is_set(&self, flag: u32) -> bool {
+        unsafe { (*self.ptr).ia_valid & flag != 0 }
+    }
+
+    pub fn mode(&self) -> Option<Mode> {
+        if self.is_set(bindings::ATTR_MODE) {
+            Some(Mode::from_int(unsafe { (*self.ptr).ia_mode }))
+        } else {
+            None
+        }
+    }
+
+    pub fn uid(&self) -> Option<Kuid> {
+        if self.is_set(bindings::ATTR_UID) {
+            Some(Kuid::from_kernel(unsafe { (*self.ptr).ia_uid }))
+        } else {
+            None
+        }
+    }
+
+    pub fn gid(&self) -> Option<Kgid> {
+        if self.is_set(bindings::ATTR_GID) {
+            Some(Kgid::from_kernel(unsafe { (*self.ptr).ia_gid }))
+        } else {
+            None
+        }
+    }
+
+    pub fn size(&self) -> Option<i64> {
+        if self.is_set(bindings::ATTR_SIZE) {
+            Some(unsafe { (*self.ptr).ia_size })
+        } else {
+            None
+        }
+    }
+}
+
+fn inode_data<'a, T: InodeOperations>(inode: &'a Inode) -> KernelResult<&'a T> {
+    inode.data::<T>().ok_or(Error::EINVAL)
+}
+
+unsafe fn lookup<T: InodeOperations>(
+    dir: *mut bindings::inode,
+    dentry: *mut bindings::dentry,
+) -> KernelResult<*mut bindings::inode> {
+    let lookup = T::LOOKUP.ok_or(Error::EINVAL)?;
+    let dentry = Dentry::from_ptr(dentry);
+    if dentry.name().len() > bindings::NAME_MAX as usize {
+        return Err(Error::ENAMETOOLONG);
+    }
+    let dir = Inode::from_ptr(dir);
+    match lookup(inode_data(&dir)?, &dir, &dentry)? {
+        Some(inode) => Ok(inode.into_raw()),
+        None => Ok(core::ptr::null_mut()),
+    }
+}
+
+unsafe extern "C" fn lookup_callback<T: InodeOperations>(
+    dir: *mut bindings::inode,
+    dentry: *mut bindings::dentry,
+    _flags: c_types::c_uint,
+) -> *mut bindings::dentry {
+    match lookup::<T>(dir, dentry) {
+        // A NULL inode adds a negative dentry, so that later lookups of the
+        // same name don't need to ask the filesystem again.
+        Ok(inode) => bindings::d_splice_alias(inode, dentry),
+        Err(e) => to_kernel_err_ptr(e),
+    }
+}
+
+// Shared by create and mkdir, which only differ in the file type and in the
+// link that a new directory's ".." adds to its parent.
+unsafe fn create<T: InodeOperations>(
+    create: CreateFn<T>,
+    dir: *mut bindings::inode,
+    dentry: *mut bindings::dentry,
+    mode: bindings::umode_t,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let create = create.ok_or(Error::EINVAL)?;
+        let dir_inode = Inode::from_ptr(dir);
+        let credentials = Credentials::from_ptr(current_cred_helper());
+        let inode = create(
+            inode_data(&dir_inode)?,
+            &dir_inode,
+            &Dentry::from_ptr(dentry),
+            Mode::from_int(mode),
+            &credentials,
+        )?;
+        if u32::from(mode) & bindings::S_IFMT == bindings::S_IFDIR {
+            bindings::inc_nlink(dir);
+        }
+        touch_inode_helper(dir);
+        bindings::d_instantiate(dentry, inode.into_raw());
+        Ok(0)
+    })
+}
+
+#[cfg(kernel_5_12_0_or_greater)]
+unsafe extern "C" fn create_callback<T: InodeOperations>(
+    _mnt_userns: *mut bindings::user_namespace,
+    dir: *mut bindings::inode,
+    dentry: *mut bindings::dentry,
+    mode: bindings::umode_t,
+    _excl: bool,
+) -> c_types::c_int {
+    create(T::CREATE, dir, dentry, mode)
+}
+
+#[cfg(not(kernel_5_12_0_or_greater))]
+unsafe extern "C" fn create_callback<T: InodeOperations>(
+    dir: *mut bindings::inode,
+    dentry: *mut bindings::dentry,
+    mode: bindings::umode_t,
+    _excl: bool,
+) -> c_types::c_int {
+    create(T::CREATE, dir, dentry, mode)
+}
+
+// Unlike create, mkdir isn't passed the file type bits.
+#[cfg(kernel_5_12_0_or_greater)]
+unsafe extern "C" fn mkdir_callback<T: InodeOperations>(
+    _mnt_userns: *mut bindings::user_namespace,
+    dir: *mut bindings::inode,
+    dentry: *mut bindings::dentry,
+    mode: bindings::umode_t,
+) -> c_types::c_int {
+    let mode = mode | bindings::S_IFDIR as bindings::umode_t;
+    create(T::MKDIR, dir, dentry, mode)
+}
+
+#[cfg(not(kernel_5_12_0_or_greater))]
+unsafe extern "C" fn mkdir_callback<T: InodeOperations>(
+    dir: *mut bindings::inode,
+    dentry: *mut bindings::dentry,
+    mode: bindings::umode_t,
+) -> c_types::c_int {
+    let mode = mode | bindings::S_IFDIR as bindings::umode_t;
+    create(T::MKDIR, dir, dentry, mode)
+}
+
+// Shared by unlink and rmdir. Only the caller's own entry is removed, so the
+// only links that change are the ones the entry itself provided.
+unsafe fn remove<T: InodeOperations>(
+    remove: UnlinkFn<T>,
+    dir: *mut bindings::inode,
+    dentry: *mut bindings::dentry,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let remove = remove.ok_or(Error::EINVAL)?;
+        let dir_inode = Inode::from_ptr(dir);
+        remove(
+            inode_data(&dir_inode)?,
+            &dir_inode,
+            &Dentry::from_ptr(dentry),
+        )?;
+        let inode = (*dentry).d_inode;
+        if u32::from((*inode).i_mode) & bindings::S_IFMT == bindings::S_IFDIR {
+            bindings::clear_nlink(inode);
+            bindings::drop_nlink(dir);
+        } else {
+            bindings::drop_nlink(inode);
+        }
+        touch_inode_helper(dir);
+        Ok(0)
+    })
+}
+
+unsafe extern "C" fn unlink_callback<T: InodeOperations>(
+    dir: *mut bindings::inode,
+    dentry: *mut bindings::dentry,
+) -> c_types::c_int {
+    remove(T::UNLINK, dir, dentry)
+}
+
+unsafe extern "C" fn rmdir_callback<T: InodeOperations>(
+    dir: *mut bindings::inode,
+    dentry: *mut bindings::dentry,
+) -> c_types::c_int {
+    remove(T::RMDIR, dir, dentry)
+}
+
+unsafe fn rename<T: InodeOperations>(
+    old_dir: *mut bindings::inode,
+    old_dentry: *mut bindings::dentry,
+    new_dir: *mut bindings::inode,
+    new_dentry: *mut bindings::dentry,
+    flags: c_types::c_uint,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        // RENAME_NOREPLACE is enforced by the VFS, but the other flags need
+        // support from the filesystem that we don't offer.
+        if flags & !bindings::RENAME_NOREPLACE != 0 {
+            return Err(Error::EINVAL);
+        }
+        let rename = T::RENAME.ok_or(Error::EINVAL)?;
+        let old_dir_inode = Inode::from_ptr(old_dir);
+        rename(
+            inode_data(&old_dir_inode)?,
+            &old_dir_inode,
+            &Dentry::from_ptr(old_dentry),
+            &Inode::from_ptr(new_dir),
+            &Dentry::from_ptr(new_dentry),
+        )?;
+        // The same link count changes as simple_rename.
+        let is_dir =
+            u32::from((*(*old_dentry).d_inode).i_mode) & bindings::S_IFMT == bindings::S_IFDIR;
+        let target = (*new_dentry).d_inode;
+        if !target.is_null() {
+            if is_dir {
+                bindings::clear_nlink(target);
+                bindings::drop_nlink(old_dir);
+            } else {
+                bindings::drop_nlink(target);
+            }
+        } else if is_dir {
+            bindings::drop_nlink(old_dir);
+            bindings::inc_nlink(new_dir);
+        }
+        touch_inode_helper(old_dir);
+        touch_inode_helper(new_dir);
+        Ok(0)
+    })
+}
+
+#[cfg(kernel_5_12_0_or_greater)]
+unsafe extern "C" fn rename_callback<T: InodeOperations>(
+    _mnt_userns: *mut bindings::user_namespace,
+    old_dir: *mut bindings::inode,
+    old_dentry: *mut bindings::dentry,
+    new_dir: *mut bindings::inode,
+    new_dentry: *mut bindings::dentry,
+    flags: c_types::c_uint,
+) -> c_types::c_int {
+    rename::<T>(old_dir, old_dentry, new_dir, new_dentry, flags)
+}
+
+#[cfg(all(kernel_4_9_0_or_greater, not(kernel_5_12_0_or_greater)))]
+unsafe extern "C" fn rename_callback<T: InodeOperations>(
+    old_dir: *mut bindings::inode,
+    old_dentry: *mut bindings::dentry,
+    new_dir: *mut bindings::inode,
+    new_dentry: *mut bindings::dentry,
+    flags: c_types::c_uint,
+) -> c_types::c_int {
+    rename::<T>(old_dir, old_dentry, new_dir, new_dentry, flags)
+}
+
+// Before 4.9, renames with flags went to a separate rename2 operation.
+#[cfg(not(kernel_4_9_0_or_greater))]
+unsafe extern "C" fn rename_callback<T: InodeOperations>(
+    old_dir: *mut bindings::inode,
+    old_dentry: *mut bindings::dentry,
+    new_dir: *mut bindings::inode,
+    new_dentry: *mut bindings::dentry,
+) -> c_types::c_int {
+    rename::<T>(old_dir, old_dentry, new_dir, new_dentry, 0)
+}
+
+unsafe fn setattr<T: InodeOperations>(
+    dentry: *mut bindings::dentry,
+    attr: *mut bindings::iattr,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        // Checks that the caller is allowed to make the change.
+        let rc = setattr_prepare_helper(dentry, attr);
+        if rc != 0 {
+            return Err(Error::from_kernel_errno(rc));
+        }
+        let setattr = T::SETATTR.ok_or(Error::EINVAL)?;
+        let inode = (*dentry).d_inode;
+        setattr(
+            inode_data(&Inode::from_ptr(inode))?,
+            &Dentry::from_ptr(dentry),
+            &Attr::from_ptr(attr),
+        )?;
+        if (*attr).ia_valid & bindings::ATTR_SIZE != 0 {
+            bindings::truncate_setsize(inode, (*attr).ia_size);
+        }
+        setattr_copy_helper(inode, attr);
+        Ok(0)
+    })
+}
+
+#[cfg(kernel_5_12_0_or_greater)]
+unsafe extern "C" fn setattr_callback<T: InodeOperations>(
+    _mnt_userns: *mut bindings::user_namespace,
+    dentry: *mut bindings::dentry,
+    attr: *mut bindings::iattr,
+) -> c_types::c_int {
+    setattr::<T>(dentry, attr)
+}
+
+#[cfg(not(kernel_5_12_0_or_greater))]
+unsafe extern "C" fn setattr_callback<T: InodeOperations>(
+    dentry: *mut bindings::dentry,
+    attr: *mut bindings::iattr,
+) -> c_types::c_int {
+    setattr::<T>(dentry, attr)
+}
+
+unsafe fn getattr<T: InodeOperations>(
+    dentry: *mut bindings::dentry,
+    stat: *mut bindings::kstat,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let getattr = T::GETATTR.ok_or(Error::EINVAL)?;
+        let inode = Inode::from_ptr((*dentry).d_inode);
+        getattr(inode_data(&inode)?, &inode)?;
+        generic_fillattr_helper((*dentry).d_inode, stat);
+        Ok(0)
+    })
+}
+
+#[cfg(kernel_5_12_0_or_greater)]
+unsafe extern "C" fn getattr_callback<T: InodeOperations>(
+    _mnt_userns: *mut bindings::user_namespace,
+    path: *const bindings::path,
+    stat: *mut bindings::kstat,
+    _request_mask: u32,
+    _query_flags: c_types::c_uint,
+) -> c_types::c_int {
+    getattr::<T>((*path).dentry, stat)
+}
+
+#[cfg(all(kernel_4_11_0_or_greater, not(kernel_5_12_0_or_greater)))]
+unsafe extern "C" fn getattr_callback<T: InodeOperations>(
+    path: *const bindings::path,
+    stat: *mut bindings::kstat,
+    _request_mask: u32,
+    _query_flags: c_types::c_uint,
+) -> c_types::c_int {
+    getattr::<T>((*path).dentry, stat)
+}
+
+#[cfg(not(kernel_4_11_0_or_greater))]
+unsafe extern "C" fn getattr_callback<T: InodeOperations>(
+    _mnt: *mut bindings::vfsmount,
+    dentry: *mut bindings::dentry,
+    stat: *mut bindings::kstat,
+) -> c_types::c_int {
+    getattr::<T>(dentry, stat)
+}
+
+pub(crate) struct InodeOperationsVtable<T>(marker::PhantomData<T>);
+
+impl<T: InodeOperations> InodeOperationsVtable<T> {
+    pub(crate) const VTABLE: bindings::inode_operations = bindings::inode_operations {
+        lookup: if let Some(_) = T::LOOKUP {
+            Some(lookup_callback::<T>)
+        } else {
+            None
+        },
+        create: if let Some(_) = T::CREATE {
+            Some(create_callback::<T>)
+        } else {
+            None
+        },
+        mkdir: if let Some(_) = T::MKDIR {
+            Some(mkdir_callback::<T>)
+        } else {
+            None
+        },
+        unlink: if let Some(_) = T::UNLINK {
+            Some(unlink_callback::<T>)
+        } else {
+            None
+        },
+        rmdir: if let Some(_) = T::RMDIR {
+            Some(rmdir_callback::<T>)
+        } else {
+            None
+        },
+        rename: if let Some(_) = T::RENAME {
+            Some(rename_callback::<T>)
+        } else {
+            None
+        },
+        setattr: if let Some(_) = T::SETATTR {
+            Some(setattr_callback::<T>)
+        } else {
+            None
+        },
+        getattr: if let Some(_) = T::GETATTR {
+            Some(getattr_callback::<T>)
+        } else {
+            None
+        },
+
+        ..empty_vtable!(bindings::inode_operations)
+    };
+}
+
+pub type LookupFn<T> = Option<fn(&T, &Inode, &Dentry) -> KernelResult<Option<InodeRef>>>;
+pub type CreateFn<T> =
+    Option<fn(&T, &Inode, &Dentry, Mode, &Credentials) -> KernelResult<InodeRef>>;
+pub type UnlinkFn<T> = Option<fn(&T, &Inode, &Dentry) -> KernelResult<()>>;
+pub type RenameFn<T> = Option<fn(&T, &Inode, &Dentry, &Inode, &Dentry) -> KernelResult<()>>;
+pub type SetattrFn<T> = Option<fn(&T, &Dentry, &Attr) -> KernelResult<()>>;
+pub type GetattrFn<T> = Option<fn(&T, &Inode) -> KernelResult<()>>;
+
+/// `InodeOperations` corresponds to the kernel's `struct inode_operations`.
+/// A value of the implementing type is stored in each inode created with
+/// `SuperBlock::get_inode`, and the operations on an inode are called on its
+/// value. Directory operations are called on the directory's value.
+///
+/// The directory operations only need to update the filesystem's own records.
+/// Adding the new inode to the dentry cache, adjusting link counts and
+/// updating the directory timestamps are all handled afterwards, the same way
+/// as the kernel's `simple_*` helpers do.
+pub trait InodeOperations: Send + Sync + Sized + 'static {
+    /// Looks up `dentry`'s name in the directory, returning `None` if it
+    /// doesn't exist. Corresponds to the `lookup` function pointer in
+    /// `struct inode_operations`.
+    const LOOKUP: LookupFn<Self> = None;
+
+    /// Creates a regular file for `dentry` in the directory, on behalf of the
+    /// task with the given credentials. The mode includes the file type.
+    /// Corresponds to the `create` function pointer in
+    /// `struct inode_operations`.
+    const CREATE: CreateFn<Self> = None;
+
+    /// Creates a directory for `dentry` in the directory, in the same way as
+    /// `CREATE`. Corresponds to the `mkdir` function pointer in
+    /// `struct inode_operations`.
+    const MKDIR: CreateFn<Self> = None;
+
+    /// Removes the non-directory `dentry` from the directory. Corresponds to
+    /// the `unlink` function pointer in `struct inode_operations`.
+    const UNLINK: UnlinkFn<Self> = None;
+
+    /// Removes the directory `dentry` from the directory, which should fail
+    /// with `ENOTEMPTY` unless it is empty. Corresponds to the `rmdir`
+    /// function pointer in `struct inode_operations`.
+    const RMDIR: UnlinkFn<Self> = None;
+
+    /// Moves the first `dentry` in the first directory to the second
+    /// `dentry` in the second directory, replacing what was there, which if
+    /// it's a directory should fail with `ENOTEMPTY` unless it is empty.
+    /// Corresponds to the `rename` function pointer in
+    /// `struct inode_operations`.
+    const RENAME: RenameFn<Self> = None;
+
+    /// Checks or applies changes to the inode's attributes. Permission checks
+    /// have already been done, and if it returns `Ok` the changes are copied
+    /// to the inode, including its size. Corresponds to the `setattr` function
+    /// pointer in `struct inode_operations`.
+    const SETATTR: SetattrFn<Self> = None;
+
+    /// Brings the inode's attributes up to date before they're reported by
+    /// `stat(2)`. Corresponds to the `getattr` function pointer in
+    /// `struct inode_operations`.
+    const GETATTR: GetattrFn<Self> = None;
+}
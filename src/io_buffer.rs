@@ -0,0 +1,50 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::error::KernelResult;
+
+/// A source of bytes for a write, such as a userspace buffer or an
+/// `iov_iter`. Like `UserSlicePtrReader`, it only permits forward reads, so
+/// each byte can be read at most once.
+pub trait IoBufferReader {
+    /// Returns the number of bytes left to be read. Note that even reading
+    /// less than this number of bytes may return an Error().
+    fn len(&self) -> usize;
+
+    /// Returns `true` if `self.len()` is 0.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads exactly `data.len()` bytes into `data`.
+    ///
+    /// Returns EFAULT if the source can't be read, or `data` is larger than
+    /// the remaining length.
+    fn read(&mut self, data: &mut [u8]) -> KernelResult<()>;
+
+    /// Reads all remaining data and returns it in a `Vec`.
+    fn read_all(&mut self) -> KernelResult<Vec<u8>> {
+        let mut data = vec![0; self.len()];
+        self.read(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// A destination for the bytes of a read, such as a userspace buffer or an
+/// `iov_iter`.
+pub trait IoBufferWriter {
+    /// Returns the number of bytes that can still be written.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if `self.len()` is 0.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes all of `data`.
+    ///
+    /// Returns EFAULT if the destination can't be written (in which case some
+    /// of `data` may have been written), or `data` is larger than the
+    /// remaining length (in which case nothing is written).
+    fn write(&mut self, data: &[u8]) -> KernelResult<()>;
+}
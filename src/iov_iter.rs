@@ -0,0 +1,80 @@
+use crate::bindings;
+use crate::c_types;
+use crate::error::{Error, KernelResult};
+use crate::io_buffer::{IoBufferReader, IoBufferWriter};
+
+extern "C" {
+    fn copy_to_iter_helper(
+        addr: *const c_types::c_void,
+        bytes: usize,
+        i: *mut bindings::iov_iter,
+    ) -> usize;
+    fn copy_from_iter_helper(
+        addr: *mut c_types::c_void,
+        bytes: usize,
+        i: *mut bindings::iov_iter,
+    ) -> usize;
+}
+
+/// A kernel `struct iov_iter`, which may refer to userspace memory, kernel
+/// memory or pipe buffers. Used to implement `read_iter`, `write_iter` and
+/// splicing on top of `IoBufferReader`/`IoBufferWriter`.
+pub struct IovIter {
+    ptr: *mut bindings::iov_iter,
+}
+
+impl IovIter {
+    pub(crate) unsafe fn from_ptr(ptr: *mut bindings::iov_iter) -> IovIter {
+        IovIter { ptr }
+    }
+
+    fn count(&self) -> usize {
+        unsafe { (*self.ptr).count }
+    }
+}
+
+impl IoBufferReader for IovIter {
+    fn len(&self) -> usize {
+        self.count()
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> KernelResult<()> {
+        if data.len() > self.count() {
+            return Err(Error::EFAULT);
+        }
+        let res = unsafe {
+            copy_from_iter_helper(
+                data.as_mut_ptr() as *mut c_types::c_void,
+                data.len(),
+                self.ptr,
+            )
+        };
+        if res != data.len() {
+            return Err(Error::EFAULT);
+        }
+        Ok(())
+    }
+}
+
+impl IoBufferWriter for IovIter {
+    fn len(&self) -> usize {
+        self.count()
+    }
+
+    fn write(&mut self, data: &[u8]) -> KernelResult<()> {
+        if data.len() > self.count() {
+            return Err(Error::EFAULT);
+        }
+        let res = unsafe {
+            copy_to_iter_helper(
+                data.as_ptr() as *const c_types::c_void,
+                data.len(),
+                self.ptr,
+            )
+        };
+        if res != data.len() {
+            return Err(Error::EFAULT);
+        }
+        Ok(())
+    }
+}
@@ -5,22 +5,49 @@ extern crate alloc;
 
 use core::panic::PanicInfo;
 
+// Produces a kernel vtable struct of type `$ty` with every operation unset,
+// to build vtables from with struct update syntax. This is for vtables whose
+// fields vary too much between kernel versions to list, which is fine because
+// every field is an optional function pointer or a flag that defaults to 0.
+macro_rules! empty_vtable {
+    ($ty:ty) => {{
+        union Zeroed {
+            bytes: [u8; core::mem::size_of::<$ty>()],
+            vtable: $ty,
+        }
+        unsafe {
+            Zeroed {
+                bytes: [0; core::mem::size_of::<$ty>()],
+            }
+            .vtable
+        }
+    }};
+}
+
 mod allocator;
 pub mod bindings;
 pub mod c_types;
 pub mod chrdev;
 mod error;
+pub mod file_lock;
 pub mod file_operations;
 pub mod filesystem;
+pub mod inode_operations;
+pub mod io_buffer;
+pub mod iov_iter;
+pub mod miscdev;
 pub mod printk;
 #[cfg(kernel_4_13_0_or_greater)]
 pub mod random;
+pub mod seq_file;
+pub mod super_operations;
+pub mod sync;
 pub mod sysctl;
 mod types;
 pub mod user_ptr;
 
 pub use crate::error::{Error, KernelResult};
-pub use crate::types::{CStr, Mode};
+pub use crate::types::{CStr, Kgid, Kuid, Mode};
 
 /// Declares the entrypoint for a kernel module. The first argument should be a type which
 /// implements the [`KernelModule`] trait. Also accepts various forms of kernel metadata.
@@ -0,0 +1,69 @@
+use core::marker;
+use core::mem;
+
+use alloc::boxed::Box;
+
+use crate::bindings;
+use crate::c_types;
+use crate::error::{Error, KernelResult};
+use crate::file_operations;
+use crate::types::{CStr, Mode};
+
+struct MiscDevice {
+    mdev: bindings::miscdevice,
+    fops: bindings::file_operations,
+}
+
+/// A misc device, which gets a dynamically allocated minor number under the
+/// shared misc major and a `/dev/<name>` node created by devtmpfs, without
+/// needing a chrdev region of its own. Corresponds to `misc_register`. The
+/// device is deregistered when this is dropped.
+pub struct Registration<T> {
+    // The kernel keeps pointers to both the miscdevice and its
+    // file_operations, so they must not move.
+    inner: Box<MiscDevice>,
+    _phantom: marker::PhantomData<T>,
+}
+
+impl<T: file_operations::FileOperations + file_operations::FileOpen> Registration<T> {
+    /// Registers a misc device whose node is called `name` and has
+    /// permissions `mode`.
+    pub fn register(name: CStr<'static>, mode: Mode) -> KernelResult<Registration<T>> {
+        let mut fops = file_operations::FileOperationsVtable::<T>::VTABLE;
+        // misc_open takes a reference on the owner of these, rather than of
+        // the misc device, to pin the module while the file is open.
+        fops.owner = unsafe { &mut bindings::__this_module };
+        let mut inner = Box::new(MiscDevice {
+            mdev: unsafe { mem::zeroed() },
+            fops,
+        });
+        inner.mdev.minor = bindings::MISC_DYNAMIC_MINOR as c_types::c_int;
+        inner.mdev.name = name.as_ptr() as *const c_types::c_char;
+        inner.mdev.mode = mode.as_int();
+        inner.mdev.fops = &inner.fops;
+
+        let rc = unsafe { bindings::misc_register(&mut inner.mdev) };
+        if rc != 0 {
+            return Err(Error::from_kernel_errno(rc));
+        }
+        Ok(Registration {
+            inner,
+            _phantom: marker::PhantomData,
+        })
+    }
+
+    /// The minor number allocated to the device.
+    pub fn minor(&self) -> u32 {
+        self.inner.mdev.minor as u32
+    }
+}
+
+// This is safe because Registration doesn't expose the kernel's mutable
+// state.
+unsafe impl<T> Sync for Registration<T> {}
+
+impl<T> Drop for Registration<T> {
+    fn drop(&mut self) {
+        unsafe { bindings::misc_deregister(&mut self.inner.mdev) };
+    }
+}
@@ -0,0 +1,399 @@
+use core::convert::TryInto;
+use core::{fmt, marker, mem, ptr};
+
+use alloc::boxed::Box;
+
+use crate::bindings;
+use crate::c_types;
+use crate::error::{from_kernel_err_ptr, from_kernel_result, Error, KernelResult};
+use crate::file_operations::{FileOpen, EMPTY_VTABLE};
+use crate::types::{CStr, Mode};
+
+/// The output buffer of a `struct seq_file`. Formatted output is appended with
+/// the `core::fmt::Write` methods, typically via `write!`.
+///
+/// If the output doesn't fit in the kernel's buffer, writes fail and the
+/// kernel retries the whole record with a larger buffer, so callers don't
+/// need to do anything special on error other than return it.
+pub struct SeqWriter {
+    ptr: *mut bindings::seq_file,
+}
+
+impl SeqWriter {
+    pub(crate) unsafe fn from_ptr(ptr: *mut bindings::seq_file) -> SeqWriter {
+        SeqWriter { ptr }
+    }
+
+    /// Appends raw bytes to the output.
+    pub fn write_bytes(&mut self, data: &[u8]) -> fmt::Result {
+        let res = unsafe {
+            bindings::seq_write(
+                self.ptr,
+                data.as_ptr() as *const c_types::c_void,
+                data.len(),
+            )
+        };
+        if res != 0 {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+
+    fn has_overflowed(&self) -> bool {
+        unsafe { (*self.ptr).count == (*self.ptr).size }
+    }
+}
+
+impl fmt::Write for SeqWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes())
+    }
+}
+
+/// `SeqFile` is a read-only file whose contents are a sequence of records,
+/// each rendered by `show`. The kernel's `seq_file` machinery takes care of
+/// buffering, partial reads and seeking, so implementations never deal with
+/// byte offsets. Corresponds to `struct seq_operations` opened with
+/// `seq_open`. Instances are created by `FileOpen`, once per `open`.
+pub trait SeqFile: Sync + Sized {
+    /// A cursor identifying one record.
+    type Item;
+
+    /// Returns the record at index `pos`, or `None` if there are no more
+    /// records. Corresponds to the `start` function pointer in
+    /// `struct seq_operations`.
+    fn start(&self, pos: u64) -> Option<Self::Item>;
+
+    /// Advances from `item` to the record at index `pos`, or returns `None`
+    /// at the end. Corresponds to the `next` function pointer in
+    /// `struct seq_operations`.
+    fn next(&self, item: Self::Item, pos: u64) -> Option<Self::Item> {
+        drop(item);
+        self.start(pos)
+    }
+
+    /// Called at the end of each batch of records, with the last cursor
+    /// returned by `start` or `next`. Use this to release anything acquired
+    /// in `start`. Corresponds to the `stop` function pointer in
+    /// `struct seq_operations`.
+    fn stop(&self, _item: Option<Self::Item>) {}
+
+    /// Writes the record for `item`. Corresponds to the `show` function
+    /// pointer in `struct seq_operations`.
+    fn show(&self, item: &Self::Item, m: &mut SeqWriter) -> KernelResult<()>;
+}
+
+/// `SingleSeqFile` is a read-only file whose entire contents are generated by
+/// a single call to `show`, for files that are small enough to render in one
+/// go. Corresponds to `single_open`. Instances are created by `FileOpen`,
+/// once per `open`.
+///
+/// Both kinds of file can be registered as character devices with
+/// `chrdev::Builder`, added to a filesystem with `TreeDir`, created in
+/// `/proc` with `proc_create_seq` and `proc_create_single`, or created in
+/// debugfs with `debugfs_create_seq` and `debugfs_create_single`.
+pub trait SingleSeqFile: Sync + Sized {
+    /// Writes the contents of the file.
+    fn show(&self, m: &mut SeqWriter) -> KernelResult<()>;
+}
+
+pub(crate) fn show_result(m: &SeqWriter, result: KernelResult<()>) -> c_types::c_int {
+    match result {
+        Ok(()) => 0,
+        // The kernel will retry with a bigger buffer.
+        Err(_) if m.has_overflowed() => 0,
+        Err(e) => e.to_kernel_errno(),
+    }
+}
+
+unsafe extern "C" fn seq_start_callback<T: SeqFile>(
+    m: *mut bindings::seq_file,
+    pos: *mut bindings::loff_t,
+) -> *mut c_types::c_void {
+    let f = &*((*m).private as *const T);
+    let pos = match (*pos).try_into() {
+        Ok(v) => v,
+        Err(_) => return ptr::null_mut(),
+    };
+    match f.start(pos) {
+        Some(item) => Box::into_raw(Box::new(item)) as *mut c_types::c_void,
+        None => ptr::null_mut(),
+    }
+}
+
+unsafe extern "C" fn seq_next_callback<T: SeqFile>(
+    m: *mut bindings::seq_file,
+    v: *mut c_types::c_void,
+    pos: *mut bindings::loff_t,
+) -> *mut c_types::c_void {
+    let f = &*((*m).private as *const T);
+    let item = *Box::from_raw(v as *mut T::Item);
+    // The kernel requires the position to advance even at the end.
+    *pos += 1;
+    let pos = match (*pos).try_into() {
+        Ok(v) => v,
+        Err(_) => return ptr::null_mut(),
+    };
+    match f.next(item, pos) {
+        Some(item) => Box::into_raw(Box::new(item)) as *mut c_types::c_void,
+        None => ptr::null_mut(),
+    }
+}
+
+unsafe extern "C" fn seq_stop_callback<T: SeqFile>(
+    m: *mut bindings::seq_file,
+    v: *mut c_types::c_void,
+) {
+    let f = &*((*m).private as *const T);
+    let item = if v.is_null() {
+        None
+    } else {
+        Some(*Box::from_raw(v as *mut T::Item))
+    };
+    f.stop(item);
+}
+
+unsafe extern "C" fn seq_show_callback<T: SeqFile>(
+    m: *mut bindings::seq_file,
+    v: *mut c_types::c_void,
+) -> c_types::c_int {
+    let f = &*((*m).private as *const T);
+    let item = &*(v as *const T::Item);
+    let mut writer = SeqWriter::from_ptr(m);
+    let result = f.show(item, &mut writer);
+    show_result(&writer, result)
+}
+
+unsafe extern "C" fn seq_open_callback<T: SeqFile + FileOpen>(
+    _inode: *mut bindings::inode,
+    file: *mut bindings::file,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let f = Box::new(T::open()?);
+        let rc = bindings::seq_open(file, &SeqFileVtable::<T>::SEQ_OPERATIONS);
+        if rc != 0 {
+            return Err(Error::from_kernel_errno(rc));
+        }
+        let m = (*file).private_data as *mut bindings::seq_file;
+        (*m).private = Box::into_raw(f) as *mut c_types::c_void;
+        Ok(0)
+    })
+}
+
+unsafe extern "C" fn seq_release_callback<T: SeqFile>(
+    inode: *mut bindings::inode,
+    file: *mut bindings::file,
+) -> c_types::c_int {
+    let m = (*file).private_data as *mut bindings::seq_file;
+    let ptr = mem::replace(&mut (*m).private, ptr::null_mut());
+    drop(Box::from_raw(ptr as *mut T));
+    bindings::seq_release(inode, file)
+}
+
+unsafe extern "C" fn single_show_callback<T: SingleSeqFile>(
+    m: *mut bindings::seq_file,
+    _v: *mut c_types::c_void,
+) -> c_types::c_int {
+    let f = &*((*m).private as *const T);
+    let mut writer = SeqWriter::from_ptr(m);
+    let result = f.show(&mut writer);
+    show_result(&writer, result)
+}
+
+unsafe extern "C" fn single_open_callback<T: SingleSeqFile + FileOpen>(
+    _inode: *mut bindings::inode,
+    file: *mut bindings::file,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        let f = Box::into_raw(Box::new(T::open()?));
+        let rc = bindings::single_open(
+            file,
+            Some(single_show_callback::<T>),
+            f as *mut c_types::c_void,
+        );
+        if rc != 0 {
+            drop(Box::from_raw(f));
+            return Err(Error::from_kernel_errno(rc));
+        }
+        Ok(0)
+    })
+}
+
+unsafe extern "C" fn single_release_callback<T: SingleSeqFile>(
+    inode: *mut bindings::inode,
+    file: *mut bindings::file,
+) -> c_types::c_int {
+    let m = (*file).private_data as *mut bindings::seq_file;
+    let ptr = mem::replace(&mut (*m).private, ptr::null_mut());
+    drop(Box::from_raw(ptr as *mut T));
+    bindings::single_release(inode, file)
+}
+
+pub(crate) struct SeqFileVtable<T>(marker::PhantomData<T>);
+
+impl<T: SeqFile + FileOpen> SeqFileVtable<T> {
+    const SEQ_OPERATIONS: bindings::seq_operations = bindings::seq_operations {
+        start: Some(seq_start_callback::<T>),
+        next: Some(seq_next_callback::<T>),
+        stop: Some(seq_stop_callback::<T>),
+        show: Some(seq_show_callback::<T>),
+    };
+
+    pub(crate) const VTABLE: bindings::file_operations = bindings::file_operations {
+        open: Some(seq_open_callback::<T>),
+        release: Some(seq_release_callback::<T>),
+        read: Some(bindings::seq_read),
+        llseek: Some(bindings::seq_lseek),
+
+        ..EMPTY_VTABLE
+    };
+
+    #[cfg(kernel_5_6_0_or_greater)]
+    const PROC_OPS: bindings::proc_ops = bindings::proc_ops {
+        proc_open: Some(seq_open_callback::<T>),
+        proc_release: Some(seq_release_callback::<T>),
+        proc_read: Some(bindings::seq_read),
+        proc_lseek: Some(bindings::seq_lseek),
+        ..empty_vtable!(bindings::proc_ops)
+    };
+
+    #[cfg(not(kernel_5_6_0_or_greater))]
+    const PROC_OPS: bindings::file_operations = Self::VTABLE;
+}
+
+pub(crate) struct SingleSeqFileVtable<T>(marker::PhantomData<T>);
+
+impl<T: SingleSeqFile + FileOpen> SingleSeqFileVtable<T> {
+    pub(crate) const VTABLE: bindings::file_operations = bindings::file_operations {
+        open: Some(single_open_callback::<T>),
+        release: Some(single_release_callback::<T>),
+        read: Some(bindings::seq_read),
+        llseek: Some(bindings::seq_lseek),
+
+        ..EMPTY_VTABLE
+    };
+
+    #[cfg(kernel_5_6_0_or_greater)]
+    const PROC_OPS: bindings::proc_ops = bindings::proc_ops {
+        proc_open: Some(single_open_callback::<T>),
+        proc_release: Some(single_release_callback::<T>),
+        proc_read: Some(bindings::seq_read),
+        proc_lseek: Some(bindings::seq_lseek),
+        ..empty_vtable!(bindings::proc_ops)
+    };
+
+    #[cfg(not(kernel_5_6_0_or_greater))]
+    const PROC_OPS: bindings::file_operations = Self::VTABLE;
+}
+
+// Since 5.6, /proc files have their own operations rather than a
+// struct file_operations.
+#[cfg(kernel_5_6_0_or_greater)]
+type ProcOps = bindings::proc_ops;
+#[cfg(not(kernel_5_6_0_or_greater))]
+type ProcOps = bindings::file_operations;
+
+/// A file in `/proc`, created by `proc_create_seq` or `proc_create_single`.
+/// The file is removed when this is dropped, after any reads in progress
+/// have finished.
+pub struct ProcEntry {
+    entry: *mut bindings::proc_dir_entry,
+}
+
+// This is safe because ProcEntry doesn't expose the kernel's mutable state.
+unsafe impl Sync for ProcEntry {}
+
+impl Drop for ProcEntry {
+    fn drop(&mut self) {
+        unsafe { bindings::proc_remove(self.entry) };
+    }
+}
+
+fn proc_create(name: CStr, mode: Mode, ops: &'static ProcOps) -> KernelResult<ProcEntry> {
+    let entry = unsafe {
+        bindings::proc_create(
+            name.as_ptr() as *const c_types::c_char,
+            mode.as_int(),
+            ptr::null_mut(),
+            ops,
+        )
+    };
+    // The kernel doesn't say why this failed, which is usually because the
+    // name is already taken.
+    if entry.is_null() {
+        return Err(Error::EBUSY);
+    }
+    Ok(ProcEntry { entry })
+}
+
+/// Creates `/proc/<name>`, showing the records of a `SeqFile`, with the
+/// given permission bits.
+pub fn proc_create_seq<T: SeqFile + FileOpen>(name: CStr, mode: Mode) -> KernelResult<ProcEntry> {
+    proc_create(name, mode, &SeqFileVtable::<T>::PROC_OPS)
+}
+
+/// Creates `/proc/<name>`, showing the contents of a `SingleSeqFile`, with
+/// the given permission bits.
+pub fn proc_create_single<T: SingleSeqFile + FileOpen>(
+    name: CStr,
+    mode: Mode,
+) -> KernelResult<ProcEntry> {
+    proc_create(name, mode, &SingleSeqFileVtable::<T>::PROC_OPS)
+}
+
+/// A file in debugfs, created by `debugfs_create_seq` or
+/// `debugfs_create_single`. The file is removed when this is dropped, after
+/// any reads in progress have finished.
+pub struct DebugfsEntry {
+    dentry: *mut bindings::dentry,
+}
+
+// This is safe because DebugfsEntry doesn't expose the kernel's mutable state.
+unsafe impl Sync for DebugfsEntry {}
+
+impl Drop for DebugfsEntry {
+    fn drop(&mut self) {
+        unsafe { bindings::debugfs_remove(self.dentry) };
+    }
+}
+
+fn debugfs_create(
+    name: CStr,
+    mode: Mode,
+    fops: &'static bindings::file_operations,
+) -> KernelResult<DebugfsEntry> {
+    let dentry = from_kernel_err_ptr(unsafe {
+        bindings::debugfs_create_file(
+            name.as_ptr() as *const c_types::c_char,
+            mode.as_int(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            fops,
+        )
+    })?;
+    // Older kernels return NULL rather than an ERR_PTR on failure, without
+    // saying why.
+    if dentry.is_null() {
+        return Err(Error::EBUSY);
+    }
+    Ok(DebugfsEntry { dentry })
+}
+
+/// Creates `<name>` at the top of debugfs (usually mounted at
+/// `/sys/kernel/debug`), showing the records of a `SeqFile`, with the given
+/// permission bits.
+pub fn debugfs_create_seq<T: SeqFile + FileOpen>(
+    name: CStr,
+    mode: Mode,
+) -> KernelResult<DebugfsEntry> {
+    debugfs_create(name, mode, &SeqFileVtable::<T>::VTABLE)
+}
+
+/// Creates `<name>` at the top of debugfs, showing the contents of a
+/// `SingleSeqFile`, with the given permission bits.
+pub fn debugfs_create_single<T: SingleSeqFile + FileOpen>(
+    name: CStr,
+    mode: Mode,
+) -> KernelResult<DebugfsEntry> {
+    debugfs_create(name, mode, &SingleSeqFileVtable::<T>::VTABLE)
+}
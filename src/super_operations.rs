@@ -0,0 +1,147 @@
+use core::marker;
+
+use crate::bindings;
+use crate::c_types;
+use crate::error::{from_kernel_result, KernelResult};
+use crate::filesystem::{self, fs_info, FileSystem, Inode};
+use crate::seq_file::{self, SeqWriter};
+
+/// Filesystem statistics reported by `statfs(2)`, corresponding to the
+/// kernel's `struct kstatfs`. The filesystem type, block size and maximum
+/// name length are already filled in from the superblock, and everything
+/// else starts out as 0.
+pub struct StatFs<'a> {
+    ptr: *mut bindings::kstatfs,
+    _phantom: marker::PhantomData<&'a mut bindings::kstatfs>,
+}
+
+impl StatFs<'_> {
+    /// Sets the size of the filesystem in blocks.
+    pub fn set_blocks(&mut self, blocks: u64) {
+        unsafe { (*self.ptr).f_blocks = blocks };
+    }
+
+    /// Sets the number of free blocks, and how many of those can be used by
+    /// unprivileged users.
+    pub fn set_free_blocks(&mut self, free: u64, available: u64) {
+        unsafe {
+            (*self.ptr).f_bfree = free;
+            (*self.ptr).f_bavail = available;
+        }
+    }
+
+    /// Sets the total and free numbers of inodes.
+    pub fn set_files(&mut self, files: u64, free: u64) {
+        unsafe {
+            (*self.ptr).f_files = files;
+            (*self.ptr).f_ffree = free;
+        }
+    }
+}
+
+unsafe extern "C" fn statfs_callback<T: FileSystem>(
+    dentry: *mut bindings::dentry,
+    buf: *mut bindings::kstatfs,
+) -> c_types::c_int {
+    from_kernel_result(|| {
+        bindings::simple_statfs(dentry, buf);
+        if let (Some(statfs), Some(fs)) = (T::STATFS, fs_info::<T>((*dentry).d_sb)) {
+            statfs(
+                fs,
+                &mut StatFs {
+                    ptr: buf,
+                    _phantom: marker::PhantomData,
+                },
+            )?;
+        }
+        Ok(0)
+    })
+}
+
+unsafe extern "C" fn show_options_callback<T: FileSystem>(
+    m: *mut bindings::seq_file,
+    root: *mut bindings::dentry,
+) -> c_types::c_int {
+    let mut writer = SeqWriter::from_ptr(m);
+    let result = match (T::SHOW_OPTIONS, fs_info::<T>((*root).d_sb)) {
+        (Some(show_options), Some(fs)) => show_options(fs, &mut writer),
+        _ => Ok(()),
+    };
+    seq_file::show_result(&writer, result)
+}
+
+unsafe extern "C" fn evict_inode_callback<T: FileSystem>(inode: *mut bindings::inode) {
+    if let (Some(evict_inode), Some(fs)) = (T::EVICT_INODE, fs_info::<T>((*inode).i_sb)) {
+        evict_inode(fs, &Inode::from_ptr(inode));
+    }
+    bindings::truncate_inode_pages_final(&mut (*inode).i_data);
+    bindings::clear_inode(inode);
+    filesystem::free_inode_data(inode);
+}
+
+unsafe extern "C" fn put_super_callback<T: FileSystem>(sb: *mut bindings::super_block) {
+    if let (Some(put_super), Some(fs)) = (T::PUT_SUPER, fs_info::<T>(sb)) {
+        put_super(fs);
+    }
+}
+
+pub(crate) struct SuperOperationsVtable<T>(marker::PhantomData<T>);
+
+impl<T: FileSystem> SuperOperationsVtable<T> {
+    pub(crate) const VTABLE: bindings::super_operations = bindings::super_operations {
+        statfs: if let Some(_) = T::STATFS {
+            Some(statfs_callback::<T>)
+        } else {
+            Some(bindings::simple_statfs)
+        },
+        show_options: if let Some(_) = T::SHOW_OPTIONS {
+            Some(show_options_callback::<T>)
+        } else {
+            None
+        },
+        // Inodes are only kept while they're in use, so filesystems with their
+        // own tree need to recreate them with get_inode.
+        drop_inode: Some(bindings::generic_delete_inode),
+        // Always set, to free the data stored by get_inode.
+        evict_inode: Some(evict_inode_callback::<T>),
+        put_super: if let Some(_) = T::PUT_SUPER {
+            Some(put_super_callback::<T>)
+        } else {
+            None
+        },
+        #[cfg(not(kernel_5_4_0_or_greater))]
+        remount_fs: Some(filesystem::remount_callback::<T>),
+        ..empty_vtable!(bindings::super_operations)
+    };
+}
+
+pub type StatfsFn<T> = Option<fn(&T, &mut StatFs) -> KernelResult<()>>;
+pub type ShowOptionsFn<T> = Option<fn(&T, &mut SeqWriter) -> KernelResult<()>>;
+pub type EvictInodeFn<T> = Option<fn(&T, &Inode)>;
+pub type PutSuperFn<T> = Option<fn(&T)>;
+
+/// `SuperOperations` corresponds to the kernel's `struct super_operations`.
+/// It's implemented by each `FileSystem` type, and the operations are called
+/// on the state of the mount they belong to. None of them need to be
+/// provided, so a filesystem without any can use an empty `impl`.
+pub trait SuperOperations: Send + Sync + Sized {
+    /// Fills in the statistics reported by `statfs(2)` and `df`. Without it,
+    /// the filesystem reports no blocks or inodes. Corresponds to the
+    /// `statfs` function pointer in `struct super_operations`.
+    const STATFS: StatfsFn<Self> = None;
+
+    /// Writes the mount options shown in `/proc/mounts`, each preceded by a
+    /// comma, such as `,mode=700`. Corresponds to the `show_options`
+    /// function pointer in `struct super_operations`.
+    const SHOW_OPTIONS: ShowOptionsFn<Self> = None;
+
+    /// Called when an inode is removed from the inode cache, before its
+    /// pages and the value stored by `get_inode` are freed. Corresponds to
+    /// the `evict_inode` function pointer in `struct super_operations`.
+    const EVICT_INODE: EvictInodeFn<Self> = None;
+
+    /// Called when the filesystem is unmounted, after every inode has been
+    /// evicted and before the state of the mount is dropped. Corresponds to
+    /// the `put_super` function pointer in `struct super_operations`.
+    const PUT_SUPER: PutSuperFn<Self> = None;
+}
@@ -0,0 +1,144 @@
+use core::cell::UnsafeCell;
+use core::marker;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+
+use alloc::boxed::Box;
+
+use crate::bindings;
+use crate::c_types;
+use crate::types::CStr;
+
+extern "C" {
+    fn mutex_init_helper(
+        lock: *mut bindings::mutex,
+        name: *const c_types::c_char,
+        key: *mut bindings::lock_class_key,
+    );
+    fn mutex_lock_helper(lock: *mut bindings::mutex);
+    fn mutex_lock_nested_helper(lock: *mut bindings::mutex, subclass: c_types::c_uint);
+}
+
+/// Identifies a lockdep class, which lockdep uses to check that locks are
+/// always taken in a consistent order. Keys must be `static`; `new_mutex!`
+/// declares one for each place it's used.
+pub struct LockClassKey(UnsafeCell<MaybeUninit<bindings::lock_class_key>>);
+
+// Lockdep only uses the address of the key, never its contents.
+unsafe impl Sync for LockClassKey {}
+
+impl LockClassKey {
+    pub const fn new() -> LockClassKey {
+        LockClassKey(UnsafeCell::new(MaybeUninit::uninit()))
+    }
+
+    fn as_ptr(&self) -> *mut bindings::lock_class_key {
+        self.0.get() as *mut bindings::lock_class_key
+    }
+}
+
+impl Default for LockClassKey {
+    fn default() -> LockClassKey {
+        LockClassKey::new()
+    }
+}
+
+/// Creates a `Mutex` holding `data`, in a lockdep class of its own named
+/// after the call site. Mutexes created in different places can then be
+/// nested without lockdep reporting a possible deadlock.
+#[macro_export]
+macro_rules! new_mutex {
+    ($data:expr) => {{
+        static KEY: $crate::sync::LockClassKey = $crate::sync::LockClassKey::new();
+        $crate::sync::Mutex::new_with_key(
+            $data,
+            unsafe { $crate::CStr::new_unchecked(concat!(file!(), ":", line!(), "\x00")) },
+            &KEY,
+        )
+    }};
+}
+
+/// A value protected by a kernel `struct mutex`. Locking may sleep, so this
+/// can only be used in process context.
+pub struct Mutex<T> {
+    // The kernel's mutex can't be moved once it's initialized.
+    lock: Box<UnsafeCell<bindings::mutex>>,
+    data: UnsafeCell<T>,
+}
+
+// This is safe because the mutex serializes all access to `data`.
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Creates a mutex in the lockdep class identified by `key`, which is
+    /// called `name` in lockdep's reports. Most callers want `new_mutex!`
+    /// instead.
+    pub fn new_with_key(data: T, name: CStr<'static>, key: &'static LockClassKey) -> Mutex<T> {
+        let lock = Box::new(UnsafeCell::new(Default::default()));
+        unsafe {
+            mutex_init_helper(
+                lock.get(),
+                name.as_ptr() as *const c_types::c_char,
+                key.as_ptr(),
+            )
+        };
+        Mutex {
+            lock,
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Waits for the mutex and returns a guard that releases it when
+    /// dropped.
+    pub fn lock(&self) -> MutexGuard<T> {
+        unsafe { mutex_lock_helper(self.lock.get()) };
+        MutexGuard::new(self)
+    }
+
+    /// Like `lock`, for code that holds several mutexes of the same lockdep
+    /// class at once, such as two directories of a filesystem. Each must be
+    /// locked with a different `subclass`, always in the same order.
+    pub fn lock_nested(&self, subclass: u32) -> MutexGuard<T> {
+        unsafe { mutex_lock_nested_helper(self.lock.get(), subclass) };
+        MutexGuard::new(self)
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    // The kernel's mutex must be released by the task that took it.
+    _not_send: marker::PhantomData<*mut ()>,
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    fn new(mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+        MutexGuard {
+            mutex,
+            _not_send: marker::PhantomData,
+        }
+    }
+}
+
+// Sharing a guard only gives out `&T`.
+unsafe impl<T: Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { bindings::mutex_unlock(self.mutex.lock.get()) };
+    }
+}
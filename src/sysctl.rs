@@ -1,18 +1,42 @@
 use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec;
+use alloc::vec::Vec;
+use core::cell;
+use core::convert::TryFrom;
+use core::fmt;
+use core::marker;
 use core::mem;
 use core::ptr;
+#[cfg(kernel_5_8_0_or_greater)]
+use core::slice;
+use core::str;
 use core::sync::atomic;
 
 use crate::bindings;
 use crate::c_types;
 use crate::error;
+use crate::sync::Mutex;
 use crate::types;
-use crate::user_ptr::{UserSlicePtr, UserSlicePtrWriter};
+#[cfg(not(kernel_5_8_0_or_greater))]
+use crate::user_ptr::UserSlicePtr;
 
 pub trait SysctlStorage: Sync {
     fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>);
-    fn read_value(&self, data: &mut UserSlicePtrWriter) -> (usize, error::KernelResult<()>);
+
+    /// Renders the whole value. Reads at an offset are served from this, so
+    /// it should return the same thing each time unless the value changes.
+    fn read_value(&self) -> error::KernelResult<Vec<u8>>;
+
+    /// Whether a write at a non-zero offset continues the value that was
+    /// there, as `proc_dostring` does, rather than being ignored, as
+    /// `proc_dointvec` does. These are the kernel's behaviors with the
+    /// default `kernel.sysctl_writes_strict=1`.
+    fn continues_writes(&self) -> bool {
+        false
+    }
 }
 
 fn trim_whitespace(mut data: &[u8]) -> &[u8] {
@@ -37,34 +61,617 @@ where
         (*self).store_value(data)
     }
 
-    fn read_value(&self, data: &mut UserSlicePtrWriter) -> (usize, error::KernelResult<()>) {
-        (*self).read_value(data)
+    fn read_value(&self) -> error::KernelResult<Vec<u8>> {
+        (*self).read_value()
+    }
+
+    fn continues_writes(&self) -> bool {
+        (*self).continues_writes()
     }
 }
 
-impl SysctlStorage for atomic::AtomicBool {
+/// Allows the module to keep a handle on storage that's been moved into a
+/// `SysctlTable`.
+impl<T> SysctlStorage for Arc<T>
+where
+    T: SysctlStorage + Send,
+{
     fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>) {
-        let result = match trim_whitespace(data) {
-            b"0" => {
-                self.store(false, atomic::Ordering::Relaxed);
-                Ok(())
-            }
-            b"1" => {
-                self.store(true, atomic::Ordering::Relaxed);
-                Ok(())
-            }
+        (**self).store_value(data)
+    }
+
+    fn read_value(&self) -> error::KernelResult<Vec<u8>> {
+        (**self).read_value()
+    }
+
+    fn continues_writes(&self) -> bool {
+        (**self).continues_writes()
+    }
+}
+
+/// A `SysctlStorage` holding a single value, which can be parsed separately
+/// from being stored. This is what allows `Watched` to see both the old and
+/// the new value of a write.
+pub trait SysctlValue: SysctlStorage {
+    type Value;
+
+    /// Parses a write, checking any constraints of the storage itself such
+    /// as a range, without storing it.
+    fn parse_value(&self, data: &[u8]) -> error::KernelResult<Self::Value>;
+    fn current_value(&self) -> Self::Value;
+    fn set_value(&self, value: Self::Value);
+}
+
+fn store_parsed<T: SysctlValue>(storage: &T, data: &[u8]) -> (usize, error::KernelResult<()>) {
+    let result = storage
+        .parse_value(data)
+        .map(|value| storage.set_value(value));
+    (data.len(), result)
+}
+
+impl SysctlValue for atomic::AtomicBool {
+    type Value = bool;
+
+    fn parse_value(&self, data: &[u8]) -> error::KernelResult<bool> {
+        match trim_whitespace(data) {
+            b"0" => Ok(false),
+            b"1" => Ok(true),
             _ => Err(error::Error::EINVAL),
-        };
-        (data.len(), result)
+        }
+    }
+
+    fn current_value(&self) -> bool {
+        self.load(atomic::Ordering::Relaxed)
+    }
+
+    fn set_value(&self, value: bool) {
+        self.store(value, atomic::Ordering::Relaxed);
+    }
+}
+
+impl SysctlStorage for atomic::AtomicBool {
+    fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>) {
+        store_parsed(self, data)
     }
 
-    fn read_value(&self, data: &mut UserSlicePtrWriter) -> (usize, error::KernelResult<()>) {
+    fn read_value(&self) -> error::KernelResult<Vec<u8>> {
         let value = if self.load(atomic::Ordering::Relaxed) {
             b"1\n"
         } else {
             b"0\n"
         };
-        (value.len(), data.write(value))
+        Ok(value.to_vec())
+    }
+}
+
+/// An atomic integer type that can be used as sysctl storage. Values are
+/// written in decimal, or in hex with a `0x` prefix, and read back in decimal.
+pub trait AtomicInteger: Sync {
+    type Value: Copy + PartialOrd + fmt::Display + TryFrom<i128>;
+
+    fn get(&self) -> Self::Value;
+    fn set(&self, value: Self::Value);
+}
+
+macro_rules! atomic_integer {
+    ($($atomic:ident: $value:ty),*) => {
+        $(
+            impl AtomicInteger for atomic::$atomic {
+                type Value = $value;
+
+                fn get(&self) -> $value {
+                    self.load(atomic::Ordering::Relaxed)
+                }
+
+                fn set(&self, value: $value) {
+                    self.store(value, atomic::Ordering::Relaxed)
+                }
+            }
+
+            impl SysctlValue for atomic::$atomic {
+                type Value = $value;
+
+                fn parse_value(&self, data: &[u8]) -> error::KernelResult<$value> {
+                    parse_integer(data)
+                }
+
+                fn current_value(&self) -> $value {
+                    AtomicInteger::get(self)
+                }
+
+                fn set_value(&self, value: $value) {
+                    AtomicInteger::set(self, value)
+                }
+            }
+
+            impl SysctlStorage for atomic::$atomic {
+                fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>) {
+                    store_parsed(self, data)
+                }
+
+                fn read_value(&self) -> error::KernelResult<Vec<u8>> {
+                    Ok(read_display(self.get()))
+                }
+            }
+        )*
+    };
+}
+
+atomic_integer!(
+    AtomicI32: i32,
+    AtomicU32: u32,
+    AtomicI64: i64,
+    AtomicU64: u64,
+    AtomicUsize: usize
+);
+
+pub(crate) fn parse_integer<V: TryFrom<i128>>(data: &[u8]) -> error::KernelResult<V> {
+    let s = str::from_utf8(trim_whitespace(data)).map_err(|_| error::Error::EINVAL)?;
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let (radix, digits) = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(rest) => (16, rest),
+        None => (10, s),
+    };
+    // from_str_radix accepts its own sign, which would allow "--1".
+    if digits.starts_with('-') || digits.starts_with('+') {
+        return Err(error::Error::EINVAL);
+    }
+    let magnitude = i128::from_str_radix(digits, radix).map_err(|_| error::Error::EINVAL)?;
+    let value = if negative { -magnitude } else { magnitude };
+    V::try_from(value).map_err(|_| error::Error::EINVAL)
+}
+
+fn read_display(value: impl fmt::Display) -> Vec<u8> {
+    format!("{}\n", value).into_bytes()
+}
+
+/// An atomic integer that rejects writes outside of `min..=max` with
+/// `EINVAL`, like `proc_dointvec_minmax`.
+pub struct Ranged<A: AtomicInteger> {
+    value: A,
+    min: A::Value,
+    max: A::Value,
+}
+
+impl<A: AtomicInteger> Ranged<A> {
+    pub fn new(value: A, min: A::Value, max: A::Value) -> Ranged<A> {
+        Ranged { value, min, max }
+    }
+
+    pub fn get(&self) -> A::Value {
+        self.value.get()
+    }
+}
+
+impl<A: AtomicInteger> SysctlValue for Ranged<A>
+where
+    A::Value: Sync,
+{
+    type Value = A::Value;
+
+    fn parse_value(&self, data: &[u8]) -> error::KernelResult<A::Value> {
+        let value = parse_integer(data)?;
+        if value < self.min || value > self.max {
+            return Err(error::Error::EINVAL);
+        }
+        Ok(value)
+    }
+
+    fn current_value(&self) -> A::Value {
+        self.get()
+    }
+
+    fn set_value(&self, value: A::Value) {
+        self.value.set(value)
+    }
+}
+
+impl<A: AtomicInteger> SysctlStorage for Ranged<A>
+where
+    A::Value: Sync,
+{
+    fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>) {
+        store_parsed(self, data)
+    }
+
+    fn read_value(&self) -> error::KernelResult<Vec<u8>> {
+        Ok(read_display(self.get()))
+    }
+}
+
+fn parse_integers<V: TryFrom<i128> + PartialOrd + Copy>(
+    data: &[u8],
+    range: Option<(V, V)>,
+    max_len: usize,
+) -> error::KernelResult<Vec<V>> {
+    let mut values = Vec::new();
+    for word in data.split(|c| c.is_ascii_whitespace()) {
+        if word.is_empty() {
+            continue;
+        }
+        if values.len() == max_len {
+            return Err(error::Error::EINVAL);
+        }
+        let value = parse_integer(word)?;
+        if let Some((min, max)) = range {
+            if value < min || value > max {
+                return Err(error::Error::EINVAL);
+            }
+        }
+        values.push(value);
+    }
+    Ok(values)
+}
+
+fn read_integers<V: fmt::Display>(values: impl Iterator<Item = V>) -> Vec<u8> {
+    // Tab-separated, like proc_dointvec.
+    let mut result = String::new();
+    for (i, value) in values.enumerate() {
+        if i != 0 {
+            result.push('\t');
+        }
+        result.push_str(&format!("{}", value));
+    }
+    result.push('\n');
+    result.into_bytes()
+}
+
+/// A fixed number of atomic integers, read and written as a
+/// whitespace-separated list. Like `proc_dointvec`, a write with fewer
+/// values only replaces the first ones; a write with too many values, or a
+/// value outside the range set with `with_range`, is rejected with `EINVAL`
+/// and changes nothing.
+pub struct AtomicArray<A: AtomicInteger> {
+    values: Box<[A]>,
+    range: Option<(A::Value, A::Value)>,
+}
+
+impl<A: AtomicInteger> AtomicArray<A> {
+    pub fn new(values: Vec<A>) -> AtomicArray<A> {
+        AtomicArray {
+            values: values.into_boxed_slice(),
+            range: None,
+        }
+    }
+
+    /// Rejects writes containing any value outside `min..=max`.
+    pub fn with_range(mut self, min: A::Value, max: A::Value) -> AtomicArray<A> {
+        self.range = Some((min, max));
+        self
+    }
+
+    pub fn values(&self) -> &[A] {
+        &self.values
+    }
+}
+
+impl<A: AtomicInteger> SysctlValue for AtomicArray<A>
+where
+    A::Value: Sync,
+{
+    type Value = Vec<A::Value>;
+
+    fn parse_value(&self, data: &[u8]) -> error::KernelResult<Vec<A::Value>> {
+        parse_integers(data, self.range, self.values.len())
+    }
+
+    fn current_value(&self) -> Vec<A::Value> {
+        self.values.iter().map(|v| v.get()).collect()
+    }
+
+    fn set_value(&self, value: Vec<A::Value>) {
+        for (atomic, v) in self.values.iter().zip(value) {
+            atomic.set(v);
+        }
+    }
+}
+
+impl<A: AtomicInteger> SysctlStorage for AtomicArray<A>
+where
+    A::Value: Sync,
+{
+    fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>) {
+        store_parsed(self, data)
+    }
+
+    fn read_value(&self) -> error::KernelResult<Vec<u8>> {
+        Ok(read_integers(self.values.iter().map(|v| v.get())))
+    }
+}
+
+/// A list of up to `max_len` integers, protected by a mutex, read and
+/// written as a whitespace-separated list. Each write replaces the whole
+/// list. Writes with too many values, or with a value outside the range set
+/// with `with_range`, are rejected with `EINVAL`.
+pub struct LockedArray<V> {
+    values: Mutex<Vec<V>>,
+    max_len: usize,
+    range: Option<(V, V)>,
+}
+
+impl<V> LockedArray<V>
+where
+    V: Copy + PartialOrd + fmt::Display + TryFrom<i128> + Send,
+{
+    pub fn new(values: Vec<V>, max_len: usize) -> error::KernelResult<LockedArray<V>> {
+        if values.len() > max_len {
+            return Err(error::Error::EINVAL);
+        }
+        Ok(LockedArray {
+            values: crate::new_mutex!(values),
+            max_len,
+            range: None,
+        })
+    }
+
+    /// Rejects writes containing any value outside `min..=max`.
+    pub fn with_range(mut self, min: V, max: V) -> LockedArray<V> {
+        self.range = Some((min, max));
+        self
+    }
+
+    pub fn get(&self) -> Vec<V> {
+        self.values.lock().clone()
+    }
+}
+
+impl<V> SysctlValue for LockedArray<V>
+where
+    V: Copy + PartialOrd + fmt::Display + TryFrom<i128> + Send + Sync,
+{
+    type Value = Vec<V>;
+
+    fn parse_value(&self, data: &[u8]) -> error::KernelResult<Vec<V>> {
+        parse_integers(data, self.range, self.max_len)
+    }
+
+    fn current_value(&self) -> Vec<V> {
+        self.get()
+    }
+
+    fn set_value(&self, value: Vec<V>) {
+        *self.values.lock() = value;
+    }
+}
+
+impl<V> SysctlStorage for LockedArray<V>
+where
+    V: Copy + PartialOrd + fmt::Display + TryFrom<i128> + Send + Sync,
+{
+    fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>) {
+        store_parsed(self, data)
+    }
+
+    fn read_value(&self) -> error::KernelResult<Vec<u8>> {
+        Ok(read_integers(self.values.lock().iter()))
+    }
+}
+
+/// A string of at most `max_len` bytes, protected by a mutex. Writes stop at
+/// the first newline, like `proc_dostring`, and longer values are rejected
+/// with `EINVAL`.
+pub struct BoundedString {
+    value: Mutex<String>,
+    max_len: usize,
+}
+
+impl BoundedString {
+    pub fn new(value: &str, max_len: usize) -> error::KernelResult<BoundedString> {
+        if value.len() > max_len {
+            return Err(error::Error::EINVAL);
+        }
+        Ok(BoundedString {
+            value: crate::new_mutex!(String::from(value)),
+            max_len,
+        })
+    }
+
+    pub fn get(&self) -> String {
+        self.value.lock().clone()
+    }
+
+    pub fn set(&self, value: &str) -> error::KernelResult<()> {
+        if value.len() > self.max_len {
+            return Err(error::Error::EINVAL);
+        }
+        let mut guard = self.value.lock();
+        guard.clear();
+        guard.push_str(value);
+        Ok(())
+    }
+}
+
+impl SysctlValue for BoundedString {
+    type Value = String;
+
+    fn parse_value(&self, data: &[u8]) -> error::KernelResult<String> {
+        let line = data.split(|&c| c == b'\n').next().unwrap_or(&[]);
+        let value = str::from_utf8(line).map_err(|_| error::Error::EINVAL)?;
+        if value.len() > self.max_len {
+            return Err(error::Error::EINVAL);
+        }
+        Ok(String::from(value))
+    }
+
+    fn current_value(&self) -> String {
+        self.get()
+    }
+
+    fn set_value(&self, value: String) {
+        *self.value.lock() = value;
+    }
+}
+
+impl SysctlStorage for BoundedString {
+    fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>) {
+        store_parsed(self, data)
+    }
+
+    fn read_value(&self) -> error::KernelResult<Vec<u8>> {
+        Ok(read_display(self.value.lock().as_str()))
+    }
+
+    fn continues_writes(&self) -> bool {
+        true
+    }
+}
+
+/// A type with a fixed set of values that are read and written by name.
+pub trait SysctlEnum: Copy + PartialEq + 'static {
+    /// Every value, along with its name.
+    const VARIANTS: &'static [(&'static str, Self)];
+}
+
+/// Storage for a `SysctlEnum`, which rejects writes of unknown names with
+/// `EINVAL`. Values missing from `SysctlEnum::VARIANTS` can't be stored, and
+/// are rejected with `EINVAL` by `new` and `set`.
+pub struct EnumValue<E: SysctlEnum> {
+    index: atomic::AtomicUsize,
+    _phantom: marker::PhantomData<fn() -> E>,
+}
+
+impl<E: SysctlEnum> EnumValue<E> {
+    pub fn new(value: E) -> error::KernelResult<EnumValue<E>> {
+        Ok(EnumValue {
+            index: atomic::AtomicUsize::new(EnumValue::index_of(value)?),
+            _phantom: marker::PhantomData,
+        })
+    }
+
+    pub fn get(&self) -> E {
+        E::VARIANTS[self.index.load(atomic::Ordering::Relaxed)].1
+    }
+
+    pub fn set(&self, value: E) -> error::KernelResult<()> {
+        self.index
+            .store(EnumValue::index_of(value)?, atomic::Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn index_of(value: E) -> error::KernelResult<usize> {
+        E::VARIANTS
+            .iter()
+            .position(|(_, v)| *v == value)
+            .ok_or(error::Error::EINVAL)
+    }
+}
+
+impl<E: SysctlEnum> SysctlValue for EnumValue<E> {
+    type Value = E;
+
+    fn parse_value(&self, data: &[u8]) -> error::KernelResult<E> {
+        let name = trim_whitespace(data);
+        E::VARIANTS
+            .iter()
+            .find(|(n, _)| n.as_bytes() == name)
+            .map(|(_, value)| *value)
+            .ok_or(error::Error::EINVAL)
+    }
+
+    fn current_value(&self) -> E {
+        self.get()
+    }
+
+    fn set_value(&self, value: E) {
+        // parse_value only returns values from VARIANTS, so this can't fail.
+        let _ = self.set(value);
+    }
+}
+
+impl<E: SysctlEnum> SysctlStorage for EnumValue<E> {
+    fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>) {
+        store_parsed(self, data)
+    }
+
+    fn read_value(&self) -> error::KernelResult<Vec<u8>> {
+        let (name, _) = E::VARIANTS[self.index.load(atomic::Ordering::Relaxed)];
+        Ok(read_display(name))
+    }
+}
+
+/// Validation hook for `Watched`, called with the old and new values.
+pub type ValidateFn<V> = Box<dyn Fn(&V, &V) -> error::KernelResult<()> + Send + Sync>;
+/// Change hook for `Watched`, called with the old and new values.
+pub type OnChangeFn<V> = Box<dyn Fn(&V, &V) + Send + Sync>;
+
+/// Wraps a `SysctlValue` with hooks that run on every write: `validate` can
+/// reject the new value before it's stored, and `on_change` is called after
+/// it has been stored, so modules don't need to poll for changes. Writes are
+/// serialized, so the old value seen by the hooks is always the one being
+/// replaced.
+pub struct Watched<T: SysctlValue> {
+    storage: T,
+    validate: Option<ValidateFn<T::Value>>,
+    on_change: Option<OnChangeFn<T::Value>>,
+    write_lock: Mutex<()>,
+}
+
+impl<T: SysctlValue> Watched<T> {
+    pub fn new(storage: T) -> Watched<T> {
+        Watched {
+            storage,
+            validate: None,
+            on_change: None,
+            write_lock: crate::new_mutex!(()),
+        }
+    }
+
+    /// Sets a hook that can reject a write by returning an error, which is
+    /// passed on to the writer.
+    pub fn validate(
+        mut self,
+        f: impl Fn(&T::Value, &T::Value) -> error::KernelResult<()> + Send + Sync + 'static,
+    ) -> Watched<T> {
+        self.validate = Some(Box::new(f));
+        self
+    }
+
+    /// Sets a hook that is called after every successful write, even if the
+    /// value didn't change.
+    pub fn on_change(
+        mut self,
+        f: impl Fn(&T::Value, &T::Value) + Send + Sync + 'static,
+    ) -> Watched<T> {
+        self.on_change = Some(Box::new(f));
+        self
+    }
+
+    pub fn get(&self) -> &T {
+        &self.storage
+    }
+}
+
+impl<T: SysctlValue> Watched<T> {
+    fn store(&self, data: &[u8]) -> error::KernelResult<()> {
+        let new = self.storage.parse_value(data)?;
+        let _guard = self.write_lock.lock();
+        let old = self.storage.current_value();
+        if let Some(validate) = &self.validate {
+            validate(&old, &new)?;
+        }
+        self.storage.set_value(new);
+        if let Some(on_change) = &self.on_change {
+            // The storage took ownership of the new value, so read it back.
+            on_change(&old, &self.storage.current_value());
+        }
+        Ok(())
+    }
+}
+
+impl<T: SysctlValue> SysctlStorage for Watched<T> {
+    fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>) {
+        (data.len(), self.store(data))
+    }
+
+    fn read_value(&self) -> error::KernelResult<Vec<u8>> {
+        self.storage.read_value()
+    }
+
+    fn continues_writes(&self) -> bool {
+        self.storage.continues_writes()
     }
 }
 
@@ -86,34 +693,73 @@ unsafe extern "C" fn proc_handler<T: SysctlStorage>(
     len: *mut usize,
     ppos: *mut bindings::loff_t,
 ) -> c_types::c_int {
-    // If we're reading from some offset other than the beginning of the file,
-    // return an empty read to signal EOF.
-    if *ppos != 0 && write == 0 {
-        *len = 0;
-        return 0;
-    }
+    error::from_kernel_result(|| {
+        let storage = &*((*ctl).data as *const T);
+        let pos = usize::try_from(*ppos)?;
+        if write != 0 {
+            let data = copy_from_buffer(buffer, *len)?;
+            let (bytes_processed, result) = if pos == 0 {
+                storage.store_value(&data)
+            } else if storage.continues_writes() {
+                let mut value = storage.read_value()?;
+                if value.last() == Some(&b'\n') {
+                    value.pop();
+                }
+                if pos > value.len() {
+                    // Like proc_dostring, silently ignore writes past the end.
+                    return Ok(0);
+                }
+                value.truncate(pos);
+                value.extend_from_slice(&data);
+                let (_, result) = storage.store_value(&value);
+                (data.len(), result)
+            } else {
+                // Like proc_dointvec, ignore the write and report that nothing
+                // was written.
+                (0, Ok(()))
+            };
+            *len = bytes_processed;
+            *ppos += bindings::loff_t::try_from(bytes_processed)?;
+            result?;
+        } else {
+            let value = storage.read_value()?;
+            let start = core::cmp::min(pos, value.len());
+            let end = core::cmp::min(value.len(), start.saturating_add(*len));
+            copy_to_buffer(buffer, &value[start..end])?;
+            *len = end - start;
+            *ppos += bindings::loff_t::try_from(end - start)?;
+        }
+        Ok(0)
+    })
+}
 
-    let data = match UserSlicePtr::new(buffer, *len) {
-        Ok(ptr) => ptr,
-        Err(e) => return e.to_kernel_errno(),
-    };
-    let storage = &*((*ctl).data as *const T);
-    let (bytes_processed, result) = if write != 0 {
-        let data = match data.read_all() {
-            Ok(r) => r,
-            Err(e) => return e.to_kernel_errno(),
-        };
-        storage.store_value(&data)
-    } else {
-        let mut writer = data.writer();
-        storage.read_value(&mut writer)
-    };
-    *len = bytes_processed;
-    *ppos += *len as bindings::loff_t;
-    match result {
-        Ok(()) => 0,
-        Err(e) => e.to_kernel_errno(),
-    }
+// Since 5.8 the kernel copies to and from userspace itself, and passes a
+// kernel buffer.
+#[cfg(kernel_5_8_0_or_greater)]
+unsafe fn copy_from_buffer(
+    buffer: *mut c_types::c_void,
+    len: usize,
+) -> error::KernelResult<Vec<u8>> {
+    Ok(slice::from_raw_parts(buffer as *const u8, len).to_vec())
+}
+
+#[cfg(not(kernel_5_8_0_or_greater))]
+unsafe fn copy_from_buffer(
+    buffer: *mut c_types::c_void,
+    len: usize,
+) -> error::KernelResult<Vec<u8>> {
+    UserSlicePtr::new(buffer, len)?.read_all()
+}
+
+#[cfg(kernel_5_8_0_or_greater)]
+unsafe fn copy_to_buffer(buffer: *mut c_types::c_void, data: &[u8]) -> error::KernelResult<()> {
+    ptr::copy_nonoverlapping(data.as_ptr(), buffer as *mut u8, data.len());
+    Ok(())
+}
+
+#[cfg(not(kernel_5_8_0_or_greater))]
+unsafe fn copy_to_buffer(buffer: *mut c_types::c_void, data: &[u8]) -> error::KernelResult<()> {
+    UserSlicePtr::new(buffer, data.len())?.write_all(data)
 }
 
 impl<T: SysctlStorage> Sysctl<T> {
@@ -171,3 +817,364 @@ impl<T: SysctlStorage> Drop for Sysctl<T> {
         self.header = ptr::null_mut();
     }
 }
+
+enum SysctlEntry {
+    Value {
+        name: types::CStr<'static>,
+        mode: types::Mode,
+        data: *mut c_types::c_void,
+        proc_handler: bindings::proc_handler,
+    },
+    Dir {
+        name: Box<[u8]>,
+        dir: SysctlDir,
+    },
+}
+
+/// The contents of a sysctl directory, for registering with `SysctlTable`.
+/// Entries can use different storage types, and directories can be nested.
+pub struct SysctlDir {
+    entries: Vec<SysctlEntry>,
+    storages: Vec<Box<dyn SysctlStorage>>,
+}
+
+impl SysctlDir {
+    pub fn new() -> SysctlDir {
+        SysctlDir {
+            entries: vec![],
+            storages: vec![],
+        }
+    }
+
+    /// Adds a file called `name` backed by `storage`.
+    pub fn entry<T: SysctlStorage + 'static>(
+        mut self,
+        name: types::CStr<'static>,
+        storage: T,
+        mode: types::Mode,
+    ) -> SysctlDir {
+        let storage = Box::new(storage);
+        self.entries.push(SysctlEntry::Value {
+            name,
+            mode,
+            data: &*storage as *const T as *mut c_types::c_void,
+            proc_handler: Some(proc_handler::<T>),
+        });
+        self.storages.push(storage);
+        self
+    }
+
+    /// Adds a subdirectory called `name`.
+    pub fn subdir(mut self, name: types::CStr<'static>, dir: SysctlDir) -> SysctlDir {
+        self.entries.push(SysctlEntry::Dir {
+            name: name.as_bytes().into(),
+            dir,
+        });
+        self
+    }
+}
+
+impl Default for SysctlDir {
+    fn default() -> SysctlDir {
+        SysctlDir::new()
+    }
+}
+
+/// Several sysctls registered together with a single `ctl_table_header`, and
+/// unregistered together when dropped.
+pub struct SysctlTable {
+    // Responsible for keeping the names and ctl_tables alive.
+    _names: Vec<Box<[u8]>>,
+    _tables: Vec<Box<[bindings::ctl_table]>>,
+    _storages: Vec<Box<dyn SysctlStorage>>,
+    header: *mut bindings::ctl_table_header,
+}
+
+// This is safe because SysctlTable has no methods, and all of the storage is
+// Sync.
+unsafe impl Sync for SysctlTable {}
+
+impl SysctlTable {
+    /// Registers the contents of `dir` under `path`, e.g. `"net/mymodule"`
+    /// for `/proc/sys/net/mymodule`.
+    pub fn register(
+        path: types::CStr<'static>,
+        dir: SysctlDir,
+    ) -> error::KernelResult<SysctlTable> {
+        // Turn the path into directory entries, so the whole tree is a
+        // single table.
+        let mut root = dir;
+        for component in path.trim_end_matches('\0').rsplit('/') {
+            if component.is_empty() {
+                continue;
+            }
+            let mut name = Vec::with_capacity(component.len() + 1);
+            name.extend_from_slice(component.as_bytes());
+            name.push(0);
+            let mut parent = SysctlDir::new();
+            parent.entries.push(SysctlEntry::Dir {
+                name: name.into_boxed_slice(),
+                dir: root,
+            });
+            root = parent;
+        }
+
+        SysctlTable::register_with(root, |table| unsafe {
+            bindings::register_sysctl_table(table)
+        })
+    }
+
+    /// Registers the contents of `dir` under `path` in the namespace `net`.
+    /// The kernel doesn't support subdirectories in these tables, so `dir`
+    /// can't have any.
+    unsafe fn register_net(
+        net: *mut bindings::net,
+        path: types::CStr<'static>,
+        dir: SysctlDir,
+    ) -> error::KernelResult<SysctlTable> {
+        if dir
+            .entries
+            .iter()
+            .any(|e| matches!(e, SysctlEntry::Dir { .. }))
+        {
+            return Err(error::Error::EINVAL);
+        }
+        SysctlTable::register_with(dir, |table| {
+            bindings::register_net_sysctl(net, path.as_ptr() as *const i8, table)
+        })
+    }
+
+    fn register_with(
+        dir: SysctlDir,
+        register: impl FnOnce(*mut bindings::ctl_table) -> *mut bindings::ctl_table_header,
+    ) -> error::KernelResult<SysctlTable> {
+        let mut table = SysctlTable {
+            _names: vec![],
+            _tables: vec![],
+            _storages: vec![],
+            header: ptr::null_mut(),
+        };
+        let root = table.build(dir)?;
+        table.header = register(root);
+        if table.header.is_null() {
+            return Err(error::Error::ENOMEM);
+        }
+        Ok(table)
+    }
+
+    fn build(&mut self, dir: SysctlDir) -> error::KernelResult<*mut bindings::ctl_table> {
+        self._storages.extend(dir.storages);
+        let mut entries = Vec::with_capacity(dir.entries.len() + 1);
+        for entry in dir.entries {
+            entries.push(match entry {
+                SysctlEntry::Value {
+                    name,
+                    mode,
+                    data,
+                    proc_handler,
+                } => {
+                    if name.contains('/') {
+                        return Err(error::Error::EINVAL);
+                    }
+                    bindings::ctl_table {
+                        procname: name.as_ptr() as *const i8,
+                        mode: mode.as_int(),
+                        data,
+                        proc_handler,
+
+                        maxlen: 0,
+                        child: ptr::null_mut(),
+                        poll: ptr::null_mut(),
+                        extra1: ptr::null_mut(),
+                        extra2: ptr::null_mut(),
+                    }
+                }
+                SysctlEntry::Dir { name, dir } => {
+                    if name.contains(&b'/') {
+                        return Err(error::Error::EINVAL);
+                    }
+                    let procname = name.as_ptr() as *const i8;
+                    self._names.push(name);
+                    bindings::ctl_table {
+                        procname,
+                        mode: 0o555,
+                        child: self.build(dir)?,
+
+                        data: ptr::null_mut(),
+                        maxlen: 0,
+                        proc_handler: None,
+                        poll: ptr::null_mut(),
+                        extra1: ptr::null_mut(),
+                        extra2: ptr::null_mut(),
+                    }
+                }
+            });
+        }
+        entries.push(unsafe { mem::zeroed() });
+        let mut entries = entries.into_boxed_slice();
+        let ptr = entries.as_mut_ptr();
+        self._tables.push(entries);
+        Ok(ptr)
+    }
+}
+
+impl Drop for SysctlTable {
+    fn drop(&mut self) {
+        if !self.header.is_null() {
+            unsafe { bindings::unregister_sysctl_table(self.header) };
+        }
+    }
+}
+
+/// Exposes one field of shared storage as a sysctl, so that a `SysctlDir`
+/// can be built from a struct that the module also keeps a handle on.
+pub struct ArcField<T, S> {
+    owner: Arc<T>,
+    field: fn(&T) -> &S,
+}
+
+impl<T, S> ArcField<T, S> {
+    pub fn new(owner: Arc<T>, field: fn(&T) -> &S) -> ArcField<T, S> {
+        ArcField { owner, field }
+    }
+}
+
+impl<T, S> SysctlStorage for ArcField<T, S>
+where
+    T: Send + Sync,
+    S: SysctlStorage,
+{
+    fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>) {
+        (self.field)(&self.owner).store_value(data)
+    }
+
+    fn read_value(&self) -> error::KernelResult<Vec<u8>> {
+        (self.field)(&self.owner).read_value()
+    }
+
+    fn continues_writes(&self) -> bool {
+        (self.field)(&self.owner).continues_writes()
+    }
+}
+
+extern "C" {
+    fn net_generic_helper(net: *const bindings::net, id: c_types::c_uint) -> *mut c_types::c_void;
+    fn current_net_helper() -> *const bindings::net;
+}
+
+/// Where the kernel stores the pernet id of a `NetSysctl`. Each type needs its
+/// own, declared as a `static`.
+pub struct NetId(cell::UnsafeCell<c_types::c_uint>);
+
+// This is safe because the id is only written by `register_pernet_subsys`,
+// before any callbacks that read it.
+unsafe impl Sync for NetId {}
+
+#[allow(clippy::new_without_default)]
+impl NetId {
+    pub const fn new() -> NetId {
+        NetId(cell::UnsafeCell::new(0))
+    }
+}
+
+/// Sysctls that have their own values in each network namespace, like most
+/// of `/proc/sys/net`. A new instance is created for each namespace and its
+/// table registered under `PATH` there.
+pub trait NetSysctl: Send + Sync + Sized + 'static {
+    /// Where to register, e.g. `net/mymodule`.
+    const PATH: types::CStr<'static>;
+
+    /// Returns the `static` holding this type's pernet id.
+    fn net_id() -> &'static NetId;
+
+    /// Creates the storage for a new namespace.
+    fn new() -> error::KernelResult<Self>;
+
+    /// Describes the table for one namespace, typically with an `ArcField`
+    /// for each entry.
+    fn table(this: &Arc<Self>) -> SysctlDir;
+}
+
+struct PerNet<T> {
+    storage: Arc<T>,
+    _table: SysctlTable,
+}
+
+unsafe fn per_net_slot<T: NetSysctl>(net: *const bindings::net) -> *mut *mut PerNet<T> {
+    net_generic_helper(net, *T::net_id().0.get()) as *mut *mut PerNet<T>
+}
+
+unsafe extern "C" fn net_init_callback<T: NetSysctl>(net: *mut bindings::net) -> c_types::c_int {
+    error::from_kernel_result(|| {
+        let storage = Arc::new(T::new()?);
+        let table = SysctlTable::register_net(net, T::PATH, T::table(&storage))?;
+        let per_net = Box::new(PerNet {
+            storage,
+            _table: table,
+        });
+        *per_net_slot::<T>(net) = Box::into_raw(per_net);
+        Ok(0)
+    })
+}
+
+unsafe extern "C" fn net_exit_callback<T: NetSysctl>(net: *mut bindings::net) {
+    let slot = per_net_slot::<T>(net);
+    let per_net = mem::replace(&mut *slot, ptr::null_mut());
+    if !per_net.is_null() {
+        drop(Box::from_raw(per_net));
+    }
+}
+
+/// Registers a `NetSysctl` in every current and future network namespace,
+/// with `register_pernet_subsys`. Dropping this removes it from all of them.
+pub struct NetSysctlRegistration<T: NetSysctl> {
+    // The kernel keeps a pointer to this until it's unregistered.
+    ops: Box<bindings::pernet_operations>,
+    _phantom: marker::PhantomData<T>,
+}
+
+// This is safe because the only method is current(), which returns an
+// Arc<T>, and T: Send + Sync, and because it can be unregistered from any
+// thread.
+unsafe impl<T: NetSysctl> Send for NetSysctlRegistration<T> {}
+unsafe impl<T: NetSysctl> Sync for NetSysctlRegistration<T> {}
+
+impl<T: NetSysctl> NetSysctlRegistration<T> {
+    pub fn register() -> error::KernelResult<NetSysctlRegistration<T>> {
+        let mut ops: Box<bindings::pernet_operations> = Box::new(Default::default());
+        ops.init = Some(net_init_callback::<T>);
+        ops.exit = Some(net_exit_callback::<T>);
+        ops.id = T::net_id().0.get();
+        ops.size = mem::size_of::<*mut PerNet<T>>();
+        let rc = unsafe { bindings::register_pernet_subsys(&mut *ops) };
+        if rc != 0 {
+            return Err(error::Error::from_kernel_errno(rc));
+        }
+        Ok(NetSysctlRegistration {
+            ops,
+            _phantom: marker::PhantomData,
+        })
+    }
+
+    /// Returns the storage for the network namespace of the current task,
+    /// or `None` if the task is exiting and no longer has one. The storage
+    /// stays valid even if the namespace is destroyed while it's held.
+    pub fn current(&self) -> Option<Arc<T>> {
+        unsafe {
+            // The task's own reference keeps its namespace alive, and only
+            // the task itself can switch to another one.
+            let net = current_net_helper();
+            if net.is_null() {
+                return None;
+            }
+            let per_net = (*per_net_slot::<T>(net)).as_ref()?;
+            Some(per_net.storage.clone())
+        }
+    }
+}
+
+impl<T: NetSysctl> Drop for NetSysctlRegistration<T> {
+    fn drop(&mut self) {
+        unsafe { bindings::unregister_pernet_subsys(&mut *self.ops) };
+    }
+}
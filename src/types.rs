@@ -14,6 +14,50 @@ impl Mode {
     }
 }
 
+/// A user ID as seen by the kernel, corresponding to `kuid_t`. This is the
+/// ID in the initial user namespace, not necessarily the one userspace sees.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Kuid(bindings::uid_t);
+
+impl Kuid {
+    /// The root user of the initial user namespace (`GLOBAL_ROOT_UID`).
+    pub const GLOBAL_ROOT: Kuid = Kuid(0);
+
+    pub(crate) fn from_kernel(uid: bindings::kuid_t) -> Kuid {
+        Kuid(uid.val)
+    }
+
+    pub(crate) fn to_kernel(self) -> bindings::kuid_t {
+        bindings::kuid_t { val: self.0 }
+    }
+
+    pub fn as_raw(&self) -> u32 {
+        self.0
+    }
+}
+
+/// A group ID as seen by the kernel, corresponding to `kgid_t`. This is the
+/// ID in the initial user namespace, not necessarily the one userspace sees.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Kgid(bindings::gid_t);
+
+impl Kgid {
+    /// The root group of the initial user namespace (`GLOBAL_ROOT_GID`).
+    pub const GLOBAL_ROOT: Kgid = Kgid(0);
+
+    pub(crate) fn from_kernel(gid: bindings::kgid_t) -> Kgid {
+        Kgid(gid.val)
+    }
+
+    pub(crate) fn to_kernel(self) -> bindings::kgid_t {
+        bindings::kgid_t { val: self.0 }
+    }
+
+    pub fn as_raw(&self) -> u32 {
+        self.0
+    }
+}
+
 /// A string that is guaranteed to have exactly one NUL byte, which is at the
 /// end. Used for interoperability with kernel APIs that take C strings.
 #[repr(transparent)]
@@ -5,6 +5,7 @@ use core::u32;
 use crate::bindings;
 use crate::c_types;
 use crate::error;
+use crate::io_buffer::{IoBufferReader, IoBufferWriter};
 
 extern "C" {
     fn access_ok_helper(addr: *const c_types::c_void, len: c_types::c_ulong) -> c_types::c_int;
@@ -139,6 +140,16 @@ impl UserSlicePtrReader {
     }
 }
 
+impl IoBufferReader for UserSlicePtrReader {
+    fn len(&self) -> usize {
+        UserSlicePtrReader::len(self)
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> error::KernelResult<()> {
+        UserSlicePtrReader::read(self, data)
+    }
+}
+
 pub struct UserSlicePtrWriter(*mut c_types::c_void, usize);
 
 impl UserSlicePtrWriter {
@@ -172,3 +183,13 @@ impl UserSlicePtrWriter {
         Ok(())
     }
 }
+
+impl IoBufferWriter for UserSlicePtrWriter {
+    fn len(&self) -> usize {
+        UserSlicePtrWriter::len(self)
+    }
+
+    fn write(&mut self, data: &[u8]) -> error::KernelResult<()> {
+        UserSlicePtrWriter::write(self, data)
+    }
+}
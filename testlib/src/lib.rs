@@ -96,3 +96,75 @@ pub fn mknod(path: &PathBuf, major: libc::dev_t, minor: libc::dev_t) -> UnlinkOn
         .unwrap();
     return UnlinkOnDrop { path };
 }
+
+pub struct Mount {
+    path: PathBuf,
+}
+
+impl Mount {
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        let status = Command::new("sudo")
+            .arg("umount")
+            .arg(self.path.to_str().unwrap())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
+
+pub fn mount(fs_type: &str, source: &str, options: &str) -> Mount {
+    let path = TempDir::new().unwrap().into_path();
+    let mut command = Command::new("sudo");
+    command.arg("mount").arg("-t").arg(fs_type);
+    if !options.is_empty() {
+        command.arg("-o").arg(options);
+    }
+    let status = command
+        .arg(source)
+        .arg(path.to_str().unwrap())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    return Mount { path };
+}
+
+pub struct LoopDevice {
+    path: String,
+}
+
+impl LoopDevice {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Drop for LoopDevice {
+    fn drop(&mut self) {
+        let status = Command::new("sudo")
+            .arg("losetup")
+            .arg("--detach")
+            .arg(&self.path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
+
+pub fn loop_device(image: &PathBuf) -> LoopDevice {
+    let output = Command::new("sudo")
+        .arg("losetup")
+        .arg("--find")
+        .arg("--show")
+        .arg(image.to_str().unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let path = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    return LoopDevice { path };
+}
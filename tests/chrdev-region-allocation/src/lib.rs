@@ -2,8 +2,21 @@
 
 use linux_kernel_module::{self, cstr};
 
+struct DummyFile;
+
+impl linux_kernel_module::file_operations::FileOperations for DummyFile {}
+
+impl linux_kernel_module::file_operations::FileOpen for DummyFile {
+    fn open() -> linux_kernel_module::KernelResult<Self> {
+        Ok(DummyFile)
+    }
+}
+
 struct ChrdevRegionAllocationTestModule {
     _chrdev_reg: linux_kernel_module::chrdev::Registration,
+    _fixed_reg: linux_kernel_module::chrdev::Registration,
+    _single_reg: linux_kernel_module::chrdev::Registration,
+    _dynamic_reg: linux_kernel_module::chrdev::Registration,
 }
 
 impl linux_kernel_module::KernelModule for ChrdevRegionAllocationTestModule {
@@ -12,8 +25,48 @@ impl linux_kernel_module::KernelModule for ChrdevRegionAllocationTestModule {
             linux_kernel_module::chrdev::builder(cstr!("chrdev-region-allocation-tests"), 0..1)?
                 .build()?;
 
+        // 60 is reserved for local and experimental use.
+        let fixed_reg =
+            linux_kernel_module::chrdev::builder(cstr!("chrdev-region-allocation-fixed"), 0..2)?
+                .major(60)
+                .register_device::<DummyFile>()
+                .build()?;
+
+        let single_reg = linux_kernel_module::chrdev::register_single::<DummyFile>(cstr!(
+            "chrdev-region-allocation-single"
+        ))?;
+
+        // Registering more devices than minors must fail rather than panic.
+        let overfull =
+            linux_kernel_module::chrdev::builder(cstr!("chrdev-region-allocation-overfull"), 0..1)?
+                .register_device::<DummyFile>()
+                .register_device::<DummyFile>()
+                .build();
+        if overfull.is_ok() {
+            return Err(linux_kernel_module::Error::EINVAL);
+        }
+
+        // Starts with minors 0 and 1, then ends up with 1 and 3.
+        let mut dynamic_reg =
+            linux_kernel_module::chrdev::builder(cstr!("chrdev-region-allocation-dynamic"), 0..4)?
+                .register_device::<DummyFile>()
+                .register_device::<DummyFile>()
+                .node_mode(linux_kernel_module::Mode::from_int(0o666))
+                .build()?;
+        dynamic_reg.add_device::<DummyFile>(3)?;
+        dynamic_reg.remove_device(0)?;
+        if dynamic_reg.add_device::<DummyFile>(1).is_ok()
+            || dynamic_reg.add_device::<DummyFile>(4).is_ok()
+            || dynamic_reg.remove_device(2).is_ok()
+        {
+            return Err(linux_kernel_module::Error::EINVAL);
+        }
+
         Ok(ChrdevRegionAllocationTestModule {
             _chrdev_reg: chrdev_reg,
+            _fixed_reg: fixed_reg,
+            _single_reg: single_reg,
+            _dynamic_reg: dynamic_reg,
         })
     }
 }
@@ -22,3 +22,39 @@ fn test_proc_devices() {
         .find(|l| l.ends_with("chrdev-region-allocation-tests"))
         .is_none());
 }
+
+#[test]
+fn test_fixed_major() {
+    with_kernel_module(|| {
+        let devices = fs::read_to_string("/proc/devices").unwrap();
+        assert!(devices
+            .lines()
+            .any(|l| l.trim() == "60 chrdev-region-allocation-fixed"));
+    });
+}
+
+#[test]
+fn test_single_and_overfull() {
+    with_kernel_module(|| {
+        let devices = fs::read_to_string("/proc/devices").unwrap();
+        assert!(devices
+            .lines()
+            .any(|l| l.ends_with(" chrdev-region-allocation-single")));
+        assert!(!devices
+            .lines()
+            .any(|l| l.ends_with(" chrdev-region-allocation-overfull")));
+    });
+}
+
+#[test]
+fn test_dynamic_minors() {
+    with_kernel_module(|| {
+        for (minor, present) in &[(0, false), (1, true), (2, false), (3, true)] {
+            let p = format!("/dev/chrdev-region-allocation-dynamic{}", minor);
+            assert_eq!(fs::metadata(&p).is_ok(), *present, "{}", p);
+            if *present {
+                fs::File::open(&p).unwrap();
+            }
+        }
+    });
+}
@@ -3,6 +3,7 @@
 extern crate alloc;
 
 use alloc::string::ToString;
+use core::fmt::Write;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use linux_kernel_module::{self, cstr};
@@ -10,36 +11,36 @@ use linux_kernel_module::{self, cstr};
 struct CycleFile;
 
 impl linux_kernel_module::file_operations::FileOperations for CycleFile {
-    fn open() -> linux_kernel_module::KernelResult<Self> {
-        Ok(CycleFile)
-    }
-
     const READ: linux_kernel_module::file_operations::ReadFn<Self> = Some(
         |_this: &Self,
          _file: &linux_kernel_module::file_operations::File,
-         buf: &mut linux_kernel_module::user_ptr::UserSlicePtrWriter,
-         offset: u64|
-         -> linux_kernel_module::KernelResult<()> {
+         buf: &mut dyn linux_kernel_module::io_buffer::IoBufferWriter,
+         offset: &mut u64|
+         -> linux_kernel_module::KernelResult<usize> {
+            let len = buf.len();
             for c in b"123456789"
                 .iter()
                 .cycle()
-                .skip((offset % 9) as _)
-                .take(buf.len())
+                .skip((*offset % 9) as _)
+                .take(len)
             {
                 buf.write(&[*c])?;
             }
-            Ok(())
+            *offset += len as u64;
+            Ok(len)
         },
     );
 }
 
-struct SeekFile;
-
-impl linux_kernel_module::file_operations::FileOperations for SeekFile {
+impl linux_kernel_module::file_operations::FileOpen for CycleFile {
     fn open() -> linux_kernel_module::KernelResult<Self> {
-        Ok(SeekFile)
+        Ok(CycleFile)
     }
+}
+
+struct SeekFile;
 
+impl linux_kernel_module::file_operations::FileOperations for SeekFile {
     const SEEK: linux_kernel_module::file_operations::SeekFn<Self> = Some(
         |_this: &Self,
          _file: &linux_kernel_module::file_operations::File,
@@ -48,41 +49,270 @@ impl linux_kernel_module::file_operations::FileOperations for SeekFile {
     );
 }
 
+impl linux_kernel_module::file_operations::FileOpen for SeekFile {
+    fn open() -> linux_kernel_module::KernelResult<Self> {
+        Ok(SeekFile)
+    }
+}
+
 struct WriteFile {
     written: AtomicUsize,
 }
 
 impl linux_kernel_module::file_operations::FileOperations for WriteFile {
+    const READ: linux_kernel_module::file_operations::ReadFn<Self> = Some(
+        |this: &Self,
+         _file: &linux_kernel_module::file_operations::File,
+         buf: &mut dyn linux_kernel_module::io_buffer::IoBufferWriter,
+         offset: &mut u64|
+         -> linux_kernel_module::KernelResult<usize> {
+            let val = this.written.load(Ordering::SeqCst).to_string();
+            buf.write(val.as_bytes())?;
+            *offset += val.len() as u64;
+            Ok(val.len())
+        },
+    );
+
+    const WRITE: linux_kernel_module::file_operations::WriteFn<Self> = Some(
+        |this: &Self,
+         _file: &linux_kernel_module::file_operations::File,
+         buf: &mut dyn linux_kernel_module::io_buffer::IoBufferReader,
+         offset: &mut u64|
+         -> linux_kernel_module::KernelResult<usize> {
+            let data = buf.read_all()?;
+            this.written.fetch_add(data.len(), Ordering::SeqCst);
+            *offset += data.len() as u64;
+            Ok(data.len())
+        },
+    );
+}
+
+impl linux_kernel_module::file_operations::FileOpen for WriteFile {
     fn open() -> linux_kernel_module::KernelResult<Self> {
         Ok(WriteFile {
             written: AtomicUsize::new(0),
         })
     }
+}
+
+struct CredentialsFile;
+
+impl linux_kernel_module::file_operations::FileOperations for CredentialsFile {
+    const READ: linux_kernel_module::file_operations::ReadFn<Self> = Some(
+        |_this: &Self,
+         file: &linux_kernel_module::file_operations::File,
+         buf: &mut dyn linux_kernel_module::io_buffer::IoBufferWriter,
+         offset: &mut u64|
+         -> linux_kernel_module::KernelResult<usize> {
+            if *offset != 0 {
+                return Ok(0);
+            }
+            let creds = file.credentials();
+            let writable = file
+                .mode()
+                .contains(linux_kernel_module::file_operations::FileMode::WRITE);
+            let val = alloc::format!(
+                "{} {} {} {}",
+                creds.euid().as_raw(),
+                creds.egid().as_raw(),
+                writable,
+                file.flags()
+                    .contains(linux_kernel_module::file_operations::FileFlags::APPEND)
+            );
+            buf.write(val.as_bytes())?;
+            *offset += val.len() as u64;
+            Ok(val.len())
+        },
+    );
+}
+
+impl linux_kernel_module::file_operations::FileOpen for CredentialsFile {
+    fn open() -> linux_kernel_module::KernelResult<Self> {
+        Ok(CredentialsFile)
+    }
+}
+
+struct RecordFile;
+
+impl linux_kernel_module::file_operations::FileOperations for RecordFile {
+    const READ: linux_kernel_module::file_operations::ReadFn<Self> = Some(
+        |_this: &Self,
+         _file: &linux_kernel_module::file_operations::File,
+         buf: &mut dyn linux_kernel_module::io_buffer::IoBufferWriter,
+         _offset: &mut u64|
+         -> linux_kernel_module::KernelResult<usize> {
+            let record = b"record";
+            if buf.len() < record.len() {
+                return Err(linux_kernel_module::Error::EINVAL);
+            }
+            buf.write(record)?;
+            Ok(record.len())
+        },
+    );
+
+    const STREAM: bool = true;
+}
+
+impl linux_kernel_module::file_operations::FileOpen for RecordFile {
+    fn open() -> linux_kernel_module::KernelResult<Self> {
+        Ok(RecordFile)
+    }
+}
+
+struct SyncFile {
+    fsyncs: AtomicUsize,
+    flushes: AtomicUsize,
+}
 
+impl linux_kernel_module::file_operations::FileOperations for SyncFile {
     const READ: linux_kernel_module::file_operations::ReadFn<Self> = Some(
         |this: &Self,
          _file: &linux_kernel_module::file_operations::File,
-         buf: &mut linux_kernel_module::user_ptr::UserSlicePtrWriter,
-         _offset: u64|
-         -> linux_kernel_module::KernelResult<()> {
-            let val = this.written.load(Ordering::SeqCst).to_string();
+         buf: &mut dyn linux_kernel_module::io_buffer::IoBufferWriter,
+         _offset: &mut u64|
+         -> linux_kernel_module::KernelResult<usize> {
+            let val = alloc::format!(
+                "{} {}",
+                this.fsyncs.load(Ordering::SeqCst),
+                this.flushes.load(Ordering::SeqCst)
+            );
             buf.write(val.as_bytes())?;
+            Ok(val.len())
+        },
+    );
+
+    const FSYNC: linux_kernel_module::file_operations::FsyncFn<Self> = Some(
+        |this: &Self,
+         _file: &linux_kernel_module::file_operations::File,
+         _start: u64,
+         _end: u64,
+         _datasync: bool|
+         -> linux_kernel_module::KernelResult<()> {
+            this.fsyncs.fetch_add(1, Ordering::SeqCst);
             Ok(())
         },
     );
 
-    const WRITE: linux_kernel_module::file_operations::WriteFn<Self> = Some(
+    const FLUSH: linux_kernel_module::file_operations::FlushFn<Self> = Some(
         |this: &Self,
-         buf: &mut linux_kernel_module::user_ptr::UserSlicePtrReader,
-         _offset: u64|
+         _file: &linux_kernel_module::file_operations::File|
          -> linux_kernel_module::KernelResult<()> {
-            let data = buf.read_all()?;
-            this.written.fetch_add(data.len(), Ordering::SeqCst);
+            this.flushes.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        },
+    );
+
+    const SHOW_FDINFO: linux_kernel_module::file_operations::ShowFdinfoFn<Self> = Some(
+        |this: &Self,
+         _file: &linux_kernel_module::file_operations::File,
+         m: &mut linux_kernel_module::seq_file::SeqWriter|
+         -> linux_kernel_module::KernelResult<()> {
+            writeln!(m, "fsyncs:\t{}", this.fsyncs.load(Ordering::SeqCst))?;
+            writeln!(m, "flushes:\t{}", this.flushes.load(Ordering::SeqCst))?;
             Ok(())
         },
     );
 }
 
+impl linux_kernel_module::file_operations::FileOpen for SyncFile {
+    fn open() -> linux_kernel_module::KernelResult<Self> {
+        Ok(SyncFile {
+            fsyncs: AtomicUsize::new(0),
+            flushes: AtomicUsize::new(0),
+        })
+    }
+}
+
+struct SharedLockFile;
+
+impl linux_kernel_module::file_operations::FileOperations for SharedLockFile {
+    const LOCK: linux_kernel_module::file_operations::LockFn<Self> = Some(
+        |_this: &Self,
+         _file: &linux_kernel_module::file_operations::File,
+         _cmd: linux_kernel_module::file_lock::LockCommand,
+         lock: &linux_kernel_module::file_lock::FileLock|
+         -> linux_kernel_module::KernelResult<()> {
+            match lock.lock_type() {
+                linux_kernel_module::file_lock::LockType::Write => {
+                    Err(linux_kernel_module::Error::EAGAIN)
+                }
+                _ => Ok(()),
+            }
+        },
+    );
+
+    const FLOCK: linux_kernel_module::file_operations::LockFn<Self> = Self::LOCK;
+}
+
+impl linux_kernel_module::file_operations::FileOpen for SharedLockFile {
+    fn open() -> linux_kernel_module::KernelResult<Self> {
+        Ok(SharedLockFile)
+    }
+}
+
+struct AsyncFile {
+    queue: linux_kernel_module::file_operations::FasyncQueue,
+}
+
+impl AsyncFile {
+    fn queue(&self) -> &linux_kernel_module::file_operations::FasyncQueue {
+        &self.queue
+    }
+}
+
+impl linux_kernel_module::file_operations::FileOperations for AsyncFile {
+    // Every write tells the files that asked for SIGIO that there is
+    // something to read.
+    const WRITE: linux_kernel_module::file_operations::WriteFn<Self> = Some(
+        |this: &Self,
+         _file: &linux_kernel_module::file_operations::File,
+         buf: &mut dyn linux_kernel_module::io_buffer::IoBufferReader,
+         _offset: &mut u64|
+         -> linux_kernel_module::KernelResult<usize> {
+            let data = buf.read_all()?;
+            this.queue.notify_readable();
+            Ok(data.len())
+        },
+    );
+
+    const FASYNC: linux_kernel_module::file_operations::FasyncFn<Self> = Some(Self::queue);
+}
+
+impl linux_kernel_module::file_operations::FileOpen for AsyncFile {
+    fn open() -> linux_kernel_module::KernelResult<Self> {
+        Ok(AsyncFile {
+            queue: linux_kernel_module::file_operations::FasyncQueue::new(),
+        })
+    }
+}
+
+struct NamedFile {
+    name: &'static str,
+}
+
+impl linux_kernel_module::file_operations::FileOperations for NamedFile {
+    const READ: linux_kernel_module::file_operations::ReadFn<Self> = Some(
+        |this: &Self,
+         _file: &linux_kernel_module::file_operations::File,
+         buf: &mut dyn linux_kernel_module::io_buffer::IoBufferWriter,
+         offset: &mut u64|
+         -> linux_kernel_module::KernelResult<usize> {
+            let data = this.name.as_bytes();
+            let start = core::cmp::min(*offset as usize, data.len());
+            let len = core::cmp::min(buf.len(), data.len() - start);
+            buf.write(&data[start..start + len])?;
+            *offset += len as u64;
+            Ok(len)
+        },
+    );
+}
+
+impl linux_kernel_module::file_operations::FileOpener<&'static str> for NamedFile {
+    fn open_with(name: &&'static str) -> linux_kernel_module::KernelResult<Self> {
+        Ok(NamedFile { name: *name })
+    }
+}
+
 struct ChrdevTestModule {
     _chrdev_registration: linux_kernel_module::chrdev::Registration,
 }
@@ -90,10 +320,18 @@ struct ChrdevTestModule {
 impl linux_kernel_module::KernelModule for ChrdevTestModule {
     fn init() -> linux_kernel_module::KernelResult<Self> {
         let chrdev_registration =
-            linux_kernel_module::chrdev::builder(cstr!("chrdev-tests"), 0..3)?
+            linux_kernel_module::chrdev::builder(cstr!("chrdev-tests"), 0..10)?
                 .register_device::<CycleFile>()
                 .register_device::<SeekFile>()
                 .register_device::<WriteFile>()
+                .register_device::<CredentialsFile>()
+                .register_device::<RecordFile>()
+                .register_device::<SyncFile>()
+                .register_device::<SharedLockFile>()
+                .register_device_with::<NamedFile, _>("first")
+                .register_device_with::<NamedFile, _>("second")
+                .register_device::<AsyncFile>()
+                .node_mode(linux_kernel_module::Mode::from_int(0o666))
                 .build()?;
         Ok(ChrdevTestModule {
             _chrdev_registration: chrdev_registration,
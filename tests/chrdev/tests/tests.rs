@@ -1,6 +1,12 @@
 use std::fs;
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::os::unix::prelude::FileExt;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use kernel_module_testlib::*;
 
@@ -8,6 +14,19 @@ const DEVICE_NAME: &'static str = "chrdev-tests";
 const READ_FILE_MINOR: libc::dev_t = 0;
 const SEEK_FILE_MINOR: libc::dev_t = 1;
 const WRITE_FILE_MINOR: libc::dev_t = 2;
+const CREDENTIALS_FILE_MINOR: libc::dev_t = 3;
+const RECORD_FILE_MINOR: libc::dev_t = 4;
+const SYNC_FILE_MINOR: libc::dev_t = 5;
+const SHARED_LOCK_FILE_MINOR: libc::dev_t = 6;
+const FIRST_NAMED_FILE_MINOR: libc::dev_t = 7;
+const SECOND_NAMED_FILE_MINOR: libc::dev_t = 8;
+const ASYNC_FILE_MINOR: libc::dev_t = 9;
+
+fn pipe() -> (fs::File, fs::File) {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    unsafe { (fs::File::from_raw_fd(fds[0]), fs::File::from_raw_fd(fds[1])) }
+}
 
 #[test]
 fn test_mknod() {
@@ -17,6 +36,28 @@ fn test_mknod() {
     });
 }
 
+#[test]
+fn test_device_nodes() {
+    with_kernel_module(|| {
+        let major = get_device_major_number(DEVICE_NAME);
+        let p = format!("/dev/{}{}", DEVICE_NAME, READ_FILE_MINOR);
+        let metadata = fs::metadata(&p).unwrap();
+        assert!(metadata.file_type().is_char_device());
+        assert_eq!(metadata.rdev(), (major << 8) | READ_FILE_MINOR);
+        assert_eq!(metadata.mode() & 0o777, 0o666);
+        assert_eq!(metadata.uid(), 0);
+
+        let mut f = fs::File::open(&p).unwrap();
+        let mut data = [0; 3];
+        f.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"123");
+
+        assert!(fs::metadata(format!("/dev/{}{}", DEVICE_NAME, SHARED_LOCK_FILE_MINOR)).is_ok());
+    });
+
+    assert!(fs::metadata(format!("/dev/{}{}", DEVICE_NAME, READ_FILE_MINOR)).is_err());
+}
+
 #[test]
 fn test_read() {
     with_kernel_module(|| {
@@ -120,6 +161,9 @@ fn test_lseek() {
         assert_eq!(f.seek(SeekFrom::Start(12)).unwrap(), 1234);
         assert_eq!(f.seek(SeekFrom::End(-12)).unwrap(), 1234);
         assert_eq!(f.seek(SeekFrom::Current(12)).unwrap(), 1234);
+        // The position returned by SEEK is the file's new position.
+        let fdinfo = fs::read_to_string(format!("/proc/self/fdinfo/{}", f.as_raw_fd())).unwrap();
+        assert!(fdinfo.lines().any(|l| l == "pos:\t1234"));
 
         assert_eq!(
             f.seek(SeekFrom::Start(u64::max_value()))
@@ -171,3 +215,279 @@ fn test_write() {
         assert_eq!(&buf, b"8");
     })
 }
+
+#[test]
+fn test_file_credentials_and_flags() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, CREDENTIALS_FILE_MINOR);
+
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+
+        let data = fs::read_to_string(&p).unwrap();
+        assert_eq!(data, format!("{} {} false false", euid, egid));
+
+        let mut f = fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&p)
+            .unwrap();
+        let mut data = String::new();
+        f.read_to_string(&mut data).unwrap();
+        assert_eq!(data, format!("{} {} true true", euid, egid));
+    });
+}
+
+#[test]
+fn test_read_records_without_position() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, RECORD_FILE_MINOR);
+
+        let mut f = fs::File::open(&p).unwrap();
+        let mut data = [0; 100];
+        assert_eq!(f.read(&mut data).unwrap(), 6);
+        assert_eq!(&data[..6], b"record");
+        assert_eq!(f.read(&mut data).unwrap(), 6);
+        assert_eq!(&data[..6], b"record");
+
+        assert_eq!(
+            f.read_at(&mut data, 0).unwrap_err().raw_os_error().unwrap(),
+            libc::ESPIPE
+        );
+    });
+}
+
+#[test]
+fn test_fsync_and_flush() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, SYNC_FILE_MINOR);
+
+        let mut f = fs::File::open(&p).unwrap();
+        f.sync_all().unwrap();
+        f.sync_data().unwrap();
+        drop(f.try_clone().unwrap());
+
+        let mut data = [0; 16];
+        let n = f.read(&mut data).unwrap();
+        assert_eq!(&data[..n], b"2 1");
+    });
+}
+
+#[test]
+fn test_show_fdinfo() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, SYNC_FILE_MINOR);
+
+        let f = fs::File::open(&p).unwrap();
+        f.sync_all().unwrap();
+
+        let fdinfo = fs::read_to_string(format!("/proc/self/fdinfo/{}", f.as_raw_fd())).unwrap();
+        assert!(fdinfo.contains("fsyncs:\t1\n"));
+        assert!(fdinfo.contains("flushes:\t0\n"));
+    });
+}
+
+static SIGIO_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigio(_signal: libc::c_int) {
+    SIGIO_RECEIVED.store(true, Ordering::SeqCst);
+}
+
+// Waits a little while for SIGIO, which may be handled on another thread.
+fn wait_for_sigio() -> bool {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        if SIGIO_RECEIVED.swap(false, Ordering::SeqCst) {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+fn set_async(f: &fs::File, on: bool) {
+    unsafe {
+        let flags = libc::fcntl(f.as_raw_fd(), libc::F_GETFL);
+        let flags = if on {
+            flags | libc::O_ASYNC
+        } else {
+            flags & !libc::O_ASYNC
+        };
+        assert_eq!(libc::fcntl(f.as_raw_fd(), libc::F_SETFL, flags), 0);
+    }
+}
+
+#[test]
+fn test_fasync() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, ASYNC_FILE_MINOR);
+
+        SIGIO_RECEIVED.store(false, Ordering::SeqCst);
+        unsafe { libc::signal(libc::SIGIO, handle_sigio as libc::sighandler_t) };
+
+        let mut f = fs::OpenOptions::new().write(true).open(&p).unwrap();
+        assert_eq!(
+            unsafe { libc::fcntl(f.as_raw_fd(), libc::F_SETOWN, libc::getpid()) },
+            0
+        );
+        f.write_all(b"x").unwrap();
+        assert!(!wait_for_sigio());
+
+        set_async(&f, true);
+        f.write_all(b"x").unwrap();
+        assert!(wait_for_sigio());
+
+        set_async(&f, false);
+        f.write_all(b"x").unwrap();
+        assert!(!wait_for_sigio());
+
+        // The file has left the queue, so nothing can send SIGIO any more.
+        drop(f);
+        unsafe { libc::signal(libc::SIGIO, libc::SIG_DFL) };
+    });
+}
+
+#[test]
+fn test_splice_read() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, READ_FILE_MINOR);
+
+        let f = fs::File::open(&p).unwrap();
+        let (mut pipe_read, pipe_write) = pipe();
+        let n = unsafe {
+            libc::splice(
+                f.as_raw_fd(),
+                ptr::null_mut(),
+                pipe_write.as_raw_fd(),
+                ptr::null_mut(),
+                12,
+                0,
+            )
+        };
+        assert_eq!(n, 12);
+
+        let mut data = [0; 12];
+        pipe_read.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"123456789123");
+    });
+}
+
+#[test]
+fn test_splice_write() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, WRITE_FILE_MINOR);
+
+        let mut f = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&p)
+            .unwrap();
+        let (pipe_read, mut pipe_write) = pipe();
+        pipe_write.write_all(&[1, 2, 3, 4, 5]).unwrap();
+        let n = unsafe {
+            libc::splice(
+                pipe_read.as_raw_fd(),
+                ptr::null_mut(),
+                f.as_raw_fd(),
+                ptr::null_mut(),
+                5,
+                0,
+            )
+        };
+        assert_eq!(n, 5);
+
+        let mut buf = [0; 1];
+        f.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"5");
+    });
+}
+
+fn fcntl_lock(f: &fs::File, lock_type: libc::c_int) -> std::io::Result<()> {
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = lock_type as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    lock.l_start = 0;
+    lock.l_len = 10;
+    if unsafe { libc::fcntl(f.as_raw_fd(), libc::F_SETLK, &lock) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn flock(f: &fs::File, operation: libc::c_int) -> std::io::Result<()> {
+    if unsafe { libc::flock(f.as_raw_fd(), operation | libc::LOCK_NB) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_lock() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, SHARED_LOCK_FILE_MINOR);
+
+        let f = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&p)
+            .unwrap();
+        fcntl_lock(&f, libc::F_RDLCK).unwrap();
+        assert_eq!(
+            fcntl_lock(&f, libc::F_WRLCK)
+                .unwrap_err()
+                .raw_os_error()
+                .unwrap(),
+            libc::EAGAIN
+        );
+        fcntl_lock(&f, libc::F_UNLCK).unwrap();
+    });
+}
+
+#[test]
+fn test_flock() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, SHARED_LOCK_FILE_MINOR);
+
+        let f = fs::File::open(&p).unwrap();
+        flock(&f, libc::LOCK_SH).unwrap();
+        assert_eq!(
+            flock(&f, libc::LOCK_EX)
+                .unwrap_err()
+                .raw_os_error()
+                .unwrap(),
+            libc::EAGAIN
+        );
+        flock(&f, libc::LOCK_UN).unwrap();
+    });
+}
+
+#[test]
+fn test_per_minor_data() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, FIRST_NAMED_FILE_MINOR);
+        assert_eq!(fs::read_to_string(&p).unwrap(), "first");
+
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, SECOND_NAMED_FILE_MINOR);
+        assert_eq!(fs::read_to_string(&p).unwrap(), "second");
+    });
+}
@@ -0,0 +1,265 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::convert::TryInto;
+
+use linux_kernel_module::file_operations::{
+    DirContext, File, FileOpen, FileOperations, IterateFn, ReadFn,
+};
+use linux_kernel_module::filesystem::{
+    self, BlockDevice, Dentry, FileSystem, FileSystemFlags, Inode, InodeRef, NewInode, SuperBlock,
+};
+use linux_kernel_module::inode_operations::{InodeOperations, LookupFn};
+use linux_kernel_module::io_buffer::IoBufferWriter;
+use linux_kernel_module::super_operations::{EvictInodeFn, StatFs, StatfsFn, SuperOperations};
+use linux_kernel_module::{self, cstr, println, CStr, Error, KernelResult, Kgid, Kuid, Mode};
+
+// The image format: block 0 holds the magic number, a little-endian u32
+// count of files, and then an entry for each file. An entry is a NUL-padded
+// name followed by the file's first block and its length in bytes, both
+// little-endian u32s. Each file's contents are in consecutive blocks.
+const BLOCK_SIZE: u32 = 1024;
+const MAGIC: &[u8] = b"RUSTIMG1";
+const STATFS_MAGIC: u64 = 0x5255_5354;
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 32;
+const NAME_SIZE: usize = 24;
+
+const S_IFDIR: u16 = 0o040000;
+const S_IFREG: u16 = 0o100000;
+
+const ROOT_INO: u64 = 1;
+
+struct Entry {
+    name: Vec<u8>,
+    first_block: u64,
+    len: u64,
+}
+
+impl Entry {
+    fn ino(index: usize) -> u64 {
+        ROOT_INO + 1 + index as u64
+    }
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes(data[..4].try_into().unwrap())
+}
+
+fn read_entries(dev: &BlockDevice) -> KernelResult<Vec<Entry>> {
+    let header = dev.read(0)?;
+    let data = header.data();
+    if &data[..MAGIC.len()] != MAGIC {
+        return Err(Error::EINVAL);
+    }
+    let count = read_u32(&data[MAGIC.len()..]) as usize;
+    if count > (data.len() - HEADER_SIZE) / ENTRY_SIZE {
+        return Err(Error::EINVAL);
+    }
+    let mut entries = Vec::new();
+    for i in 0..count {
+        let raw = &data[HEADER_SIZE + i * ENTRY_SIZE..][..ENTRY_SIZE];
+        let name_len = raw[..NAME_SIZE]
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(NAME_SIZE);
+        let name = &raw[..name_len];
+        if name.is_empty() || name == b"." || name == b".." || name.contains(&b'/') {
+            return Err(Error::EINVAL);
+        }
+        let first_block = u64::from(read_u32(&raw[NAME_SIZE..]));
+        let len = u64::from(read_u32(&raw[NAME_SIZE + 4..]));
+        let blocks = (len + u64::from(BLOCK_SIZE) - 1) / u64::from(BLOCK_SIZE);
+        if first_block == 0 || first_block + blocks > dev.num_blocks() {
+            return Err(Error::EINVAL);
+        }
+        entries.push(Entry {
+            name: name.to_vec(),
+            first_block,
+            len,
+        });
+    }
+    Ok(entries)
+}
+
+// The value stored in each inode.
+enum ImageNode {
+    Root(Arc<Vec<Entry>>),
+    File { first_block: u64, len: u64 },
+}
+
+impl InodeOperations for ImageNode {
+    const LOOKUP: LookupFn<Self> = Some(
+        |this: &Self, dir: &Inode, dentry: &Dentry| -> KernelResult<Option<InodeRef>> {
+            let entries = match this {
+                ImageNode::Root(entries) => entries,
+                ImageNode::File { .. } => return Err(Error::EINVAL),
+            };
+            let index = match entries.iter().position(|e| &e.name[..] == dentry.name()) {
+                Some(index) => index,
+                None => return Ok(None),
+            };
+            let entry = &entries[index];
+            let inode = dir.get_inode::<ImageNode, ImageFile>(Entry::ino(index), || {
+                Ok(NewInode {
+                    mode: Mode::from_int(S_IFREG | 0o444),
+                    uid: Kuid::GLOBAL_ROOT,
+                    gid: Kgid::GLOBAL_ROOT,
+                    nlink: 1,
+                    size: entry.len as i64,
+                    data: ImageNode::File {
+                        first_block: entry.first_block,
+                        len: entry.len,
+                    },
+                })
+            })?;
+            Ok(Some(inode))
+        },
+    );
+}
+
+struct RootDir;
+
+impl FileOperations for RootDir {
+    const ITERATE: IterateFn<Self> = Some(
+        |_this: &Self, file: &File, ctx: &mut DirContext| -> KernelResult<()> {
+            if !ctx.emit_dots(file) {
+                return Ok(());
+            }
+            let inode = file.inode();
+            let entries = match inode.data::<ImageNode>() {
+                Some(ImageNode::Root(entries)) => entries,
+                _ => return Err(Error::EINVAL),
+            };
+            let start = ctx.pos() as usize - 2;
+            let mode = Mode::from_int(S_IFREG | 0o444);
+            for (i, entry) in entries.iter().enumerate().skip(start) {
+                if !ctx.emit(&entry.name, Entry::ino(i), &mode) {
+                    break;
+                }
+                ctx.set_pos(i as u64 + 3);
+            }
+            Ok(())
+        },
+    );
+}
+
+impl FileOpen for RootDir {
+    fn open() -> KernelResult<Self> {
+        Ok(RootDir)
+    }
+}
+
+struct ImageFile;
+
+impl FileOperations for ImageFile {
+    const READ: ReadFn<Self> = Some(
+        |_this: &Self,
+         file: &File,
+         buf: &mut dyn IoBufferWriter,
+         offset: &mut u64|
+         -> KernelResult<usize> {
+            let inode = file.inode();
+            let (first_block, len) = match inode.data::<ImageNode>() {
+                Some(ImageNode::File { first_block, len }) => (*first_block, *len),
+                _ => return Err(Error::EINVAL),
+            };
+            let dev = inode.block_device().ok_or(Error::EIO)?;
+            let block_size = u64::from(dev.block_size());
+            let start = min(*offset, len);
+            let end = min(start + buf.len() as u64, len);
+            let mut pos = start;
+            while pos < end {
+                let block = dev.read(first_block + pos / block_size)?;
+                let within = pos % block_size;
+                let n = min(end - pos, block_size - within);
+                buf.write(&block.data()[within as usize..(within + n) as usize])?;
+                pos += n;
+            }
+            *offset = end;
+            Ok((end - start) as usize)
+        },
+    );
+}
+
+impl FileOpen for ImageFile {
+    fn open() -> KernelResult<Self> {
+        Ok(ImageFile)
+    }
+}
+
+struct ImageFS {
+    blocks: u64,
+    files: u64,
+}
+
+impl SuperOperations for ImageFS {
+    const STATFS: StatfsFn<Self> = Some(|this: &Self, buf: &mut StatFs| -> KernelResult<()> {
+        buf.set_blocks(this.blocks);
+        buf.set_files(this.files, 0);
+        Ok(())
+    });
+
+    const EVICT_INODE: EvictInodeFn<Self> = Some(|_this: &Self, inode: &Inode| {
+        println!("rust-imagefs: evicting inode {}", inode.ino());
+    });
+}
+
+impl FileSystem for ImageFS {
+    const NAME: CStr<'static> = cstr!("rust-imagefs");
+    const FLAGS: FileSystemFlags = FileSystemFlags::REQUIRES_DEV;
+    type Options = ();
+
+    fn fill_super(sb: &mut SuperBlock<Self>, _options: &(), silent: bool) -> KernelResult<Self> {
+        sb.set_block_size(BLOCK_SIZE)?;
+        sb.set_magic(STATFS_MAGIC);
+        let dev = sb.block_device().ok_or(Error::EINVAL)?;
+        let blocks = dev.num_blocks();
+        let entries = match read_entries(&dev) {
+            Ok(entries) => Arc::new(entries),
+            Err(e) => {
+                if !silent {
+                    println!("rust-imagefs: invalid image");
+                }
+                return Err(e);
+            }
+        };
+        // The root directory counts as a file too.
+        let files = entries.len() as u64 + 1;
+        let root = sb.get_inode::<ImageNode, RootDir>(ROOT_INO, || {
+            Ok(NewInode {
+                mode: Mode::from_int(S_IFDIR | 0o555),
+                uid: Kuid::GLOBAL_ROOT,
+                gid: Kgid::GLOBAL_ROOT,
+                nlink: 2,
+                size: 0,
+                data: ImageNode::Root(entries),
+            })
+        })?;
+        sb.set_root(root)?;
+        Ok(ImageFS { blocks, files })
+    }
+}
+
+struct ImageFSModule {
+    _fs_registration: filesystem::Registration<ImageFS>,
+}
+
+impl linux_kernel_module::KernelModule for ImageFSModule {
+    fn init() -> KernelResult<Self> {
+        Ok(ImageFSModule {
+            _fs_registration: filesystem::register::<ImageFS>()?,
+        })
+    }
+}
+
+linux_kernel_module::kernel_module!(
+    ImageFSModule,
+    author: b"Fish in a Barrel Contributors",
+    description: b"A module for testing filesystems on block devices",
+    license: b"GPL"
+);
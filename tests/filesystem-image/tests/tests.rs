@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs;
+use std::mem::MaybeUninit;
+use std::path::Path;
+use std::process::Command;
+
+use kernel_module_testlib::{assert_dmesg_contains, loop_device, mount, with_kernel_module};
+
+const BLOCK_SIZE: usize = 1024;
+
+fn list(path: &Path) -> HashSet<String> {
+    fs::read_dir(path)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect()
+}
+
+fn set(names: &[&str]) -> HashSet<String> {
+    names.iter().map(|s| s.to_string()).collect()
+}
+
+// Builds an image in the format read by rust-imagefs, with each file's
+// contents starting at the next free block.
+fn build_image(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut image = vec![0; BLOCK_SIZE];
+    image[..8].copy_from_slice(b"RUSTIMG1");
+    image[8..12].copy_from_slice(&(files.len() as u32).to_le_bytes());
+    for (i, (name, contents)) in files.iter().enumerate() {
+        let first_block = image.len() / BLOCK_SIZE;
+        let entry = &mut image[16 + i * 32..][..32];
+        entry[..name.len()].copy_from_slice(name.as_bytes());
+        entry[24..28].copy_from_slice(&(first_block as u32).to_le_bytes());
+        entry[28..32].copy_from_slice(&(contents.len() as u32).to_le_bytes());
+        image.extend_from_slice(contents);
+        let blocks = (contents.len() + BLOCK_SIZE - 1) / BLOCK_SIZE;
+        image.resize((first_block + blocks) * BLOCK_SIZE, 0);
+    }
+    image
+}
+
+fn statfs(path: &Path) -> libc::statfs {
+    let path = CString::new(path.to_str().unwrap()).unwrap();
+    let mut buf = MaybeUninit::uninit();
+    assert_eq!(unsafe { libc::statfs(path.as_ptr(), buf.as_mut_ptr()) }, 0);
+    unsafe { buf.assume_init() }
+}
+
+fn try_mount(source: &str) -> bool {
+    let path = tempfile::TempDir::new().unwrap();
+    let status = Command::new("sudo")
+        .args(&["mount", "-t", "rust-imagefs", "-o", "ro", source])
+        .arg(path.path())
+        .status()
+        .unwrap();
+    if status.success() {
+        let status = Command::new("sudo")
+            .arg("umount")
+            .arg(path.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+    status.success()
+}
+
+#[test]
+fn test_read_image() {
+    let large: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+    let dir = tempfile::TempDir::new().unwrap();
+    let image = dir.path().join("image");
+    fs::write(
+        &image,
+        build_image(&[
+            ("greeting", "hello world\n".as_bytes()),
+            ("large", &large[..]),
+            ("empty", "".as_bytes()),
+        ]),
+    )
+    .unwrap();
+
+    with_kernel_module(|| {
+        let device = loop_device(&image);
+        let mount = mount("rust-imagefs", device.path(), "ro");
+        let root = mount.path();
+        assert_eq!(list(root), set(&["greeting", "large", "empty"]));
+
+        assert_eq!(
+            fs::read_to_string(root.join("greeting")).unwrap(),
+            "hello world\n"
+        );
+        assert_eq!(fs::read(root.join("large")).unwrap(), large);
+        assert_eq!(fs::read(root.join("empty")).unwrap(), b"");
+
+        let metadata = fs::metadata(root.join("large")).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len(), 3000);
+
+        let err = fs::write(root.join("greeting"), "goodbye").unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EROFS));
+        assert!(fs::metadata(root.join("missing")).is_err());
+    });
+}
+
+#[test]
+fn test_statfs() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let image = dir.path().join("image");
+    let contents = build_image(&[("a", &[1; 2000][..]), ("b", "b".as_bytes())]);
+    let blocks = contents.len() / BLOCK_SIZE;
+    fs::write(&image, contents).unwrap();
+
+    with_kernel_module(|| {
+        let device = loop_device(&image);
+        let mount = mount("rust-imagefs", device.path(), "ro");
+        let stats = statfs(mount.path());
+        assert_eq!(stats.f_type, 0x5255_5354);
+        assert_eq!(stats.f_bsize, BLOCK_SIZE as _);
+        assert_eq!(stats.f_blocks, blocks as u64);
+        assert_eq!(stats.f_bfree, 0);
+        assert_eq!(stats.f_files, 3);
+    });
+}
+
+#[test]
+fn test_evict_inode() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let image = dir.path().join("image");
+    fs::write(&image, build_image(&[("greeting", "hello".as_bytes())])).unwrap();
+
+    with_kernel_module(|| {
+        let device = loop_device(&image);
+        let mount = mount("rust-imagefs", device.path(), "ro");
+        assert_eq!(
+            fs::read_to_string(mount.path().join("greeting")).unwrap(),
+            "hello"
+        );
+        drop(mount);
+        assert_dmesg_contains(&[b"rust-imagefs: evicting inode 2"]);
+    });
+}
+
+#[test]
+fn test_invalid_image() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let bad_magic = dir.path().join("bad-magic");
+    let mut image = build_image(&[("greeting", "hello world\n".as_bytes())]);
+    image[0] = b'X';
+    fs::write(&bad_magic, image).unwrap();
+
+    // An entry whose contents would be past the end of the device.
+    let truncated = dir.path().join("truncated");
+    let mut image = build_image(&[("large", &[0; 3000][..])]);
+    image.truncate(2 * BLOCK_SIZE);
+    fs::write(&truncated, image).unwrap();
+
+    with_kernel_module(|| {
+        let device = loop_device(&bad_magic);
+        assert!(!try_mount(device.path()));
+        assert_dmesg_contains(&[b"rust-imagefs: invalid image"]);
+
+        let device = loop_device(&truncated);
+        assert!(!try_mount(device.path()));
+    });
+}
@@ -0,0 +1,415 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use linux_kernel_module::file_lock::LockType;
+use linux_kernel_module::file_operations::{
+    CopyFileRangeFn, Credentials, DirContext, File, FileOpen, FileOperations, IterateFn, ReadFn,
+    SetleaseFn, WriteFn,
+};
+use linux_kernel_module::filesystem::{
+    self, Dentry, FileSystem, FileSystemFlags, Inode, InodeRef, NewInode, SuperBlock,
+};
+use linux_kernel_module::inode_operations::{
+    Attr, CreateFn, GetattrFn, InodeOperations, LookupFn, RenameFn, SetattrFn, UnlinkFn,
+};
+use linux_kernel_module::io_buffer::{IoBufferReader, IoBufferWriter};
+use linux_kernel_module::super_operations::SuperOperations;
+use linux_kernel_module::sync::Mutex;
+use linux_kernel_module::{
+    self, cstr, new_mutex, println, CStr, Error, KernelResult, Kgid, Kuid, Mode,
+};
+
+const S_IFMT: u16 = 0o170000;
+const S_IFDIR: u16 = 0o040000;
+
+const ROOT_INO: u64 = 1;
+static NEXT_INO: AtomicU64 = AtomicU64::new(ROOT_INO + 1);
+
+struct Attrs {
+    mode: u16,
+    uid: Kuid,
+    gid: Kgid,
+}
+
+enum Contents {
+    File(Mutex<Vec<u8>>),
+    Dir(Mutex<Vec<(Vec<u8>, Arc<Node>)>>),
+}
+
+struct Node {
+    ino: u64,
+    attrs: Mutex<Attrs>,
+    contents: Contents,
+}
+
+impl Node {
+    fn new(mode: u16, credentials: Option<&Credentials>, contents: Contents) -> Arc<Node> {
+        let (ino, uid, gid) = match credentials {
+            Some(c) => (
+                NEXT_INO.fetch_add(1, Ordering::SeqCst),
+                c.fsuid(),
+                c.fsgid(),
+            ),
+            None => (ROOT_INO, Kuid::GLOBAL_ROOT, Kgid::GLOBAL_ROOT),
+        };
+        Arc::new(Node {
+            ino,
+            attrs: new_mutex!(Attrs { mode, uid, gid }),
+            contents,
+        })
+    }
+
+    fn file(&self) -> KernelResult<&Mutex<Vec<u8>>> {
+        match &self.contents {
+            Contents::File(data) => Ok(data),
+            Contents::Dir(_) => Err(Error::EINVAL),
+        }
+    }
+
+    fn dir(&self) -> KernelResult<&Mutex<Vec<(Vec<u8>, Arc<Node>)>>> {
+        match &self.contents {
+            Contents::Dir(entries) => Ok(entries),
+            Contents::File(_) => Err(Error::EINVAL),
+        }
+    }
+
+    fn size(&self) -> i64 {
+        match &self.contents {
+            Contents::File(data) => data.lock().len() as i64,
+            Contents::Dir(_) => 0,
+        }
+    }
+
+    fn nlink(&self) -> u32 {
+        match &self.contents {
+            Contents::File(_) => 1,
+            Contents::Dir(entries) => {
+                let subdirs = entries
+                    .lock()
+                    .iter()
+                    .filter(|(_, child)| child.dir().is_ok())
+                    .count();
+                2 + subdirs as u32
+            }
+        }
+    }
+}
+
+// The value stored in each inode.
+struct NodeRef(Arc<Node>);
+
+fn new_inode(node: &Arc<Node>) -> KernelResult<NewInode<NodeRef>> {
+    let (mode, uid, gid) = {
+        let attrs = node.attrs.lock();
+        (attrs.mode, attrs.uid, attrs.gid)
+    };
+    Ok(NewInode {
+        mode: Mode::from_int(mode),
+        uid,
+        gid,
+        nlink: node.nlink(),
+        size: node.size(),
+        data: NodeRef(node.clone()),
+    })
+}
+
+fn inode_for(dir: &Inode, node: &Arc<Node>) -> KernelResult<InodeRef> {
+    match node.contents {
+        Contents::File(_) => dir.get_inode::<NodeRef, DataFile>(node.ino, || new_inode(node)),
+        Contents::Dir(_) => dir.get_inode::<NodeRef, DirFile>(node.ino, || new_inode(node)),
+    }
+}
+
+fn create_node(
+    this: &NodeRef,
+    dir: &Inode,
+    dentry: &Dentry,
+    mode: Mode,
+    credentials: &Credentials,
+) -> KernelResult<InodeRef> {
+    let contents = if mode.as_int() & S_IFMT == S_IFDIR {
+        Contents::Dir(new_mutex!(Vec::new()))
+    } else {
+        Contents::File(new_mutex!(Vec::new()))
+    };
+    let node = Node::new(mode.as_int(), Some(credentials), contents);
+    this.0
+        .dir()?
+        .lock()
+        .push((dentry.name().to_vec(), node.clone()));
+    inode_for(dir, &node)
+}
+
+fn remove_node(this: &NodeRef, dentry: &Dentry) -> KernelResult<()> {
+    let mut entries = this.0.dir()?.lock();
+    let index = entries
+        .iter()
+        .position(|(name, _)| &name[..] == dentry.name())
+        .ok_or(Error::ENOENT)?;
+    if let Contents::Dir(children) = &entries[index].1.contents {
+        if !children.lock_nested(1).is_empty() {
+            return Err(Error::ENOTEMPTY);
+        }
+    }
+    entries.remove(index);
+    Ok(())
+}
+
+fn check_replaceable(entries: &[(Vec<u8>, Arc<Node>)], name: &[u8]) -> KernelResult<()> {
+    if let Some((_, node)) = entries.iter().find(|(n, _)| &n[..] == name) {
+        if let Contents::Dir(children) = &node.contents {
+            // Below both directories of a rename.
+            if !children.lock_nested(2).is_empty() {
+                return Err(Error::ENOTEMPTY);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn rename_node(
+    this: &NodeRef,
+    _old_dir: &Inode,
+    old_dentry: &Dentry,
+    new_dir: &Inode,
+    new_dentry: &Dentry,
+) -> KernelResult<()> {
+    let new_parent = new_dir.data::<NodeRef>().ok_or(Error::EINVAL)?;
+    let new_name = new_dentry.name();
+    let mut old_entries = this.0.dir()?.lock();
+    let index = old_entries
+        .iter()
+        .position(|(name, _)| &name[..] == old_dentry.name())
+        .ok_or(Error::ENOENT)?;
+    if Arc::ptr_eq(&this.0, &new_parent.0) {
+        check_replaceable(&old_entries, new_name)?;
+        let (_, node) = old_entries.remove(index);
+        old_entries.retain(|(name, _)| &name[..] != new_name);
+        old_entries.push((new_name.to_vec(), node));
+    } else {
+        // Renames between directories are serialized by the VFS, so taking
+        // both locks can't deadlock.
+        let mut new_entries = new_parent.0.dir()?.lock_nested(1);
+        check_replaceable(&new_entries, new_name)?;
+        let (_, node) = old_entries.remove(index);
+        new_entries.retain(|(name, _)| &name[..] != new_name);
+        new_entries.push((new_name.to_vec(), node));
+    }
+    Ok(())
+}
+
+impl InodeOperations for NodeRef {
+    const LOOKUP: LookupFn<Self> = Some(
+        |this: &Self, dir: &Inode, dentry: &Dentry| -> KernelResult<Option<InodeRef>> {
+            let entries = this.0.dir()?.lock();
+            match entries.iter().find(|(name, _)| &name[..] == dentry.name()) {
+                Some((_, node)) => Ok(Some(inode_for(dir, node)?)),
+                None => Ok(None),
+            }
+        },
+    );
+
+    const CREATE: CreateFn<Self> = Some(create_node);
+    const MKDIR: CreateFn<Self> = Some(create_node);
+    const UNLINK: UnlinkFn<Self> =
+        Some(|this: &Self, _dir: &Inode, dentry: &Dentry| remove_node(this, dentry));
+    const RMDIR: UnlinkFn<Self> =
+        Some(|this: &Self, _dir: &Inode, dentry: &Dentry| remove_node(this, dentry));
+    const RENAME: RenameFn<Self> = Some(rename_node);
+
+    const SETATTR: SetattrFn<Self> = Some(
+        |this: &Self, _dentry: &Dentry, attr: &Attr| -> KernelResult<()> {
+            if let Some(size) = attr.size() {
+                this.0.file()?.lock().resize(size.try_into()?, 0);
+            }
+            let mut attrs = this.0.attrs.lock();
+            if let Some(mode) = attr.mode() {
+                attrs.mode = (attrs.mode & S_IFMT) | (mode.as_int() & !S_IFMT);
+            }
+            if let Some(uid) = attr.uid() {
+                attrs.uid = uid;
+            }
+            if let Some(gid) = attr.gid() {
+                attrs.gid = gid;
+            }
+            Ok(())
+        },
+    );
+
+    const GETATTR: GetattrFn<Self> = Some(|this: &Self, inode: &Inode| -> KernelResult<()> {
+        inode.lock().set_size(this.0.size());
+        Ok(())
+    });
+}
+
+struct DirFile;
+
+impl FileOperations for DirFile {
+    const ITERATE: IterateFn<Self> = Some(
+        |_this: &Self, file: &File, ctx: &mut DirContext| -> KernelResult<()> {
+            if !ctx.emit_dots(file) {
+                return Ok(());
+            }
+            let inode = file.inode();
+            let node = inode.data::<NodeRef>().ok_or(Error::EINVAL)?;
+            let entries = node.0.dir()?.lock();
+            let start = ctx.pos() as usize - 2;
+            for (i, (name, child)) in entries.iter().enumerate().skip(start) {
+                let mode = Mode::from_int(child.attrs.lock().mode);
+                if !ctx.emit(name, child.ino, &mode) {
+                    break;
+                }
+                ctx.set_pos(i as u64 + 3);
+            }
+            Ok(())
+        },
+    );
+}
+
+impl FileOpen for DirFile {
+    fn open() -> KernelResult<Self> {
+        Ok(DirFile)
+    }
+}
+
+struct DataFile;
+
+impl FileOperations for DataFile {
+    const READ: ReadFn<Self> = Some(
+        |_this: &Self,
+         file: &File,
+         buf: &mut dyn IoBufferWriter,
+         offset: &mut u64|
+         -> KernelResult<usize> {
+            let inode = file.inode();
+            let node = inode.data::<NodeRef>().ok_or(Error::EINVAL)?;
+            let data = node.0.file()?.lock();
+            let start = core::cmp::min((*offset).try_into()?, data.len());
+            let len = core::cmp::min(buf.len(), data.len() - start);
+            buf.write(&data[start..start + len])?;
+            *offset += len as u64;
+            Ok(len)
+        },
+    );
+
+    const WRITE: WriteFn<Self> = Some(
+        |_this: &Self,
+         file: &File,
+         buf: &mut dyn IoBufferReader,
+         offset: &mut u64|
+         -> KernelResult<usize> {
+            let inode = file.inode();
+            let node = inode.data::<NodeRef>().ok_or(Error::EINVAL)?;
+            // Like SETATTR, which the kernel calls with the inode locked,
+            // take the inode's lock before the data's.
+            let locked = inode.lock();
+            let mut data = node.0.file()?.lock();
+            let start: usize = (*offset).try_into()?;
+            let len = buf.len();
+            if data.len() < start + len {
+                data.resize(start + len, 0);
+            }
+            buf.read(&mut data[start..start + len])?;
+            *offset += len as u64;
+            locked.set_size(data.len() as i64);
+            Ok(len)
+        },
+    );
+
+    const COPY_FILE_RANGE: CopyFileRangeFn<Self> = Some(
+        |_this: &Self,
+         file_out: &File,
+         pos_out: u64,
+         source: Option<&Self>,
+         file_in: &File,
+         pos_in: u64,
+         len: usize|
+         -> KernelResult<usize> {
+            // Leave copies from other kinds of file to the kernel, or to
+            // userspace on kernels without a generic fallback.
+            if source.is_none() {
+                return Err(Error::EXDEV);
+            }
+            let inode_in = file_in.inode();
+            let node_in = inode_in.data::<NodeRef>().ok_or(Error::EINVAL)?;
+            // Copy out of the source first, since it may be the same file.
+            let copied = {
+                let data = node_in.0.file()?.lock();
+                let start = core::cmp::min(pos_in.try_into()?, data.len());
+                let end = core::cmp::min(start + len, data.len());
+                data[start..end].to_vec()
+            };
+            let inode_out = file_out.inode();
+            let node_out = inode_out.data::<NodeRef>().ok_or(Error::EINVAL)?;
+            let locked = inode_out.lock();
+            let mut data = node_out.0.file()?.lock();
+            let start: usize = pos_out.try_into()?;
+            if data.len() < start + copied.len() {
+                data.resize(start + copied.len(), 0);
+            }
+            data[start..start + copied.len()].copy_from_slice(&copied);
+            locked.set_size(data.len() as i64);
+            println!("rust-memfs: copied {} bytes", copied.len());
+            Ok(copied.len())
+        },
+    );
+
+    // Only read leases are allowed, so that the hook's refusal can be told
+    // apart from the kernel's own checks.
+    const SETLEASE: SetleaseFn<Self> = Some(
+        |_this: &Self, _file: &File, lease_type: LockType| -> KernelResult<()> {
+            match lease_type {
+                LockType::Write => Err(Error::EAGAIN),
+                _ => Ok(()),
+            }
+        },
+    );
+}
+
+impl FileOpen for DataFile {
+    fn open() -> KernelResult<Self> {
+        Ok(DataFile)
+    }
+}
+
+struct MemFS;
+
+impl SuperOperations for MemFS {}
+
+impl FileSystem for MemFS {
+    const NAME: CStr<'static> = cstr!("rust-memfs");
+    const FLAGS: FileSystemFlags = FileSystemFlags::empty();
+    type Options = ();
+
+    fn fill_super(sb: &mut SuperBlock<Self>, _options: &(), _silent: bool) -> KernelResult<Self> {
+        let root = Node::new(S_IFDIR | 0o777, None, Contents::Dir(new_mutex!(Vec::new())));
+        let inode = sb.get_inode::<NodeRef, DirFile>(ROOT_INO, || new_inode(&root))?;
+        sb.set_root(inode)?;
+        Ok(MemFS)
+    }
+}
+
+struct MemFSModule {
+    _fs_registration: filesystem::Registration<MemFS>,
+}
+
+impl linux_kernel_module::KernelModule for MemFSModule {
+    fn init() -> KernelResult<Self> {
+        Ok(MemFSModule {
+            _fs_registration: filesystem::register::<MemFS>()?,
+        })
+    }
+}
+
+linux_kernel_module::kernel_module!(
+    MemFSModule,
+    author: b"Fish in a Barrel Contributors",
+    description: b"A module for testing inode operations with an in-memory filesystem",
+    license: b"GPL"
+);
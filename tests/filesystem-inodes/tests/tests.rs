@@ -0,0 +1,258 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process::Command;
+
+use kernel_module_testlib::{assert_dmesg_contains, mount, with_kernel_module};
+
+fn list(path: &Path) -> HashSet<String> {
+    fs::read_dir(path)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect()
+}
+
+fn set(names: &[&str]) -> HashSet<String> {
+    names.iter().map(|s| s.to_string()).collect()
+}
+
+// Evicts unused dentries and inodes, so later accesses have to look them up
+// again from the filesystem.
+fn drop_caches() {
+    let status = Command::new("sudo")
+        .args(&["sh", "-c", "echo 2 > /proc/sys/vm/drop_caches"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn fcntl(file: &fs::File, cmd: libc::c_int, arg: libc::c_int) -> io::Result<libc::c_int> {
+    let result = unsafe { libc::fcntl(file.as_raw_fd(), cmd, arg) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+fn copy_file_range(
+    file_in: &fs::File,
+    mut off_in: i64,
+    file_out: &fs::File,
+    mut off_out: i64,
+    len: usize,
+) -> io::Result<usize> {
+    let n = unsafe {
+        libc::syscall(
+            libc::SYS_copy_file_range,
+            file_in.as_raw_fd(),
+            &mut off_in as *mut i64,
+            file_out.as_raw_fd(),
+            &mut off_out as *mut i64,
+            len,
+            0,
+        )
+    };
+    if n < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
+
+#[test]
+fn test_create_and_read() {
+    with_kernel_module(|| {
+        let mount = mount("rust-memfs", "none", "");
+        let root = mount.path();
+        assert_eq!(list(root), set(&[]));
+
+        fs::write(root.join("a"), "hello").unwrap();
+        fs::write(root.join("b"), "world").unwrap();
+        assert_eq!(list(root), set(&["a", "b"]));
+        assert_eq!(fs::read_to_string(root.join("a")).unwrap(), "hello");
+
+        let metadata = fs::metadata(root.join("a")).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len(), 5);
+        assert_eq!(metadata.uid(), unsafe { libc::getuid() });
+
+        fs::write(root.join("a"), "hi").unwrap();
+        assert_eq!(fs::read_to_string(root.join("a")).unwrap(), "hi");
+
+        drop_caches();
+        assert_eq!(list(root), set(&["a", "b"]));
+        assert_eq!(fs::read_to_string(root.join("b")).unwrap(), "world");
+        assert_eq!(fs::metadata(root.join("a")).unwrap().len(), 2);
+    });
+}
+
+#[test]
+fn test_directories() {
+    with_kernel_module(|| {
+        let mount = mount("rust-memfs", "none", "");
+        let root = mount.path();
+
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::create_dir(root.join("dir/sub")).unwrap();
+        fs::write(root.join("dir/sub/file"), "data").unwrap();
+        assert!(fs::metadata(root.join("dir")).unwrap().is_dir());
+        assert_eq!(list(&root.join("dir")), set(&["sub"]));
+        assert_eq!(fs::metadata(root).unwrap().nlink(), 3);
+        assert_eq!(fs::metadata(root.join("dir")).unwrap().nlink(), 3);
+
+        let err = fs::remove_dir(root.join("dir/sub")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOTEMPTY));
+
+        fs::remove_file(root.join("dir/sub/file")).unwrap();
+        fs::remove_dir(root.join("dir/sub")).unwrap();
+        assert_eq!(list(&root.join("dir")), set(&[]));
+        assert_eq!(fs::metadata(root.join("dir")).unwrap().nlink(), 2);
+        assert_eq!(
+            fs::metadata(root.join("dir/sub")).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+
+        drop_caches();
+        assert_eq!(list(root), set(&["dir"]));
+        assert_eq!(fs::metadata(root).unwrap().nlink(), 3);
+    });
+}
+
+#[test]
+fn test_rename() {
+    with_kernel_module(|| {
+        let mount = mount("rust-memfs", "none", "");
+        let root = mount.path();
+
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("a"), "a").unwrap();
+        fs::write(root.join("b"), "b").unwrap();
+
+        fs::rename(root.join("a"), root.join("c")).unwrap();
+        assert_eq!(list(root), set(&["b", "c", "dir"]));
+
+        fs::rename(root.join("c"), root.join("b")).unwrap();
+        assert_eq!(list(root), set(&["b", "dir"]));
+        assert_eq!(fs::read_to_string(root.join("b")).unwrap(), "a");
+
+        fs::rename(root.join("b"), root.join("dir/moved")).unwrap();
+        assert_eq!(list(root), set(&["dir"]));
+        assert_eq!(list(&root.join("dir")), set(&["moved"]));
+
+        drop_caches();
+        assert_eq!(fs::read_to_string(root.join("dir/moved")).unwrap(), "a");
+    });
+}
+
+#[test]
+fn test_setattr() {
+    with_kernel_module(|| {
+        let mount = mount("rust-memfs", "none", "");
+        let root = mount.path();
+
+        fs::write(root.join("file"), "0123456789").unwrap();
+        fs::set_permissions(root.join("file"), fs::Permissions::from_mode(0o600)).unwrap();
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .open(root.join("file"))
+            .unwrap();
+        file.set_len(4).unwrap();
+        drop(file);
+
+        drop_caches();
+        let metadata = fs::metadata(root.join("file")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+        assert_eq!(metadata.len(), 4);
+        assert_eq!(fs::read_to_string(root.join("file")).unwrap(), "0123");
+    });
+}
+
+#[test]
+fn test_copy_file_range() {
+    with_kernel_module(|| {
+        let mount = mount("rust-memfs", "none", "");
+        let root = mount.path();
+
+        fs::write(root.join("src"), "hello world").unwrap();
+        fs::write(root.join("dst"), "0123456789").unwrap();
+        let file_in = fs::File::open(root.join("src")).unwrap();
+        let file_out = fs::OpenOptions::new()
+            .write(true)
+            .open(root.join("dst"))
+            .unwrap();
+        assert_eq!(copy_file_range(&file_in, 6, &file_out, 8, 5).unwrap(), 5);
+        assert_dmesg_contains(&[b"rust-memfs: copied 5 bytes"]);
+        assert_eq!(
+            fs::read_to_string(root.join("dst")).unwrap(),
+            "01234567world"
+        );
+        assert_eq!(fs::metadata(root.join("dst")).unwrap().len(), 13);
+
+        // Copies stop at the end of the source.
+        assert_eq!(copy_file_range(&file_in, 9, &file_out, 0, 100).unwrap(), 2);
+        assert_dmesg_contains(&[b"rust-memfs: copied 2 bytes"]);
+        assert_eq!(
+            fs::read_to_string(root.join("dst")).unwrap(),
+            "ld234567world"
+        );
+    });
+}
+
+#[test]
+fn test_copy_file_range_from_other_filesystem() {
+    let dir = tempfile::TempDir::new().unwrap();
+    fs::write(dir.path().join("src"), "elsewhere").unwrap();
+
+    with_kernel_module(|| {
+        let mount = mount("rust-memfs", "none", "");
+        let root = mount.path();
+
+        fs::write(root.join("dst"), "").unwrap();
+        let file_in = fs::File::open(dir.path().join("src")).unwrap();
+        let file_out = fs::OpenOptions::new()
+            .write(true)
+            .open(root.join("dst"))
+            .unwrap();
+        // The source isn't a memfs file, so the hook refuses the copy.
+        // Depending on the kernel version, it's then either done by the
+        // kernel's generic implementation or left to userspace.
+        match copy_file_range(&file_in, 0, &file_out, 0, 9) {
+            Ok(n) => {
+                assert_eq!(n, 9);
+                assert_eq!(fs::read_to_string(root.join("dst")).unwrap(), "elsewhere");
+            }
+            Err(e) => {
+                assert_eq!(e.raw_os_error(), Some(libc::EXDEV));
+                assert_eq!(fs::read_to_string(root.join("dst")).unwrap(), "");
+            }
+        }
+    });
+}
+
+#[test]
+fn test_setlease() {
+    with_kernel_module(|| {
+        let mount = mount("rust-memfs", "none", "");
+        let root = mount.path();
+
+        // Leases are only allowed on regular files owned by the caller, so
+        // unlike locks they can't be tested on a character device.
+        fs::write(root.join("file"), "data").unwrap();
+        let f = fs::File::open(root.join("file")).unwrap();
+        assert_eq!(fcntl(&f, libc::F_GETLEASE, 0).unwrap(), libc::F_UNLCK);
+
+        fcntl(&f, libc::F_SETLEASE, libc::F_RDLCK).unwrap();
+        assert_eq!(fcntl(&f, libc::F_GETLEASE, 0).unwrap(), libc::F_RDLCK);
+
+        let err = fcntl(&f, libc::F_SETLEASE, libc::F_WRLCK).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+        assert_eq!(fcntl(&f, libc::F_GETLEASE, 0).unwrap(), libc::F_RDLCK);
+
+        fcntl(&f, libc::F_SETLEASE, libc::F_UNLCK).unwrap();
+        assert_eq!(fcntl(&f, libc::F_GETLEASE, 0).unwrap(), libc::F_UNLCK);
+    });
+}
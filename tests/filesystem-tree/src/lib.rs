@@ -0,0 +1,177 @@
+#![no_std]
+
+extern crate alloc;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use linux_kernel_module::file_operations::{
+    FallocateFn, FallocateMode, File, FileOpen, FileOperations, ReadFn,
+};
+use linux_kernel_module::filesystem::{self, FileSystem, FileSystemFlags, SuperBlock, TreeDir};
+use linux_kernel_module::io_buffer::IoBufferWriter;
+use linux_kernel_module::seq_file::{SeqWriter, SingleSeqFile};
+use linux_kernel_module::super_operations::SuperOperations;
+use linux_kernel_module::sync::Mutex;
+use linux_kernel_module::{self, cstr, new_mutex, CStr, Error, KernelResult, Mode};
+
+static OPENS: AtomicUsize = AtomicUsize::new(0);
+
+struct GreetingFile;
+
+impl FileOperations for GreetingFile {
+    const READ: ReadFn<Self> = Some(
+        |_this: &Self,
+         _file: &File,
+         buf: &mut dyn IoBufferWriter,
+         offset: &mut u64|
+         -> KernelResult<usize> {
+            let data = b"hello from treefs\n";
+            let start = core::cmp::min(*offset as usize, data.len());
+            let len = core::cmp::min(buf.len(), data.len() - start);
+            buf.write(&data[start..start + len])?;
+            *offset += len as u64;
+            Ok(len)
+        },
+    );
+}
+
+impl FileOpen for GreetingFile {
+    fn open() -> KernelResult<Self> {
+        OPENS.fetch_add(1, Ordering::SeqCst);
+        Ok(GreetingFile)
+    }
+}
+
+struct OpenCount;
+
+impl SingleSeqFile for OpenCount {
+    fn show(&self, m: &mut SeqWriter) -> KernelResult<()> {
+        core::fmt::write(m, format_args!("{}\n", OPENS.load(Ordering::SeqCst)))?;
+        Ok(())
+    }
+}
+
+impl FileOpen for OpenCount {
+    fn open() -> KernelResult<Self> {
+        Ok(OpenCount)
+    }
+}
+
+// The size of the space AllocateFile pretends to manage.
+const ALLOCATE_LIMIT: u64 = 4096;
+
+// Remembers the arguments of the last fallocate(2) call on this file
+// description, and shows them when read.
+struct AllocateFile {
+    last: Mutex<Option<(FallocateMode, u64, u64)>>,
+}
+
+impl FileOperations for AllocateFile {
+    const READ: ReadFn<Self> = Some(
+        |this: &Self,
+         _file: &File,
+         buf: &mut dyn IoBufferWriter,
+         offset: &mut u64|
+         -> KernelResult<usize> {
+            if *offset != 0 {
+                return Ok(0);
+            }
+            let data = match *this.last.lock() {
+                Some((mode, start, len)) => {
+                    alloc::format!("{} {} {}\n", mode.bits(), start, len)
+                }
+                None => alloc::string::String::from("none\n"),
+            };
+            buf.write(data.as_bytes())?;
+            *offset += data.len() as u64;
+            Ok(data.len())
+        },
+    );
+
+    const FALLOCATE: FallocateFn<Self> = Some(
+        |this: &Self,
+         _file: &File,
+         mode: FallocateMode,
+         offset: u64,
+         len: u64|
+         -> KernelResult<()> {
+            if offset + len > ALLOCATE_LIMIT {
+                return Err(Error::ENXIO);
+            }
+            *this.last.lock() = Some((mode, offset, len));
+            Ok(())
+        },
+    );
+}
+
+impl FileOpen for AllocateFile {
+    fn open() -> KernelResult<Self> {
+        Ok(AllocateFile {
+            last: new_mutex!(None),
+        })
+    }
+}
+
+struct TreeFS;
+
+impl SuperOperations for TreeFS {}
+
+impl FileSystem for TreeFS {
+    const NAME: CStr<'static> = cstr!("rust-treefs");
+    const FLAGS: FileSystemFlags = FileSystemFlags::empty();
+    type Options = ();
+
+    fn fill_super(sb: &mut SuperBlock<Self>, _options: &(), _silent: bool) -> KernelResult<Self> {
+        let tree = TreeDir::new()
+            .file::<GreetingFile>(cstr!("greeting"), Mode::from_int(0o444))
+            .file::<AllocateFile>(cstr!("allocate"), Mode::from_int(0o666))
+            .subdir(
+                cstr!("stats"),
+                Mode::from_int(0o555),
+                TreeDir::new()
+                    .single_seq_file::<OpenCount>(cstr!("opens"), Mode::from_int(0o400))
+                    .subdir(cstr!("empty"), Mode::from_int(0o700), TreeDir::new()),
+            );
+        sb.create_root_tree(Mode::from_int(0o755), &tree)?;
+        Ok(TreeFS)
+    }
+}
+
+struct DuplicateFS;
+
+impl SuperOperations for DuplicateFS {}
+
+impl FileSystem for DuplicateFS {
+    const NAME: CStr<'static> = cstr!("rust-treefs-duplicate");
+    const FLAGS: FileSystemFlags = FileSystemFlags::empty();
+    type Options = ();
+
+    fn fill_super(sb: &mut SuperBlock<Self>, _options: &(), _silent: bool) -> KernelResult<Self> {
+        let tree = TreeDir::new()
+            .file::<GreetingFile>(cstr!("greeting"), Mode::from_int(0o444))
+            .file::<GreetingFile>(cstr!("greeting"), Mode::from_int(0o444));
+        sb.create_root_tree(Mode::from_int(0o755), &tree)?;
+        Ok(DuplicateFS)
+    }
+}
+
+struct TreeFSModule {
+    _tree: filesystem::Registration<TreeFS>,
+    _duplicate: filesystem::Registration<DuplicateFS>,
+}
+
+impl linux_kernel_module::KernelModule for TreeFSModule {
+    fn init() -> KernelResult<Self> {
+        Ok(TreeFSModule {
+            _tree: filesystem::register::<TreeFS>()?,
+            _duplicate: filesystem::register::<DuplicateFS>()?,
+        })
+    }
+}
+
+linux_kernel_module::kernel_module!(
+    TreeFSModule,
+    author: b"Fish in a Barrel Contributors",
+    description: b"A module for testing filesystems built from a TreeDir",
+    license: b"GPL"
+);
@@ -0,0 +1,144 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileExt, MetadataExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::process::Command;
+
+use kernel_module_testlib::{mount, with_kernel_module};
+
+fn mode(path: &std::path::Path) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode()
+}
+
+#[test]
+fn test_tree_layout() {
+    with_kernel_module(|| {
+        let mount = mount("rust-treefs", "none", "");
+        let root = mount.path();
+
+        let names = fs::read_dir(root)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            names,
+            ["greeting", "allocate", "stats"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect()
+        );
+
+        assert_eq!(mode(root) & 0o170777, 0o040755);
+        assert_eq!(mode(&root.join("greeting")) & 0o170777, 0o100444);
+        assert_eq!(mode(&root.join("allocate")) & 0o170777, 0o100666);
+        assert_eq!(mode(&root.join("stats")) & 0o170777, 0o040555);
+        assert_eq!(mode(&root.join("stats/opens")) & 0o170777, 0o100400);
+        assert_eq!(mode(&root.join("stats/empty")) & 0o170777, 0o040700);
+
+        // "." and ".." links.
+        assert_eq!(fs::metadata(root).unwrap().nlink(), 3);
+        assert_eq!(fs::metadata(root.join("stats")).unwrap().nlink(), 3);
+        assert_eq!(fs::metadata(root.join("stats/empty")).unwrap().nlink(), 2);
+
+        let inodes = [
+            "",
+            "greeting",
+            "allocate",
+            "stats",
+            "stats/opens",
+            "stats/empty",
+        ]
+        .iter()
+        .map(|p| fs::metadata(root.join(p)).unwrap().ino())
+        .collect::<HashSet<_>>();
+        assert_eq!(inodes.len(), 6);
+    });
+}
+
+#[test]
+fn test_tree_files() {
+    with_kernel_module(|| {
+        let mount = mount("rust-treefs", "none", "");
+        let root = mount.path();
+
+        assert_eq!(
+            fs::read_to_string(root.join("greeting")).unwrap(),
+            "hello from treefs\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("greeting")).unwrap(),
+            "hello from treefs\n"
+        );
+
+        let output = Command::new("sudo")
+            .arg("cat")
+            .arg(root.join("stats/opens"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"2\n");
+
+        assert!(fs::write(root.join("new"), "data").is_err());
+    });
+}
+
+#[test]
+fn test_duplicate_names() {
+    with_kernel_module(|| {
+        let path = tempfile::TempDir::new().unwrap();
+        let status = Command::new("sudo")
+            .arg("mount")
+            .arg("-t")
+            .arg("rust-treefs-duplicate")
+            .arg("none")
+            .arg(path.path())
+            .status()
+            .unwrap();
+        assert!(!status.success());
+    });
+}
+
+fn fallocate(f: &fs::File, mode: libc::c_int, offset: i64, len: i64) -> io::Result<()> {
+    if unsafe { libc::fallocate(f.as_raw_fd(), mode, offset, len) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn last_fallocate(f: &fs::File) -> String {
+    let mut buf = [0; 64];
+    let n = f.read_at(&mut buf, 0).unwrap();
+    String::from_utf8(buf[..n].to_vec()).unwrap()
+}
+
+// fallocate(2) only reaches the file's operations for regular files, so this
+// is tested here rather than on a character device.
+#[test]
+fn test_fallocate() {
+    with_kernel_module(|| {
+        let mount = mount("rust-treefs", "none", "");
+        let f = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(mount.path().join("allocate"))
+            .unwrap();
+        assert_eq!(last_fallocate(&f), "none\n");
+
+        fallocate(&f, 0, 100, 200).unwrap();
+        assert_eq!(last_fallocate(&f), "0 100 200\n");
+
+        fallocate(&f, libc::FALLOC_FL_KEEP_SIZE, 0, 4096).unwrap();
+        assert_eq!(
+            last_fallocate(&f),
+            format!("{} 0 4096\n", libc::FALLOC_FL_KEEP_SIZE)
+        );
+
+        let err = fallocate(&f, 0, 4000, 200).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENXIO));
+        assert_eq!(
+            last_fallocate(&f),
+            format!("{} 0 4096\n", libc::FALLOC_FL_KEEP_SIZE)
+        );
+    });
+}
@@ -2,25 +2,160 @@
 
 extern crate alloc;
 
-use linux_kernel_module::filesystem::{self, FileSystem, FileSystemFlags};
-use linux_kernel_module::{self, cstr, CStr};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use linux_kernel_module::file_operations::FileOpen;
+use linux_kernel_module::filesystem::{
+    self, FileSystem, FileSystemFlags, MountOptions, MountParameter, OptionValue, SuperBlock,
+    TreeDir,
+};
+use linux_kernel_module::seq_file::{SeqWriter, SingleSeqFile};
+use linux_kernel_module::super_operations::{PutSuperFn, ShowOptionsFn, SuperOperations};
+use linux_kernel_module::{self, cstr, println, CStr, Error, KernelResult, Mode};
 
 struct TestFSModule {
     _fs_registration: filesystem::Registration<TestFS>,
+    _options_fs_registration: filesystem::Registration<OptionsFS>,
 }
 
 struct TestFS {}
 
+impl SuperOperations for TestFS {}
+
 impl FileSystem for TestFS {
     const NAME: CStr<'static> = cstr!("testfs");
     const FLAGS: FileSystemFlags = FileSystemFlags::empty();
+    type Options = ();
+
+    fn fill_super(sb: &mut SuperBlock<Self>, _options: &(), _silent: bool) -> KernelResult<Self> {
+        sb.set_block_size(4096)?;
+        sb.set_magic(0x7275_7374);
+        sb.create_root_dir(Mode::from_int(0o755))?;
+        Ok(TestFS {})
+    }
+}
+
+// The most recently mounted or remounted limit, shown in the "limit" file.
+static LIMIT: AtomicU32 = AtomicU32::new(0);
+
+struct LimitFile;
+
+impl SingleSeqFile for LimitFile {
+    fn show(&self, m: &mut SeqWriter) -> KernelResult<()> {
+        core::fmt::write(m, format_args!("{}\n", LIMIT.load(Ordering::SeqCst)))?;
+        Ok(())
+    }
+}
+
+impl FileOpen for LimitFile {
+    fn open() -> KernelResult<Self> {
+        Ok(LimitFile)
+    }
+}
+
+#[derive(Default)]
+struct TestOptions {
+    mode: Option<Mode>,
+    limit: Option<u32>,
+    verbose: bool,
+}
+
+impl MountOptions for TestOptions {
+    const PARAMETERS: &'static [MountParameter] = &[
+        MountParameter::value(cstr!("mode")),
+        MountParameter::value(cstr!("limit")),
+        MountParameter::flag(cstr!("verbose")),
+    ];
+
+    fn parse_option(&mut self, name: &str, value: Option<&str>) -> KernelResult<()> {
+        match name {
+            "mode" => self.mode = Some(Mode::parse_value(value)?),
+            "limit" => self.limit = Some(u32::parse_value(value)?),
+            "verbose" => self.verbose = bool::parse_value(value)?,
+            _ => return Err(Error::EINVAL),
+        }
+        Ok(())
+    }
+}
+
+struct OptionsFS {
+    mode: Option<u16>,
+    verbose: bool,
+}
+
+impl SuperOperations for OptionsFS {
+    const SHOW_OPTIONS: ShowOptionsFn<Self> =
+        Some(|this: &Self, m: &mut SeqWriter| -> KernelResult<()> {
+            if let Some(mode) = this.mode {
+                core::fmt::write(m, format_args!(",mode={:o}", mode))?;
+            }
+            core::fmt::write(m, format_args!(",limit={}", LIMIT.load(Ordering::SeqCst)))?;
+            if this.verbose {
+                m.write_bytes(b",verbose")?;
+            }
+            Ok(())
+        });
+
+    const PUT_SUPER: PutSuperFn<Self> = Some(|_this: &Self| {
+        println!("testfs-options: unmounted");
+    });
+}
+
+impl FileSystem for OptionsFS {
+    const NAME: CStr<'static> = cstr!("testfs-options");
+    const FLAGS: FileSystemFlags = FileSystemFlags::empty();
+    type Options = TestOptions;
+
+    fn fill_super(
+        sb: &mut SuperBlock<Self>,
+        options: &TestOptions,
+        _silent: bool,
+    ) -> KernelResult<Self> {
+        let mode = match &options.mode {
+            Some(mode) => Mode::from_int(mode.as_int()),
+            None => Mode::from_int(0o755),
+        };
+        let mut tree =
+            TreeDir::new().single_seq_file::<LimitFile>(cstr!("limit"), Mode::from_int(0o444));
+        if options.verbose {
+            tree = tree.single_seq_file::<LimitFile>(cstr!("verbose"), Mode::from_int(0o444));
+        }
+        sb.create_root_tree(mode, &tree)?;
+        LIMIT.store(options.limit.unwrap_or(0), Ordering::SeqCst);
+        Ok(OptionsFS {
+            mode: options.mode.as_ref().map(Mode::as_int),
+            verbose: options.verbose,
+        })
+    }
+
+    fn current_options(&self) -> TestOptions {
+        TestOptions {
+            mode: self.mode.map(Mode::from_int),
+            limit: Some(LIMIT.load(Ordering::SeqCst)),
+            verbose: self.verbose,
+        }
+    }
+
+    // Only the limit can be changed by remounting.
+    fn reconfigure(&self, _sb: &mut SuperBlock<Self>, options: TestOptions) -> KernelResult<()> {
+        let mode = options.mode.as_ref().map(Mode::as_int);
+        if mode != self.mode || options.verbose != self.verbose {
+            return Err(Error::EINVAL);
+        }
+        if let Some(limit) = options.limit {
+            LIMIT.store(limit, Ordering::SeqCst);
+        }
+        Ok(())
+    }
 }
 
 impl linux_kernel_module::KernelModule for TestFSModule {
-    fn init() -> linux_kernel_module::KernelResult<Self> {
+    fn init() -> KernelResult<Self> {
         let fs_registration = filesystem::register::<TestFS>()?;
+        let options_fs_registration = filesystem::register::<OptionsFS>()?;
         Ok(TestFSModule {
             _fs_registration: fs_registration,
+            _options_fs_registration: options_fs_registration,
         })
     }
 }
@@ -1,6 +1,9 @@
 use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
 
-use kernel_module_testlib::with_kernel_module;
+use kernel_module_testlib::{assert_dmesg_contains, mount, with_kernel_module};
 
 #[test]
 fn test_proc_filesystems() {
@@ -15,3 +18,169 @@ fn test_proc_filesystems() {
     let filesystems = fs::read_to_string("/proc/filesystems").unwrap();
     assert!(!filesystems.contains("testfs"));
 }
+
+#[test]
+fn test_mount() {
+    with_kernel_module(|| {
+        let mount = mount("testfs", "none", "");
+        let metadata = fs::metadata(mount.path()).unwrap();
+        assert!(metadata.is_dir());
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+        assert_eq!(fs::read_dir(mount.path()).unwrap().count(), 0);
+
+        let mounts = fs::read_to_string("/proc/mounts").unwrap();
+        assert!(mounts
+            .lines()
+            .any(|l| l.starts_with(&format!("none {} testfs ", mount.path().display()))));
+    });
+}
+
+#[test]
+fn test_multiple_mounts() {
+    with_kernel_module(|| {
+        let first = mount("testfs", "none", "");
+        let second = mount("testfs", "none", "");
+        assert!(fs::metadata(first.path()).unwrap().is_dir());
+        assert!(fs::metadata(second.path()).unwrap().is_dir());
+    });
+}
+
+// Returns the options shown in /proc/mounts for the filesystem mounted at
+// `path`.
+fn mount_options(path: &Path) -> String {
+    let mounts = fs::read_to_string("/proc/mounts").unwrap();
+    let line = mounts
+        .lines()
+        .find(|l| l.split(' ').nth(1) == Some(path.to_str().unwrap()))
+        .unwrap();
+    line.split(' ').nth(3).unwrap().to_string()
+}
+
+fn try_mount(fs_type: &str, options: &str) -> bool {
+    let path = tempfile::TempDir::new().unwrap();
+    let status = Command::new("sudo")
+        .args(&["mount", "-t", fs_type, "-o", options, "none"])
+        .arg(path.path())
+        .status()
+        .unwrap();
+    if status.success() {
+        let status = Command::new("sudo")
+            .arg("umount")
+            .arg(path.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+    status.success()
+}
+
+#[test]
+fn test_mount_options() {
+    with_kernel_module(|| {
+        let mount = mount("testfs-options", "none", "mode=700,limit=5,verbose");
+        let metadata = fs::metadata(mount.path()).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o700);
+        assert!(mount.path().join("verbose").exists());
+        assert_eq!(
+            fs::read_to_string(mount.path().join("limit")).unwrap(),
+            "5\n"
+        );
+        assert!(mount_options(mount.path()).ends_with(",mode=700,limit=5,verbose"));
+    });
+}
+
+#[test]
+fn test_default_mount_options() {
+    with_kernel_module(|| {
+        let mount = mount("testfs-options", "none", "");
+        let metadata = fs::metadata(mount.path()).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+        assert!(!mount.path().join("verbose").exists());
+        assert_eq!(
+            fs::read_to_string(mount.path().join("limit")).unwrap(),
+            "0\n"
+        );
+        assert!(mount_options(mount.path()).ends_with(",limit=0"));
+    });
+}
+
+#[test]
+fn test_invalid_mount_options() {
+    with_kernel_module(|| {
+        assert!(try_mount("testfs-options", "limit=0x10"));
+        assert!(!try_mount("testfs-options", "bogus"));
+        assert!(!try_mount("testfs-options", "limit"));
+        assert!(!try_mount("testfs-options", "limit=ten"));
+        assert!(!try_mount("testfs-options", "mode=999"));
+        assert!(!try_mount("testfs-options", "verbose=1"));
+        assert!(!try_mount("testfs", "limit=1"));
+    });
+}
+
+#[test]
+fn test_unknown_mount_option_logged() {
+    with_kernel_module(|| {
+        // Options are checked against the registered fs_parameter_spec, which
+        // the VFS uses to report unknown ones on 5.1 and later.
+        assert!(!try_mount("testfs-options", "bogus"));
+        assert_dmesg_contains(&[b"testfs-options: Unknown parameter 'bogus'"]);
+    });
+}
+
+#[test]
+fn test_remount() {
+    with_kernel_module(|| {
+        let mount = mount("testfs-options", "none", "limit=5");
+        let remount = |options: &str| {
+            Command::new("sudo")
+                .args(&["mount", "-o", &format!("remount,{}", options)])
+                .arg(mount.path())
+                .status()
+                .unwrap()
+                .success()
+        };
+        assert!(remount("limit=9"));
+        assert_eq!(
+            fs::read_to_string(mount.path().join("limit")).unwrap(),
+            "9\n"
+        );
+        assert!(!remount("mode=700"));
+        assert!(!remount("bogus"));
+        assert_eq!(
+            fs::read_to_string(mount.path().join("limit")).unwrap(),
+            "9\n"
+        );
+        assert!(mount_options(mount.path()).ends_with(",limit=9"));
+    });
+}
+
+#[test]
+fn test_remount_keeps_options() {
+    with_kernel_module(|| {
+        let mount = mount("testfs-options", "none", "mode=700,limit=5,verbose");
+        let status = Command::new("sudo")
+            .args(&["mount", "-o", "remount,limit=9"])
+            .arg(mount.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(
+            fs::read_to_string(mount.path().join("limit")).unwrap(),
+            "9\n"
+        );
+        assert!(mount_options(mount.path()).ends_with(",mode=700,limit=9,verbose"));
+        assert_eq!(
+            fs::metadata(mount.path()).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+    });
+}
+
+#[test]
+fn test_put_super() {
+    with_kernel_module(|| {
+        let mount = mount("testfs-options", "none", "");
+        drop(mount);
+        assert_dmesg_contains(&[b"testfs-options: unmounted"]);
+    });
+}
@@ -0,0 +1,51 @@
+#![no_std]
+
+use linux_kernel_module::{self, cstr};
+
+struct GreetingFile;
+
+impl linux_kernel_module::file_operations::FileOperations for GreetingFile {
+    const READ: linux_kernel_module::file_operations::ReadFn<Self> = Some(
+        |_this: &Self,
+         _file: &linux_kernel_module::file_operations::File,
+         buf: &mut dyn linux_kernel_module::io_buffer::IoBufferWriter,
+         offset: &mut u64|
+         -> linux_kernel_module::KernelResult<usize> {
+            let data = b"hello from misc\n";
+            let start = core::cmp::min(*offset as usize, data.len());
+            let len = core::cmp::min(buf.len(), data.len() - start);
+            buf.write(&data[start..start + len])?;
+            *offset += len as u64;
+            Ok(len)
+        },
+    );
+}
+
+impl linux_kernel_module::file_operations::FileOpen for GreetingFile {
+    fn open() -> linux_kernel_module::KernelResult<Self> {
+        Ok(GreetingFile)
+    }
+}
+
+struct MiscdevTestModule {
+    _registration: linux_kernel_module::miscdev::Registration<GreetingFile>,
+}
+
+impl linux_kernel_module::KernelModule for MiscdevTestModule {
+    fn init() -> linux_kernel_module::KernelResult<Self> {
+        let registration = linux_kernel_module::miscdev::Registration::register(
+            cstr!("rust-miscdev-tests"),
+            linux_kernel_module::Mode::from_int(0o444),
+        )?;
+        Ok(MiscdevTestModule {
+            _registration: registration,
+        })
+    }
+}
+
+linux_kernel_module::kernel_module!(
+    MiscdevTestModule,
+    author: b"Fish in a Barrel Contributors",
+    description: b"A module for testing misc devices",
+    license: b"GPL"
+);
@@ -0,0 +1,36 @@
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use kernel_module_testlib::with_kernel_module;
+
+const DEVICE_PATH: &'static str = "/dev/rust-miscdev-tests";
+
+#[test]
+fn test_device_node() {
+    with_kernel_module(|| {
+        let metadata = fs::metadata(DEVICE_PATH).unwrap();
+        assert!(metadata.file_type().is_char_device());
+        assert_eq!(metadata.mode() & 0o777, 0o444);
+
+        // Misc devices share major 10, with the minor listed in sysfs.
+        let dev = fs::read_to_string("/sys/class/misc/rust-miscdev-tests/dev").unwrap();
+        let (major, minor) = dev.trim().split_at(dev.find(':').unwrap());
+        assert_eq!(major, "10");
+        assert_eq!(
+            metadata.rdev(),
+            (10 << 8) | minor[1..].parse::<u64>().unwrap()
+        );
+    });
+
+    assert!(fs::metadata(DEVICE_PATH).is_err());
+}
+
+#[test]
+fn test_read() {
+    with_kernel_module(|| {
+        assert_eq!(
+            fs::read_to_string(DEVICE_PATH).unwrap(),
+            "hello from misc\n"
+        );
+    });
+}
@@ -0,0 +1,100 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use linux_kernel_module::file_operations::{File, FileOpener, FileOperations, ReadFn};
+use linux_kernel_module::io_buffer::IoBufferWriter;
+use linux_kernel_module::sysctl::{ArcField, NetId, NetSysctl, NetSysctlRegistration, SysctlDir};
+use linux_kernel_module::{self, chrdev, cstr, Error, KernelResult, Mode};
+
+static NET_ID: NetId = NetId::new();
+
+struct Settings {
+    timeout: AtomicU32,
+    enabled: AtomicBool,
+}
+
+impl NetSysctl for Settings {
+    const PATH: linux_kernel_module::CStr<'static> = cstr!("net/rust-net-sysctl-tests");
+
+    fn net_id() -> &'static NetId {
+        &NET_ID
+    }
+
+    fn new() -> linux_kernel_module::KernelResult<Self> {
+        Ok(Settings {
+            timeout: AtomicU32::new(30),
+            enabled: AtomicBool::new(false),
+        })
+    }
+
+    fn table(this: &Arc<Self>) -> SysctlDir {
+        SysctlDir::new()
+            .entry(
+                cstr!("timeout"),
+                ArcField::new(this.clone(), |s| &s.timeout),
+                Mode::from_int(0o666),
+            )
+            .entry(
+                cstr!("enabled"),
+                ArcField::new(this.clone(), |s| &s.enabled),
+                Mode::from_int(0o666),
+            )
+    }
+}
+
+// Shows the timeout of the namespace of the process that opened it.
+struct TimeoutFile {
+    settings: Arc<Settings>,
+}
+
+impl FileOperations for TimeoutFile {
+    const READ: ReadFn<Self> = Some(
+        |this: &Self,
+         _file: &File,
+         buf: &mut dyn IoBufferWriter,
+         offset: &mut u64|
+         -> KernelResult<usize> {
+            let data = format!("{}\n", this.settings.timeout.load(Ordering::Relaxed));
+            let start = core::cmp::min(*offset as usize, data.len());
+            let len = core::cmp::min(buf.len(), data.len() - start);
+            buf.write(&data.as_bytes()[start..start + len])?;
+            *offset += len as u64;
+            Ok(len)
+        },
+    );
+}
+
+impl FileOpener<Arc<NetSysctlRegistration<Settings>>> for TimeoutFile {
+    fn open_with(registration: &Arc<NetSysctlRegistration<Settings>>) -> KernelResult<Self> {
+        Ok(TimeoutFile {
+            settings: registration.current().ok_or(Error::ENXIO)?,
+        })
+    }
+}
+
+struct NetSysctlTestModule {
+    _chrdev_registration: chrdev::Registration,
+}
+
+impl linux_kernel_module::KernelModule for NetSysctlTestModule {
+    fn init() -> linux_kernel_module::KernelResult<Self> {
+        let registration = Arc::new(NetSysctlRegistration::<Settings>::register()?);
+        Ok(NetSysctlTestModule {
+            _chrdev_registration: chrdev::builder(cstr!("net-sysctl-tests"), 0..1)?
+                .register_device_with::<TimeoutFile, _>(registration)
+                .build()?,
+        })
+    }
+}
+
+linux_kernel_module::kernel_module!(
+    NetSysctlTestModule,
+    author: b"Fish in a Barrel Contributors",
+    description: b"A module for testing per-network-namespace sysctls",
+    license: b"GPL"
+);
@@ -0,0 +1,60 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use kernel_module_testlib::{
+    get_device_major_number, mknod, temporary_file_path, with_kernel_module,
+};
+
+const TIMEOUT_PATH: &'static str = "/proc/sys/net/rust-net-sysctl-tests/timeout";
+const DEVICE_NAME: &'static str = "net-sysctl-tests";
+
+fn read_in_new_namespace(path: &str) -> String {
+    let output = Command::new("sudo")
+        .args(&["unshare", "--net", "cat", path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_read_write() {
+    with_kernel_module(|| {
+        assert_eq!(fs::read_to_string(TIMEOUT_PATH).unwrap(), "30\n");
+        fs::write(TIMEOUT_PATH, "45").unwrap();
+        assert_eq!(fs::read_to_string(TIMEOUT_PATH).unwrap(), "45\n");
+        assert_eq!(
+            fs::read_to_string("/proc/sys/net/rust-net-sysctl-tests/enabled").unwrap(),
+            "0\n"
+        );
+    });
+}
+
+#[test]
+fn test_per_namespace() {
+    with_kernel_module(|| {
+        fs::write(TIMEOUT_PATH, "60").unwrap();
+        assert_eq!(read_in_new_namespace(TIMEOUT_PATH), "30\n");
+        assert_eq!(fs::read_to_string(TIMEOUT_PATH).unwrap(), "60\n");
+    });
+}
+
+#[test]
+fn test_current_namespace() {
+    with_kernel_module(|| {
+        let p = temporary_file_path();
+        let _u = mknod(&p, get_device_major_number(DEVICE_NAME), 0);
+        fs::write(TIMEOUT_PATH, "45").unwrap();
+        assert_eq!(fs::read_to_string(&p).unwrap(), "45\n");
+        assert_eq!(read_in_new_namespace(p.to_str().unwrap()), "30\n");
+    });
+}
+
+#[test]
+fn test_unregistered_on_unload() {
+    with_kernel_module(|| {
+        assert!(Path::new(TIMEOUT_PATH).exists());
+    });
+    assert!(!Path::new(TIMEOUT_PATH).exists());
+}
@@ -13,15 +13,10 @@ impl SysctlStorage for EntropySource {
         (data.len(), Ok(()))
     }
 
-    fn read_value(
-        &self,
-        data: &mut linux_kernel_module::user_ptr::UserSlicePtrWriter,
-    ) -> (usize, linux_kernel_module::KernelResult<()>) {
-        let mut storage = alloc::vec![0; data.len()];
-        if let Err(e) = random::getrandom(&mut storage) {
-            return (0, Err(e));
-        }
-        (storage.len(), data.write(&storage))
+    fn read_value(&self) -> linux_kernel_module::KernelResult<alloc::vec::Vec<u8>> {
+        let mut storage = alloc::vec![0; 32];
+        random::getrandom(&mut storage)?;
+        Ok(storage)
     }
 }
 
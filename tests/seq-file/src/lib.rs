@@ -0,0 +1,95 @@
+#![no_std]
+
+use core::fmt::Write;
+
+use linux_kernel_module::file_operations::FileOpen;
+use linux_kernel_module::seq_file::{
+    self, DebugfsEntry, ProcEntry, SeqFile, SeqWriter, SingleSeqFile,
+};
+use linux_kernel_module::{self, cstr, Mode};
+
+const LINE_COUNT: u64 = 1000;
+
+struct LinesFile;
+
+impl SeqFile for LinesFile {
+    type Item = u64;
+
+    fn start(&self, pos: u64) -> Option<u64> {
+        if pos < LINE_COUNT {
+            Some(pos)
+        } else {
+            None
+        }
+    }
+
+    fn show(&self, item: &u64, m: &mut SeqWriter) -> linux_kernel_module::KernelResult<()> {
+        writeln!(m, "line {}", item)?;
+        Ok(())
+    }
+}
+
+impl FileOpen for LinesFile {
+    fn open() -> linux_kernel_module::KernelResult<Self> {
+        Ok(LinesFile)
+    }
+}
+
+struct GreetingFile;
+
+impl SingleSeqFile for GreetingFile {
+    fn show(&self, m: &mut SeqWriter) -> linux_kernel_module::KernelResult<()> {
+        writeln!(m, "hello from a {}", "seq_file")?;
+        Ok(())
+    }
+}
+
+impl FileOpen for GreetingFile {
+    fn open() -> linux_kernel_module::KernelResult<Self> {
+        Ok(GreetingFile)
+    }
+}
+
+struct SeqFileTestModule {
+    _chrdev_registration: linux_kernel_module::chrdev::Registration,
+    _proc_lines: ProcEntry,
+    _proc_greeting: ProcEntry,
+    _debugfs_lines: DebugfsEntry,
+    _debugfs_greeting: DebugfsEntry,
+}
+
+impl linux_kernel_module::KernelModule for SeqFileTestModule {
+    fn init() -> linux_kernel_module::KernelResult<Self> {
+        let chrdev_registration =
+            linux_kernel_module::chrdev::builder(cstr!("seq-file-tests"), 0..2)?
+                .register_seq_device::<LinesFile>()
+                .register_single_seq_device::<GreetingFile>()
+                .build()?;
+        Ok(SeqFileTestModule {
+            _chrdev_registration: chrdev_registration,
+            _proc_lines: seq_file::proc_create_seq::<LinesFile>(
+                cstr!("rust-seq-file-lines"),
+                Mode::from_int(0o444),
+            )?,
+            _proc_greeting: seq_file::proc_create_single::<GreetingFile>(
+                cstr!("rust-seq-file-greeting"),
+                Mode::from_int(0o444),
+            )?,
+            _debugfs_lines: seq_file::debugfs_create_seq::<LinesFile>(
+                cstr!("rust-seq-file-lines"),
+                Mode::from_int(0o444),
+            )?,
+            _debugfs_greeting: seq_file::debugfs_create_single::<GreetingFile>(
+                cstr!("rust-seq-file-greeting"),
+                Mode::from_int(0o444),
+            )?,
+        })
+    }
+}
+
+linux_kernel_module::kernel_module!(
+    SeqFileTestModule,
+    author: b"Fish in a Barrel Contributors",
+    description: b"A module for testing seq_file",
+    license: b"GPL"
+);
@@ -0,0 +1,124 @@
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use kernel_module_testlib::*;
+
+const DEVICE_NAME: &'static str = "seq-file-tests";
+const LINES_FILE_MINOR: libc::dev_t = 0;
+const GREETING_FILE_MINOR: libc::dev_t = 1;
+const PROC_LINES_PATH: &'static str = "/proc/rust-seq-file-lines";
+const PROC_GREETING_PATH: &'static str = "/proc/rust-seq-file-greeting";
+const DEBUGFS_LINES_PATH: &'static str = "/sys/kernel/debug/rust-seq-file-lines";
+const DEBUGFS_GREETING_PATH: &'static str = "/sys/kernel/debug/rust-seq-file-greeting";
+
+fn expected_lines() -> String {
+    (0..1000).map(|i| format!("line {}\n", i)).collect()
+}
+
+#[test]
+fn test_read_lines() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, LINES_FILE_MINOR);
+
+        assert_eq!(fs::read_to_string(&p).unwrap(), expected_lines());
+    });
+}
+
+#[test]
+fn test_read_lines_byte_at_a_time() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, LINES_FILE_MINOR);
+
+        let mut f = fs::File::open(&p).unwrap();
+        let mut data = vec![];
+        let mut buf = [0; 1];
+        while f.read(&mut buf).unwrap() == 1 {
+            data.push(buf[0]);
+        }
+        assert_eq!(String::from_utf8(data).unwrap(), expected_lines());
+    });
+}
+
+#[test]
+fn test_seek_lines() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, LINES_FILE_MINOR);
+
+        let expected = expected_lines();
+        let mut f = fs::File::open(&p).unwrap();
+        assert_eq!(f.seek(SeekFrom::Start(5000)).unwrap(), 5000);
+        let mut data = String::new();
+        f.read_to_string(&mut data).unwrap();
+        assert_eq!(data, &expected[5000..]);
+    });
+}
+
+#[test]
+fn test_read_single() {
+    with_kernel_module(|| {
+        let device_number = get_device_major_number(DEVICE_NAME);
+        let p = temporary_file_path();
+        let _u = mknod(&p, device_number, GREETING_FILE_MINOR);
+
+        assert_eq!(fs::read_to_string(&p).unwrap(), "hello from a seq_file\n");
+    });
+}
+
+#[test]
+fn test_proc_files() {
+    assert!(!Path::new(PROC_LINES_PATH).exists());
+
+    with_kernel_module(|| {
+        assert_eq!(
+            fs::read_to_string(PROC_LINES_PATH).unwrap(),
+            expected_lines()
+        );
+        assert_eq!(
+            fs::read_to_string(PROC_GREETING_PATH).unwrap(),
+            "hello from a seq_file\n"
+        );
+
+        let expected = expected_lines();
+        let mut f = fs::File::open(PROC_LINES_PATH).unwrap();
+        assert_eq!(f.seek(SeekFrom::Start(5000)).unwrap(), 5000);
+        let mut data = String::new();
+        f.read_to_string(&mut data).unwrap();
+        assert_eq!(data, &expected[5000..]);
+    });
+
+    assert!(!Path::new(PROC_LINES_PATH).exists());
+    assert!(!Path::new(PROC_GREETING_PATH).exists());
+}
+
+#[test]
+fn test_debugfs_files() {
+    assert!(!Path::new(DEBUGFS_LINES_PATH).exists());
+
+    with_kernel_module(|| {
+        assert_eq!(
+            fs::read_to_string(DEBUGFS_LINES_PATH).unwrap(),
+            expected_lines()
+        );
+        assert_eq!(
+            fs::read_to_string(DEBUGFS_GREETING_PATH).unwrap(),
+            "hello from a seq_file\n"
+        );
+
+        let expected = expected_lines();
+        let mut f = fs::File::open(DEBUGFS_LINES_PATH).unwrap();
+        assert_eq!(f.seek(SeekFrom::Start(5000)).unwrap(), 5000);
+        let mut data = String::new();
+        f.read_to_string(&mut data).unwrap();
+        assert_eq!(data, &expected[5000..]);
+    });
+
+    assert!(!Path::new(DEBUGFS_LINES_PATH).exists());
+    assert!(!Path::new(DEBUGFS_GREETING_PATH).exists());
+}
@@ -1,15 +1,50 @@
 #![no_std]
 
-use core::sync::atomic::AtomicBool;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, Ordering};
+
+extern crate alloc;
+
+use alloc::vec;
 
 use linux_kernel_module::{self, cstr};
 
-use linux_kernel_module::sysctl::Sysctl;
+use linux_kernel_module::sysctl::{
+    AtomicArray, BoundedString, EnumValue, LockedArray, Ranged, Sysctl, SysctlDir, SysctlEnum,
+    SysctlTable, Watched,
+};
 use linux_kernel_module::Mode;
 
+#[derive(Clone, Copy, PartialEq)]
+enum Color {
+    Red,
+    Green,
+    Blue,
+}
+
+impl SysctlEnum for Color {
+    const VARIANTS: &'static [(&'static str, Color)] = &[
+        ("red", Color::Red),
+        ("green", Color::Green),
+        ("blue", Color::Blue),
+    ];
+}
+
+// The value that `even` had before its last change.
+static PREVIOUS_EVEN: AtomicU32 = AtomicU32::new(0);
+
 struct SysctlTestModule {
     _sysctl_a: Sysctl<AtomicBool>,
     _sysctl_b: Sysctl<AtomicBool>,
+    _sysctl_int: Sysctl<AtomicI32>,
+    _sysctl_u64: Sysctl<AtomicU64>,
+    _sysctl_ranged: Sysctl<Ranged<AtomicU32>>,
+    _sysctl_string: Sysctl<BoundedString>,
+    _sysctl_color: Sysctl<EnumValue<Color>>,
+    _sysctl_even: Sysctl<Watched<AtomicU32>>,
+    _sysctl_previous_even: Sysctl<&'static AtomicU32>,
+    _table: SysctlTable,
+    _sysctl_limits: Sysctl<AtomicArray<AtomicU32>>,
+    _sysctl_list: Sysctl<LockedArray<i32>>,
 }
 
 impl linux_kernel_module::KernelModule for SysctlTestModule {
@@ -27,6 +62,96 @@ impl linux_kernel_module::KernelModule for SysctlTestModule {
                 AtomicBool::new(false),
                 Mode::from_int(0o666),
             )?,
+            _sysctl_int: Sysctl::register(
+                cstr!("rust/sysctl-tests"),
+                cstr!("int"),
+                AtomicI32::new(7),
+                Mode::from_int(0o666),
+            )?,
+            _sysctl_u64: Sysctl::register(
+                cstr!("rust/sysctl-tests"),
+                cstr!("u64"),
+                AtomicU64::new(0),
+                Mode::from_int(0o666),
+            )?,
+            _sysctl_ranged: Sysctl::register(
+                cstr!("rust/sysctl-tests"),
+                cstr!("ranged"),
+                Ranged::new(AtomicU32::new(50), 10, 100),
+                Mode::from_int(0o666),
+            )?,
+            _sysctl_string: Sysctl::register(
+                cstr!("rust/sysctl-tests"),
+                cstr!("string"),
+                BoundedString::new("hello", 16)?,
+                Mode::from_int(0o666),
+            )?,
+            _sysctl_color: Sysctl::register(
+                cstr!("rust/sysctl-tests"),
+                cstr!("color"),
+                EnumValue::new(Color::Green)?,
+                Mode::from_int(0o666),
+            )?,
+            _sysctl_even: Sysctl::register(
+                cstr!("rust/sysctl-tests"),
+                cstr!("even"),
+                Watched::new(AtomicU32::new(4))
+                    .validate(|_old, new| {
+                        if new % 2 == 0 {
+                            Ok(())
+                        } else {
+                            Err(linux_kernel_module::Error::EINVAL)
+                        }
+                    })
+                    .on_change(|old, _new| PREVIOUS_EVEN.store(*old, Ordering::Relaxed)),
+                Mode::from_int(0o666),
+            )?,
+            _sysctl_previous_even: Sysctl::register(
+                cstr!("rust/sysctl-tests"),
+                cstr!("previous_even"),
+                &PREVIOUS_EVEN,
+                Mode::from_int(0o444),
+            )?,
+            _sysctl_limits: Sysctl::register(
+                cstr!("rust/sysctl-tests"),
+                cstr!("limits"),
+                AtomicArray::new(vec![
+                    AtomicU32::new(1),
+                    AtomicU32::new(2),
+                    AtomicU32::new(3),
+                ])
+                .with_range(0, 1000),
+                Mode::from_int(0o666),
+            )?,
+            _sysctl_list: Sysctl::register(
+                cstr!("rust/sysctl-tests"),
+                cstr!("list"),
+                LockedArray::new(vec![-1, 1], 4)?.with_range(-10, 10),
+                Mode::from_int(0o666),
+            )?,
+            _table: SysctlTable::register(
+                cstr!("rust/sysctl-table-tests"),
+                SysctlDir::new()
+                    .entry(cstr!("flag"), AtomicBool::new(true), Mode::from_int(0o666))
+                    .entry(cstr!("count"), AtomicU32::new(3), Mode::from_int(0o666))
+                    .subdir(
+                        cstr!("nested"),
+                        SysctlDir::new()
+                            .entry(
+                                cstr!("name"),
+                                BoundedString::new("inner", 16)?,
+                                Mode::from_int(0o666),
+                            )
+                            .subdir(
+                                cstr!("deeper"),
+                                SysctlDir::new().entry(
+                                    cstr!("color"),
+                                    EnumValue::new(Color::Red)?,
+                                    Mode::from_int(0o644),
+                                ),
+                            ),
+                    ),
+            )?,
         })
     }
 }
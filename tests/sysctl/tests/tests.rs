@@ -1,4 +1,6 @@
 use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::FileExt;
 use std::path::Path;
 
 use kernel_module_testlib::with_kernel_module;
@@ -42,3 +44,210 @@ fn test_file_doesnt_exit_after_module_unloaded() {
     });
     assert!(!Path::new("/proc/sys/rust/sysctl-tests/a").exists());
 }
+
+fn read(name: &str) -> String {
+    fs::read_to_string(format!("/proc/sys/rust/sysctl-tests/{}", name)).unwrap()
+}
+
+fn write(name: &str, value: &str) -> std::io::Result<()> {
+    fs::write(format!("/proc/sys/rust/sysctl-tests/{}", name), value)
+}
+
+#[test]
+fn test_int() {
+    with_kernel_module(|| {
+        assert_eq!(read("int"), "7\n");
+        write("int", "-42\n").unwrap();
+        assert_eq!(read("int"), "-42\n");
+        write("int", "0x1f").unwrap();
+        assert_eq!(read("int"), "31\n");
+        write("int", "-0x10").unwrap();
+        assert_eq!(read("int"), "-16\n");
+    });
+}
+
+#[test]
+fn test_int_invalid() {
+    with_kernel_module(|| {
+        assert!(write("int", "abc").is_err());
+        assert!(write("int", "--1").is_err());
+        assert!(write("int", "2147483648").is_err());
+        assert!(write("u64", "-1").is_err());
+        assert_eq!(read("int"), "7\n");
+        assert_eq!(read("u64"), "0\n");
+    });
+}
+
+#[test]
+fn test_u64() {
+    with_kernel_module(|| {
+        write("u64", "18446744073709551615").unwrap();
+        assert_eq!(read("u64"), "18446744073709551615\n");
+    });
+}
+
+#[test]
+fn test_ranged() {
+    with_kernel_module(|| {
+        assert_eq!(read("ranged"), "50\n");
+        write("ranged", "100").unwrap();
+        assert_eq!(read("ranged"), "100\n");
+        write("ranged", "10").unwrap();
+        assert_eq!(read("ranged"), "10\n");
+        assert!(write("ranged", "101").is_err());
+        assert!(write("ranged", "9").is_err());
+        assert_eq!(read("ranged"), "10\n");
+    });
+}
+
+#[test]
+fn test_string() {
+    with_kernel_module(|| {
+        assert_eq!(read("string"), "hello\n");
+        write("string", "goodbye world\n").unwrap();
+        assert_eq!(read("string"), "goodbye world\n");
+        assert!(write("string", "this is far too long").is_err());
+        assert_eq!(read("string"), "goodbye world\n");
+    });
+}
+
+#[test]
+fn test_enum() {
+    with_kernel_module(|| {
+        assert_eq!(read("color"), "green\n");
+        write("color", "blue\n").unwrap();
+        assert_eq!(read("color"), "blue\n");
+        assert!(write("color", "purple").is_err());
+        assert_eq!(read("color"), "blue\n");
+    });
+}
+
+#[test]
+fn test_watched() {
+    with_kernel_module(|| {
+        assert_eq!(read("even"), "4\n");
+        assert_eq!(read("previous_even"), "0\n");
+        write("even", "10").unwrap();
+        assert_eq!(read("even"), "10\n");
+        assert_eq!(read("previous_even"), "4\n");
+
+        // Rejected by the validation hook, so the change hook doesn't run.
+        assert!(write("even", "11").is_err());
+        assert_eq!(read("even"), "10\n");
+        assert_eq!(read("previous_even"), "4\n");
+        // Rejected by parsing before either hook runs.
+        assert!(write("even", "ten").is_err());
+        assert_eq!(read("even"), "10\n");
+    });
+}
+
+#[test]
+fn test_table() {
+    with_kernel_module(|| {
+        let base = "/proc/sys/rust/sysctl-table-tests";
+        assert_eq!(fs::read_to_string(format!("{}/flag", base)).unwrap(), "1\n");
+        assert_eq!(
+            fs::read_to_string(format!("{}/count", base)).unwrap(),
+            "3\n"
+        );
+        fs::write(format!("{}/count", base), "12").unwrap();
+        assert_eq!(
+            fs::read_to_string(format!("{}/count", base)).unwrap(),
+            "12\n"
+        );
+        assert_eq!(
+            fs::read_to_string(format!("{}/nested/name", base)).unwrap(),
+            "inner\n"
+        );
+        assert_eq!(
+            fs::read_to_string(format!("{}/nested/deeper/color", base)).unwrap(),
+            "red\n"
+        );
+    });
+    assert!(!Path::new("/proc/sys/rust/sysctl-table-tests").exists());
+}
+
+#[test]
+fn test_read_one_byte_at_a_time() {
+    with_kernel_module(|| {
+        write("string", "0123456789abcdef").unwrap();
+        let mut f = fs::File::open("/proc/sys/rust/sysctl-tests/string").unwrap();
+        let mut result = Vec::new();
+        let mut byte = [0; 1];
+        while f.read(&mut byte).unwrap() == 1 {
+            result.push(byte[0]);
+        }
+        assert_eq!(result, b"0123456789abcdef\n");
+    });
+}
+
+#[test]
+fn test_read_at_offset() {
+    with_kernel_module(|| {
+        let f = fs::File::open("/proc/sys/rust/sysctl-tests/color").unwrap();
+        let mut data = [0; 3];
+        assert_eq!(f.read_at(&mut data, 2).unwrap(), 3);
+        assert_eq!(&data, b"een");
+        assert_eq!(f.read_at(&mut data, 100).unwrap(), 0);
+    });
+}
+
+#[test]
+fn test_string_continued_write() {
+    with_kernel_module(|| {
+        let mut f = fs::OpenOptions::new()
+            .write(true)
+            .open("/proc/sys/rust/sysctl-tests/string")
+            .unwrap();
+        assert_eq!(f.write(b"abc").unwrap(), 3);
+        assert_eq!(f.write(b"def").unwrap(), 3);
+        drop(f);
+        assert_eq!(read("string"), "abcdef\n");
+    });
+}
+
+#[test]
+fn test_int_continued_write_ignored() {
+    with_kernel_module(|| {
+        let mut f = fs::OpenOptions::new()
+            .write(true)
+            .open("/proc/sys/rust/sysctl-tests/int")
+            .unwrap();
+        assert_eq!(f.write(b"12").unwrap(), 2);
+        assert_eq!(f.write(b"3").unwrap(), 0);
+        drop(f);
+        assert_eq!(read("int"), "12\n");
+    });
+}
+
+#[test]
+fn test_atomic_array() {
+    with_kernel_module(|| {
+        assert_eq!(read("limits"), "1\t2\t3\n");
+        write("limits", "10 20\t30\n").unwrap();
+        assert_eq!(read("limits"), "10\t20\t30\n");
+        // Fewer values only replace the first ones.
+        write("limits", "7").unwrap();
+        assert_eq!(read("limits"), "7\t20\t30\n");
+        assert!(write("limits", "1 2 3 4").is_err());
+        assert!(write("limits", "1 2000 3").is_err());
+        assert!(write("limits", "1 x 3").is_err());
+        assert_eq!(read("limits"), "7\t20\t30\n");
+    });
+}
+
+#[test]
+fn test_locked_array() {
+    with_kernel_module(|| {
+        assert_eq!(read("list"), "-1\t1\n");
+        write("list", "5 -5 0x3 4").unwrap();
+        assert_eq!(read("list"), "5\t-5\t3\t4\n");
+        write("list", "9").unwrap();
+        assert_eq!(read("list"), "9\n");
+        write("list", "\n").unwrap();
+        assert_eq!(read("list"), "\n");
+        assert!(write("list", "1 2 3 4 5").is_err());
+        assert!(write("list", "11").is_err());
+        assert_eq!(read("list"), "\n");
+    });
+}